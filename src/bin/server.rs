@@ -0,0 +1,16 @@
+use bevy::prelude::*;
+
+use test_bevy_game::plugins::cli::{LaunchOptions, LaunchRole};
+use test_bevy_game::plugins::replay::ReplayRecordPlugin;
+
+// 无窗口无渲染的专用服务器, 可以运行在没有 GPU 的机器上. 参数和主程序相同, 总是以 --role server --headless 运行
+fn main() {
+    let options = LaunchOptions {
+        role: LaunchRole::Server,
+        headless: true,
+        ..LaunchOptions::from_command_line()
+    };
+    let mut app = App::build();
+    options.build_app(&mut app);
+    app.add_plugin(ReplayRecordPlugin).run();
+}
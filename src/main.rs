@@ -1,29 +1,37 @@
-use bevy::log::LogSettings;
 use bevy::prelude::*;
-use bevy::{asset::AssetServerSettings, log::Level};
-mod plugins;
 
-use plugins::TestNetGamePlugins;
-// 游戏窗口 Title 名字
-const GAME_WINDOW_TITLE: &str = "openra-rs bevy example";
+use test_bevy_game::plugins::cli::{LaunchOptions, LaunchRole};
+use test_bevy_game::plugins::input_script::{
+    InputScriptPlayback, InputScriptPlaybackPlugin, InputScriptRecordPlugin, InputScriptRecorder,
+};
+use test_bevy_game::plugins::replay::{ReplayPlayback, ReplayPlaybackPlugin, ReplayRecordPlugin};
 
 fn main() {
+    let options = LaunchOptions::from_command_line();
     let mut app = App::build();
+    options.build_app(&mut app);
 
-    app.insert_resource(WindowDescriptor {
-        title: GAME_WINDOW_TITLE.to_string(),
-        ..Default::default()
-    })
-    .insert_resource(AssetServerSettings {
-        // 这里重置 assets 目录防止 ide debug 的时候程序是在生成 target 文件夹里跑导致 assets 文件夹找不到
-        asset_folder: format!("{}/assets", env!("CARGO_MANIFEST_DIR")),
-    })
-    .insert_resource(LogSettings {
-        level: Level::DEBUG,
-        ..Default::default()
-    })
-    .add_plugins(DefaultPlugins)
-    .add_plugins(TestNetGamePlugins);
+    // --replay <file> 回放录像, 否则录制本局比赛. 客户端不运行玩法逻辑, 不录制
+    match &options.replay {
+        Some(replay) => {
+            app.insert_resource(ReplayPlayback::new(replay.clone()))
+                .add_plugin(ReplayPlaybackPlugin);
+        }
+        None if options.role != LaunchRole::Client => {
+            app.add_plugin(ReplayRecordPlugin);
+        }
+        None => {}
+    }
+
+    // --input-script <file> 用脚本代替键盘输入, --record-input <file> 录制本地输入
+    if let Some(script) = &options.input_script {
+        app.insert_resource(InputScriptPlayback::new(script.clone()))
+            .add_plugin(InputScriptPlaybackPlugin);
+    }
+    if let Some(path) = &options.record_input {
+        app.insert_resource(InputScriptRecorder::new(path.clone()))
+            .add_plugin(InputScriptRecordPlugin);
+    }
 
     app.run();
 }
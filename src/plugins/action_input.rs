@@ -0,0 +1,223 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use super::input_ext::{
+    seat_input_mut, HeldOperates, InputExtSystem, InputSource, PlayerInputSettings, PlayerOperate,
+    SeatInputs,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, SystemLabel)]
+pub struct ActionInputSystem;
+
+// 在按键映射之上识别组合键、长按和双击, 触发时按下对应的操作
+pub struct ActionInputPlugin;
+
+impl Plugin for ActionInputPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ActionInputSettings>()
+            .init_resource::<ActionDetector>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                action_bindings_update_system
+                    .system()
+                    .after(InputExtSystem)
+                    .before(ActionInputSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                action_input_system
+                    .system()
+                    .label(ActionInputSystem)
+                    .after(InputExtSystem),
+            );
+    }
+}
+
+// 双击两次按下之间的最长间隔(秒)
+pub const DOUBLE_TAP_WINDOW: f32 = 0.3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionTrigger {
+    Chord(Vec<KeyCode>),                     // 同时按住所有按键, 例如 Shift+W
+    Hold { key: KeyCode, seconds: f32 },     // 按住超过 seconds 秒
+    DoubleTap { key: KeyCode, window: f32 }, // window 秒内按下两次, 松开前一直触发
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionBinding {
+    pub seat: usize,
+    pub trigger: ActionTrigger,
+    pub operate: PlayerOperate,
+}
+
+pub struct ActionInputSettings {
+    pub bindings: Vec<ActionBinding>,
+    // 为 true 时 bindings 由各座位当前的移动按键生成, 改键后重新生成
+    pub follow_key_bindings: bool,
+}
+
+// 各座位疾跑组合键使用的 Shift, 超出的座位只有双击冲刺
+pub const SPRINT_MODIFIERS: [KeyCode; 2] = [KeyCode::LShift, KeyCode::RShift];
+
+const MOVE_OPERATES: [PlayerOperate; 4] = [
+    PlayerOperate::MoveFrond,
+    PlayerOperate::MoveBack,
+    PlayerOperate::MoveRight,
+    PlayerOperate::MoveLeft,
+];
+
+impl Default for ActionInputSettings {
+    fn default() -> Self {
+        Self::from_input_settings(&PlayerInputSettings::default())
+    }
+}
+
+impl ActionInputSettings {
+    // 每个移动按键: Shift+按键 疾跑, 双击按键冲刺. 座位 0 用左 Shift, 座位 1 用右 Shift
+    pub fn from_input_settings(input_settings: &PlayerInputSettings) -> Self {
+        let bindings = (0..input_settings.seat_num())
+            .flat_map(|seat| {
+                input_settings
+                    .bindings(seat)
+                    .into_iter()
+                    .filter(|(_, op)| MOVE_OPERATES.contains(op))
+                    .flat_map(move |(key, _)| {
+                        let sprint = SPRINT_MODIFIERS.get(seat).map(|shift| ActionBinding {
+                            seat,
+                            trigger: ActionTrigger::Chord(vec![*shift, key]),
+                            operate: PlayerOperate::Sprint,
+                        });
+                        let dash = ActionBinding {
+                            seat,
+                            trigger: ActionTrigger::DoubleTap {
+                                key,
+                                window: DOUBLE_TAP_WINDOW,
+                            },
+                            operate: PlayerOperate::Dash,
+                        };
+                        sprint.into_iter().chain(std::iter::once(dash))
+                    })
+            })
+            .collect();
+        ActionInputSettings {
+            bindings,
+            follow_key_bindings: true,
+        }
+    }
+}
+
+// 单个触发条件的识别状态
+#[derive(Default, Clone)]
+struct TriggerState {
+    active: bool,
+    pressed_at: Option<f64>, // 长按: 本次按下的时间
+    last_tap: Option<f64>,   // 双击: 上一次按下的时间
+}
+
+impl TriggerState {
+    fn update(&mut self, trigger: &ActionTrigger, keys: &Input<KeyCode>, now: f64) {
+        match trigger {
+            ActionTrigger::Chord(chord) => {
+                self.active = !chord.is_empty() && chord.iter().all(|key| keys.pressed(*key));
+            }
+            ActionTrigger::Hold { key, seconds } => {
+                if keys.just_pressed(*key) {
+                    self.pressed_at = Some(now);
+                }
+                if !keys.pressed(*key) {
+                    self.pressed_at = None;
+                }
+                self.active = matches!(self.pressed_at, Some(at) if now - at >= *seconds as f64);
+            }
+            ActionTrigger::DoubleTap { key, window } => {
+                if keys.just_pressed(*key) {
+                    match self.last_tap {
+                        Some(at) if now - at <= *window as f64 => {
+                            // 触发后重新计数, 连按三次不会再次触发
+                            self.active = true;
+                            self.last_tap = None;
+                        }
+                        _ => self.last_tap = Some(now),
+                    }
+                }
+                if !keys.pressed(*key) {
+                    self.active = false;
+                }
+            }
+        }
+    }
+}
+
+// 记录各触发条件的状态和每个座位当前触发的操作
+#[derive(Default)]
+pub struct ActionDetector {
+    elapsed: f64,
+    triggers: Vec<TriggerState>,
+}
+
+impl ActionDetector {
+    // 经过 delta 秒后按本帧的按键状态更新
+    pub fn update(&mut self, settings: &ActionInputSettings, keys: &Input<KeyCode>, delta: f32) {
+        self.elapsed += delta as f64;
+        self.triggers
+            .resize_with(settings.bindings.len(), Default::default);
+        let now = self.elapsed;
+        settings
+            .bindings
+            .iter()
+            .zip(self.triggers.iter_mut())
+            .for_each(|(binding, state)| state.update(&binding.trigger, keys, now));
+    }
+
+    // 座位上当前触发的操作
+    pub fn active(&self, settings: &ActionInputSettings, seat: usize) -> HashSet<PlayerOperate> {
+        settings
+            .bindings
+            .iter()
+            .zip(self.triggers.iter())
+            .filter(|(binding, state)| binding.seat == seat && state.active)
+            .map(|(binding, _)| binding.operate)
+            .collect()
+    }
+}
+
+// 改键或读取按键配置后重新生成触发条件, 识别状态从头开始
+fn action_bindings_update_system(
+    input_settings: Res<PlayerInputSettings>,
+    mut settings: ResMut<ActionInputSettings>,
+    mut detector: ResMut<ActionDetector>,
+) {
+    if !settings.follow_key_bindings || !input_settings.is_changed() {
+        return;
+    }
+    let bindings = ActionInputSettings::from_input_settings(&input_settings).bindings;
+    if settings.bindings != bindings {
+        settings.bindings = bindings;
+        detector.triggers.clear();
+    }
+}
+
+// 触发的操作和按键映射、手柄一起合并, 不影响其它来源按住的操作
+fn action_input_system(
+    time: Res<Time>,
+    settings: Res<ActionInputSettings>,
+    keys: Res<Input<KeyCode>>,
+    mut detector: ResMut<ActionDetector>,
+    mut player_op: ResMut<Input<PlayerOperate>>,
+    mut seat_inputs: ResMut<SeatInputs>,
+    mut held_operates: ResMut<HeldOperates>,
+) {
+    detector.update(&settings, &keys, time.delta_seconds());
+    // 包括已经没有触发条件的座位, 让它们松开之前按下的操作
+    let seats: HashSet<usize> = settings
+        .bindings
+        .iter()
+        .map(|binding| binding.seat)
+        .chain(held_operates.seats_of(InputSource::Action))
+        .collect();
+    for seat in seats {
+        let operates = detector.active(&settings, seat);
+        if let Some(input) = seat_input_mut(seat, &mut player_op, &mut seat_inputs) {
+            held_operates.update(seat, InputSource::Action, operates, input);
+        }
+    }
+}
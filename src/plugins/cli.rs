@@ -0,0 +1,448 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerSettings;
+use bevy::asset::AssetServerSettings;
+use bevy::input::InputPlugin;
+use bevy::log::{Level, LogPlugin, LogSettings};
+use bevy::prelude::*;
+
+use super::game::{
+    GameCorePlugin, GameRules, GameSeed, HeadlessGamePlugins, TestNetGamePlugins, SIMULATION_TICK,
+};
+use super::input_ext::{InputExtPlugin, PlayerInputSettings};
+use super::input_script::{InputScript, INPUT_SCRIPT_ARG, RECORD_INPUT_ARG};
+use super::net::conditions::{NET_SIM_ARG, NET_SIM_ENV};
+use super::net::{
+    ClientRole, DedicatedServerPlugins, NetClientPlugin, NetClientPlugins, NetClientSettings,
+    NetConditions, NetServerPlugin, NetServerSettings, NetSpectatorPlugins,
+};
+use super::player::{LocalPlayerName, SpawnLocalPlayer};
+use super::presentation::PresentationPlugin;
+use super::replay::{Replay, REPLAY_ARG};
+use super::rules::{RulesReloadSettings, RULES_ARG};
+use super::ui::UiPlugin;
+
+// 游戏窗口 Title 名字
+pub const GAME_WINDOW_TITLE: &str = "openra-rs bevy example";
+
+pub const ROLE_ARG: &str = "--role";
+pub const LOG_LEVEL_ARG: &str = "--log-level";
+pub const ADDR_ARG: &str = "--addr";
+pub const PORT_ARG: &str = "--port";
+pub const NAME_ARG: &str = "--name";
+pub const SEED_ARG: &str = "--seed";
+pub const HEADLESS_ARG: &str = "--headless";
+pub const SPLIT_KEYBOARD_ARG: &str = "--split-keyboard";
+pub const HELP_ARG: &str = "--help";
+
+pub const USAGE: &str = "\
+usage: test_bevy_game [options]
+
+  --role <offline|host|server|client|spectator>
+                                       运行方式, 默认 offline
+  --addr <ip>                          server/host 监听的地址, client/spectator 连接的服务器地址
+  --port <port>                        端口, 默认 7878
+  --name <name>                        玩家名称
+  --seed <u64>                         玩法随机数种子, 不能用于 client/spectator(使用服务器的种子)
+  --headless                           不创建窗口, 只运行逻辑
+  --split-keyboard                     offline 时两个玩家共用键盘: WASD 和方向键
+  --log-level <trace|debug|info|warn|error>
+  --rules <file>                       比赛规则文件, .ron 或 .toml
+  --net-sim <spec>                     模拟网络状况, 如 latency=100,loss=0.05
+  --replay <file>                      回放录像
+  --input-script <file>                用输入脚本代替键盘
+  --record-input <file>                录制本地输入";
+
+// 同一个程序的几种运行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaunchRole {
+    Offline,   // 单机
+    Host,      // 本机有玩家的服务器
+    Server,    // 专用服务器, 没有本地玩家
+    Client,    // 连接服务器的客户端
+    Spectator, // 只观看比赛的客户端, 不占玩家位置
+}
+
+impl LaunchRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LaunchRole::Offline => "offline",
+            LaunchRole::Host => "host",
+            LaunchRole::Server => "server",
+            LaunchRole::Client => "client",
+            LaunchRole::Spectator => "spectator",
+        }
+    }
+}
+
+impl fmt::Display for LaunchRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LaunchRole {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "offline" => Ok(LaunchRole::Offline),
+            "host" => Ok(LaunchRole::Host),
+            "server" => Ok(LaunchRole::Server),
+            "client" => Ok(LaunchRole::Client),
+            "spectator" => Ok(LaunchRole::Spectator),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    MissingValue(String),
+    InvalidValue {
+        arg: String,
+        value: String,
+    },
+    InvalidFile {
+        arg: String,
+        path: String,
+        reason: String,
+    }, // 文件不存在或内容有误
+    UnknownArg(String),
+    RoleConflict {
+        arg: String,
+        role: LaunchRole,
+    }, // 该参数不能用于所选运行方式
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::MissingValue(arg) => write!(f, "{} needs a value", arg),
+            CliError::InvalidValue { arg, value } => {
+                write!(f, "invalid value `{}` for {}", value, arg)
+            }
+            CliError::InvalidFile { arg, path, reason } => {
+                write!(f, "{} {}: {}", arg, path, reason)
+            }
+            CliError::UnknownArg(arg) => write!(f, "unknown argument `{}`", arg),
+            CliError::RoleConflict { arg, role } => {
+                write!(f, "{} can not be used with {} {}", arg, ROLE_ARG, role)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+// 命令行参数, 没有指定的项使用各个设置的默认值
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaunchOptions {
+    pub role: LaunchRole,
+    pub rules: Option<PathBuf>,
+    pub game_rules: GameRules, // 从 rules 读取的规则, 没有指定时为默认规则
+    pub log_level: Option<Level>,
+    pub addr: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub player_name: Option<String>,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub split_keyboard: bool, // 本机两个座位, 只用于 offline
+    pub help: bool,
+    pub net_conditions: Option<NetConditions>, // 没有指定时读取 NET_SIM 环境变量
+    pub replay: Option<Replay>,                // 回放的录像
+    pub input_script: Option<InputScript>,     // 代替键盘的输入脚本
+    pub record_input: Option<PathBuf>,         // 录制本地输入的文件
+}
+
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        LaunchOptions {
+            role: LaunchRole::Offline,
+            rules: None,
+            game_rules: GameRules::default(),
+            log_level: None,
+            addr: None,
+            port: None,
+            player_name: None,
+            seed: None,
+            headless: false,
+            split_keyboard: false,
+            help: false,
+            net_conditions: None,
+            replay: None,
+            input_script: None,
+            record_input: None,
+        }
+    }
+}
+
+// 读取参数 arg 后面的值
+fn value<T: FromStr>(arg: &str, args: &mut impl Iterator<Item = String>) -> Result<T, CliError> {
+    let value = args
+        .next()
+        .ok_or_else(|| CliError::MissingValue(arg.to_string()))?;
+    value.parse().map_err(|_| CliError::InvalidValue {
+        arg: arg.to_string(),
+        value,
+    })
+}
+
+// 读取参数 arg 指定的文件
+fn load<T, E: fmt::Display>(
+    arg: &str,
+    path: &Path,
+    load: impl FnOnce(&Path) -> Result<T, E>,
+) -> Result<T, CliError> {
+    load(path).map_err(|e| CliError::InvalidFile {
+        arg: arg.to_string(),
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    })
+}
+
+impl LaunchOptions {
+    // 参数有误时打印用法并退出. 没有 --net-sim 时读取 NET_SIM 环境变量, 格式错误同样退出
+    pub fn from_command_line() -> Self {
+        let options = Self::parse(std::env::args().skip(1))
+            .and_then(Self::with_env)
+            .unwrap_or_else(|e| {
+                eprintln!("{}\n\n{}", e, USAGE);
+                std::process::exit(2);
+            });
+        if options.help {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        options
+    }
+
+    fn with_env(mut self) -> Result<Self, CliError> {
+        if self.net_conditions.is_none() {
+            let conditions = NetConditions::from_env().map_err(|e| CliError::InvalidValue {
+                arg: NET_SIM_ENV.to_string(),
+                value: e.0,
+            })?;
+            self.net_conditions = Some(conditions);
+        }
+        Ok(self)
+    }
+
+    // args 不包含程序名. 规则、录像和输入脚本文件在这里读取, 内容有误时返回错误
+    pub fn parse<I, S>(args: I) -> Result<Self, CliError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut options = LaunchOptions::default();
+        let mut args = args.into_iter().map(Into::into);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                ROLE_ARG => options.role = value(&arg, &mut args)?,
+                RULES_ARG => {
+                    let path: PathBuf = value(&arg, &mut args)?;
+                    options.game_rules = load(&arg, &path, GameRules::load)?;
+                    options.rules = Some(path);
+                }
+                LOG_LEVEL_ARG => options.log_level = Some(value(&arg, &mut args)?),
+                ADDR_ARG => options.addr = Some(value(&arg, &mut args)?),
+                PORT_ARG => options.port = Some(value(&arg, &mut args)?),
+                NAME_ARG => options.player_name = Some(value(&arg, &mut args)?),
+                SEED_ARG => options.seed = Some(value(&arg, &mut args)?),
+                HEADLESS_ARG => options.headless = true,
+                SPLIT_KEYBOARD_ARG => options.split_keyboard = true,
+                HELP_ARG | "-h" => options.help = true,
+                NET_SIM_ARG => options.net_conditions = Some(value(&arg, &mut args)?),
+                REPLAY_ARG => {
+                    let path: PathBuf = value(&arg, &mut args)?;
+                    options.replay = Some(load(&arg, &path, Replay::load)?);
+                }
+                INPUT_SCRIPT_ARG => {
+                    let path: PathBuf = value(&arg, &mut args)?;
+                    options.input_script = Some(load(&arg, &path, InputScript::load)?);
+                }
+                RECORD_INPUT_ARG => options.record_input = Some(value(&arg, &mut args)?),
+                _ => return Err(CliError::UnknownArg(arg)),
+            }
+        }
+        // host 的第二个座位会占掉远程玩家的位置
+        if options.split_keyboard && options.role != LaunchRole::Offline {
+            return Err(CliError::RoleConflict {
+                arg: SPLIT_KEYBOARD_ARG.to_string(),
+                role: options.role,
+            });
+        }
+        // 客户端的玩法在服务器上运行, 使用服务器的种子
+        if options.seed.is_some() && options.is_client() {
+            return Err(CliError::RoleConflict {
+                arg: SEED_ARG.to_string(),
+                role: options.role,
+            });
+        }
+        Ok(options)
+    }
+
+    // 连接服务器的运行方式
+    pub fn is_client(&self) -> bool {
+        matches!(self.role, LaunchRole::Client | LaunchRole::Spectator)
+    }
+
+    // 服务器默认只输出 INFO, 其它运行方式方便调试输出 DEBUG
+    pub fn log_level(&self) -> Level {
+        match (self.log_level, self.role) {
+            (Some(level), _) => level,
+            (None, LaunchRole::Server) => Level::INFO,
+            (None, _) => Level::DEBUG,
+        }
+    }
+
+    // server/host 监听的地址
+    pub fn bind_addr(&self) -> SocketAddr {
+        let default = NetServerSettings::default().bind_addr;
+        SocketAddr::new(
+            self.addr.unwrap_or_else(|| default.ip()),
+            self.port.unwrap_or_else(|| default.port()),
+        )
+    }
+
+    // client/spectator 连接的服务器地址
+    pub fn server_addr(&self) -> SocketAddr {
+        let default = NetClientSettings::default().server_addr;
+        SocketAddr::new(
+            self.addr.unwrap_or_else(|| default.ip()),
+            self.port.unwrap_or_else(|| default.port()),
+        )
+    }
+
+    pub fn net_conditions(&self) -> NetConditions {
+        self.net_conditions.clone().unwrap_or_default()
+    }
+
+    pub fn window_title(&self) -> String {
+        match (self.role, &self.player_name) {
+            (LaunchRole::Offline, _) => GAME_WINDOW_TITLE.to_string(),
+            (role, Some(name)) => format!("{} - {} {}", GAME_WINDOW_TITLE, role, name),
+            (role, None) => format!("{} - {}", GAME_WINDOW_TITLE, role),
+        }
+    }
+
+    // 插入所选运行方式需要的资源和插件, 之后还可以添加录像等插件
+    pub fn build_app(&self, app: &mut AppBuilder) {
+        self.insert_resources(app);
+        if self.headless {
+            app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+                SIMULATION_TICK,
+            )))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(LogPlugin)
+            .add_plugin(InputPlugin);
+        } else {
+            app.insert_resource(WindowDescriptor {
+                title: self.window_title(),
+                ..Default::default()
+            })
+            .insert_resource(AssetServerSettings {
+                // 这里重置 assets 目录防止 ide debug 的时候程序是在生成 target 文件夹里跑导致 assets 文件夹找不到
+                asset_folder: format!("{}/assets", env!("CARGO_MANIFEST_DIR")),
+            })
+            .add_plugins(DefaultPlugins);
+        }
+        self.add_game_plugins(app);
+        info!(
+            "launch as {}{}",
+            self.role,
+            if self.headless { " (headless)" } else { "" }
+        );
+    }
+
+    // 规则、种子、网络地址等资源, 需要在添加插件之前插入
+    pub fn insert_resources(&self, app: &mut AppBuilder) {
+        app.insert_resource(LogSettings {
+            level: self.log_level(),
+            ..Default::default()
+        })
+        .insert_resource(self.game_rules.clone())
+        .insert_resource(RulesReloadSettings {
+            path: self.rules.clone(),
+            ..Default::default()
+        })
+        .insert_resource(LocalPlayerName(self.player_name.clone()));
+        if let Some(seed) = self.seed {
+            app.insert_resource(GameSeed(seed));
+        }
+        if self.split_keyboard {
+            app.insert_resource(PlayerInputSettings::split_keyboard());
+        }
+        match self.role {
+            LaunchRole::Offline => {}
+            LaunchRole::Host | LaunchRole::Server => {
+                app.insert_resource(self.net_conditions())
+                    .insert_resource(NetServerSettings {
+                        bind_addr: self.bind_addr(),
+                        ..Default::default()
+                    });
+            }
+            LaunchRole::Client | LaunchRole::Spectator => {
+                let default = NetClientSettings::default();
+                let role = match self.role {
+                    LaunchRole::Spectator => ClientRole::Spectator,
+                    _ => ClientRole::Player,
+                };
+                app.insert_resource(self.net_conditions())
+                    .insert_resource(NetClientSettings {
+                        server_addr: self.server_addr(),
+                        player_name: self.player_name.clone().unwrap_or(default.player_name),
+                        role,
+                        ..default
+                    });
+            }
+        }
+        // 服务器上没有本地玩家, 所有玩家都由客户端加入
+        if self.role == LaunchRole::Server {
+            app.insert_resource(SpawnLocalPlayer(false));
+        }
+    }
+
+    // 所选运行方式的玩法和网络插件, 不包含窗口、日志等引擎插件
+    pub fn add_game_plugins(&self, app: &mut AppBuilder) {
+        match (self.role, self.headless) {
+            (LaunchRole::Offline, false) => {
+                app.add_plugins(TestNetGamePlugins);
+            }
+            (LaunchRole::Offline, true) => {
+                app.add_plugins(HeadlessGamePlugins);
+            }
+            (LaunchRole::Host, false) => {
+                app.add_plugins(TestNetGamePlugins)
+                    .add_plugin(NetServerPlugin);
+            }
+            (LaunchRole::Host, true) => {
+                app.add_plugins(HeadlessGamePlugins)
+                    .add_plugin(NetServerPlugin);
+            }
+            // 有窗口的服务器显示比赛画面, 方便观察
+            (LaunchRole::Server, headless) => {
+                app.add_plugins(DedicatedServerPlugins);
+                if !headless {
+                    app.add_plugin(PresentationPlugin).add_plugin(UiPlugin);
+                }
+            }
+            (LaunchRole::Client, false) => {
+                app.add_plugins(NetClientPlugins);
+            }
+            (LaunchRole::Spectator, false) => {
+                app.add_plugins(NetSpectatorPlugins);
+            }
+            // 无窗口时没有视角可以切换, 和客户端一样只同步比赛
+            (LaunchRole::Client, true) | (LaunchRole::Spectator, true) => {
+                app.add_plugin(GameCorePlugin)
+                    .add_plugin(InputExtPlugin)
+                    .add_plugin(NetClientPlugin);
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use super::game::*;
+use super::game_mode::GameMode;
+use super::player::PlayerSystem;
 use bevy::prelude::*;
 use rand::Rng;
 pub struct CoinPlugin;
@@ -6,75 +8,149 @@ pub struct CoinPlugin;
 impl Plugin for CoinPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_event::<CoinPickedupEvent>()
-            .add_event::<NewCoinSpawnedEvent>()
+            .add_simulation_event::<NewCoinSpawnedEvent>()
+            .init_resource::<CoinPool>()
+            .init_resource::<CoinSerial>()
             .add_system_set_to_stage(
-                GameStage::EventHandle,
+                GameStage::Simulation,
                 SystemSet::new()
-                    .with_system(pickedup_event_listener_system.system())
-                    .with_system(spawn_new_event_listener_system.system()),
+                    .with_system(
+                        pickedup_event_listener_system
+                            .system()
+                            .label(CoinSystem::Pickedup)
+                            .after(PlayerSystem::PlayerCollision),
+                    )
+                    .with_system(
+                        rules_changed_system
+                            .system()
+                            .label(CoinSystem::RulesChanged)
+                            .after(CoinSystem::Pickedup),
+                    )
+                    .with_system(
+                        spawn_new_event_listener_system
+                            .system()
+                            .after(CoinSystem::RulesChanged),
+                    ),
             )
-            .add_system_set(
+            .add_system_set_to_stage(
+                GameStage::Simulation,
                 SystemSet::on_enter(MatchState::Playing).with_system(beginplay_system.system()),
             )
-            .add_system_set(
+            .add_system_set_to_stage(
+                GameStage::Simulation,
                 SystemSet::on_update(MatchState::Playing).with_system(update_system.system()),
             )
-            .add_system_set(
+            .add_system_set_to_stage(
+                GameStage::Simulation,
                 SystemSet::on_enter(MatchState::GameOver).with_system(gameover_system.system()),
             );
     }
 }
 
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+enum CoinSystem {
+    Pickedup,
+    RulesChanged,
+}
+
 pub struct CoinPickedupEvent {
     pub coin: Entity,
 }
 
 pub struct NewCoinSpawnedEvent {}
 
-pub struct Coin;
+pub struct Coin {
+    pub serial: u64, // 生成序号, 越大生成得越晚
+}
 
 pub struct CoinInfo {
     pub score_value: usize,
 }
 
-fn beginplay_system(rules: Res<GameRules>, mut event: EventWriter<NewCoinSpawnedEvent>) {
+// 本局还能生成的金币数量, 只在 CoinPool 模式下有限制
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CoinPool(pub Option<usize>);
+
+// 下一枚金币的生成序号
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CoinSerial(pub u64);
+
+fn beginplay_system(
+    rules: Res<GameRules>,
+    mut pool: ResMut<CoinPool>,
+    mut event: EventWriter<NewCoinSpawnedEvent>,
+) {
     debug!("init coins!");
+    pool.0 = match rules.mode {
+        GameMode::CoinPool { coins } => Some(coins),
+        _ => None,
+    };
     (0..rules.max_coin_num).for_each(|_| {
         event.send(NewCoinSpawnedEvent {});
     });
 }
 
+// 生成一枚金币实体, 服务器和客户端镜像共用. 只包含玩法组件, 显示由 PresentationPlugin 添加
+pub fn spawn_coin(
+    commands: &mut Commands,
+    rules: &GameRules,
+    serial: u64,
+    score_value: usize,
+    position: Vec3,
+) -> Entity {
+    commands
+        .spawn()
+        .insert_bundle((Coin { serial },))
+        .insert_bundle((CoinInfo { score_value },))
+        .insert_bundle((
+            Collider {
+                size: rules.coin_brick_size,
+            },
+            Transform::from_translation(position),
+            GlobalTransform::default(),
+        ))
+        .id()
+}
+
 fn spawn_new_event_listener_system(
     rules: Res<GameRules>,
+    mut rng: ResMut<GameRng>,
+    mut pool: ResMut<CoinPool>,
+    mut serial: ResMut<CoinSerial>,
     mut commands: Commands,
     mut events: EventReader<NewCoinSpawnedEvent>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
     events.iter().for_each(|_| {
-        commands
-            .spawn()
-            .insert_bundle((Coin,))
-            .insert_bundle((CoinInfo {
-                score_value: rand::thread_rng()
-                    .gen_range(rules.min_coin_score_value..rules.max_coin_score_value),
-            },))
-            .insert_bundle(SpriteBundle {
-                material: materials.add(Color::GOLD.into()),
-                transform: Transform::from_xyz(
-                    rand::thread_rng().gen_range(-300.0..300.0),
-                    rand::thread_rng().gen_range(-300.0..300.0),
-                    0.0,
-                ),
-                sprite: Sprite::new(Vec2::new(50.0, 50.0)),
-                ..Default::default()
-            });
+        match pool.0.as_mut() {
+            Some(0) => return,
+            Some(left) => *left -= 1,
+            None => {}
+        }
+        serial.0 += 1;
+        spawn_coin(
+            &mut commands,
+            &rules,
+            serial.0,
+            rng.0
+                .gen_range(rules.min_coin_score_value..rules.max_coin_score_value),
+            Vec3::new(
+                rng.0.gen_range(-300.0..300.0),
+                rng.0.gen_range(-300.0..300.0),
+                0.0,
+            ),
+        );
     });
 }
 
 fn update_system() {}
 
-fn gameover_system(mut commands: Commands, query: Query<Entity, With<Coin>>) {
+fn gameover_system(
+    mut commands: Commands,
+    mut pool: ResMut<CoinPool>,
+    query: Query<Entity, With<Coin>>,
+) {
     debug!("game over!");
+    pool.0 = None;
     query.for_each(|e| commands.entity(e).despawn());
 }
 
@@ -89,3 +165,36 @@ fn pickedup_event_listener_system(
         spawn_coin_event.send(NewCoinSpawnedEvent {});
     });
 }
+
+// 规则重新读取后, 已有金币换成新的尺寸, 比赛中按新的数量补充或移除金币. 分值范围只影响之后生成的金币
+// 在 tick 内运行, 帧同步和录像回放的各个节点在同一个 tick 生效
+fn rules_changed_system(
+    mut commands: Commands,
+    rules: Res<GameRules>,
+    state: Res<State<MatchState>>,
+    mut rules_events: EventReader<GameRulesChangedEvent>,
+    mut spawn_coin_event: EventWriter<NewCoinSpawnedEvent>,
+    mut query: Query<(Entity, &Coin, &mut Collider)>,
+) {
+    if rules_events.iter().last().is_none() {
+        return;
+    }
+    let mut coins: Vec<(u64, Entity)> = query
+        .iter_mut()
+        .map(|(entity, coin, mut collider)| {
+            collider.size = rules.coin_brick_size;
+            (coin.serial, entity)
+        })
+        .collect();
+    if *state.current() != MatchState::Playing {
+        return;
+    }
+    // 先移除最后生成的金币
+    coins.sort_unstable();
+    coins
+        .iter()
+        .skip(rules.max_coin_num)
+        .for_each(|(_, coin)| commands.entity(*coin).despawn());
+    (coins.len()..rules.max_coin_num).for_each(|_| spawn_coin_event.send(NewCoinSpawnedEvent {}));
+    debug!("coins: {} -> {}", coins.len(), rules.max_coin_num);
+}
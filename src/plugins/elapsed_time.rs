@@ -1,33 +1,66 @@
-use bevy::core::FixedTimestep;
+use super::game::{GameStage, MatchState, SimulationClock, SIMULATION_TICK};
 use bevy::prelude::*;
 pub struct ElapsedTimePlugin;
 
+// 比赛开始后经过的秒数, 按模拟 tick 计算, 回放和帧同步的各个节点一致
 #[derive(Default)]
-struct ElapsedSeconds(usize);
+pub struct ElapsedSeconds {
+    start_tick: u32,
+    seconds: usize,
+}
+
+impl ElapsedSeconds {
+    pub fn seconds(&self) -> usize {
+        self.seconds
+    }
+}
 
 pub struct ElapsedSecondChangedEvent {
     pub seconds: usize,
 }
 
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+pub struct ElapsedTimeSystem;
+
 impl Plugin for ElapsedTimePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<ElapsedSeconds>()
             .add_event::<ElapsedSecondChangedEvent>()
-            .add_system_set(
-                SystemSet::new()
-                    .with_run_criteria(FixedTimestep::step(1.0))
-                    .with_system(elapsed_time_update_system.system()),
+            .add_system_set_to_stage(
+                GameStage::Simulation,
+                SystemSet::on_enter(MatchState::Playing)
+                    .with_system(elapsed_time_start_system.system().label(ElapsedTimeSystem)),
+            )
+            .add_system_set_to_stage(
+                GameStage::Simulation,
+                SystemSet::on_update(MatchState::Playing)
+                    .with_system(elapsed_time_update_system.system().label(ElapsedTimeSystem)),
             );
     }
 }
 
+fn elapsed_time_start_system(
+    clock: Res<SimulationClock>,
+    mut elapsed_time: ResMut<ElapsedSeconds>,
+    mut changed_event: EventWriter<ElapsedSecondChangedEvent>,
+) {
+    elapsed_time.start_tick = clock.tick();
+    elapsed_time.seconds = 0;
+    changed_event.send(ElapsedSecondChangedEvent { seconds: 0 });
+}
+
 fn elapsed_time_update_system(
+    clock: Res<SimulationClock>,
     mut elapsed_time: ResMut<ElapsedSeconds>,
     mut changed_event: EventWriter<ElapsedSecondChangedEvent>,
 ) {
-    elapsed_time.0 += 1;
-    //debug!("elapsed_time update, {}s now!", elapsed_time.0);
-    changed_event.send(ElapsedSecondChangedEvent {
-        seconds: elapsed_time.0,
-    });
+    // 用整数 tick 计算, 避免浮点累加误差
+    let ticks_per_second = (1.0 / SIMULATION_TICK).round() as u32;
+    let seconds =
+        (clock.tick().saturating_sub(elapsed_time.start_tick) / ticks_per_second) as usize;
+    if seconds != elapsed_time.seconds {
+        elapsed_time.seconds = seconds;
+        //debug!("elapsed_time update, {}s now!", seconds);
+        changed_event.send(ElapsedSecondChangedEvent { seconds });
+    }
 }
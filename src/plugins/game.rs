@@ -1,13 +1,26 @@
-use bevy::app::{PluginGroup, PluginGroupBuilder};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bevy::app::{Events, PluginGroup, PluginGroupBuilder};
+use bevy::ecs::component::Component;
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
 
+use super::action_input::ActionInputPlugin;
 use super::coin::CoinPlugin;
 use super::elapsed_time::ElapsedTimePlugin;
+use super::game_mode::{GameMode, GameModePlugin};
 use super::input_ext::InputExtPlugin;
+use super::key_binding::KeyBindingPlugin;
 use super::player::*;
+use super::presentation::PresentationPlugin;
+use super::rules::RulesHotReloadPlugin;
 use super::ui::UiPlugin;
 
-#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
 pub enum MatchState {
     WaitingForBegin,
     Playing,
@@ -17,40 +30,95 @@ pub enum MatchState {
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, StageLabel)]
 pub enum GameStage {
     EventHandle, // 事件处理统一注册Stage
+    Simulation,  // 玩法逻辑按固定 tick 运行的Stage, 一帧内可能运行 0 到多次
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+pub enum GameSystem {
+    MatchStateUpdate,
+    ReadyInput,
+}
+
+// 大厅阶段切换准备状态的按键
+pub const READY_KEY: KeyCode = KeyCode::Return;
+
+// 本地玩家请求切换准备状态, 网络客户端会转发给服务器
+pub struct ReadyRequestEvent {
+    pub ready: bool,
 }
 
+// 模拟 tick 的固定时长(秒)
+pub const SIMULATION_TICK: f64 = 1.0 / 60.0;
+
+// 一帧内最多追赶的 tick 数, 避免卡顿后一次模拟太多
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
 pub struct TestNetGamePlugins;
 
 impl PluginGroup for TestNetGamePlugins {
     fn build(&mut self, group: &mut PluginGroupBuilder) {
         group.add(GameCorePlugin);
         group.add(InputExtPlugin);
+        group.add(KeyBindingPlugin);
+        group.add(ActionInputPlugin);
         group.add(PlayerPlugin);
         group.add(CoinPlugin);
         group.add(ElapsedTimePlugin);
+        group.add(PresentationPlugin);
         group.add(UiPlugin);
+        group.add(RulesHotReloadPlugin);
     }
 }
+
+// 无窗口的单机插件组: 只运行玩法逻辑, 配合输入脚本使用
+pub struct HeadlessGamePlugins;
+
+impl PluginGroup for HeadlessGamePlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(GameCorePlugin);
+        group.add(InputExtPlugin);
+        group.add(PlayerPlugin);
+        group.add(CoinPlugin);
+        group.add(ElapsedTimePlugin);
+        group.add(RulesHotReloadPlugin);
+    }
+}
+
+// 比赛规则, 可以从 RON/TOML 文件读取, 文件中没有写的字段使用默认值
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct GameRules {
     // Gameplay
     pub max_coin_num: usize,         // 可同时存在的最大硬币数量
-    pub min_player_num: usize,       // 可以开始游戏的最小玩家数量(小于该数量不会开始游戏) (未实现)
-    pub max_player_num: usize,       // 游戏最大容纳的玩家数量 (未实现)
-    pub target_score: usize,         // 得到 target_score 分数以上游戏结束
+    pub min_player_num: usize,       // 可以开始游戏的最小已准备玩家数量(小于该数量不会开始游戏)
+    pub max_player_num: usize,       // 游戏最大容纳的玩家数量, 超过的加入请求会被拒绝
+    pub target_score: usize,         // FirstToScore 模式下得到 target_score 分数以上游戏结束
     pub min_coin_score_value: usize, // 单枚金币最x小价值
     pub max_coin_score_value: usize, // 单枚金币最大价值
-    pub delay_seconds: f32,          // 延迟开始游戏的秒数(enable_delay==true 时有效) 0 表示不延迟
+    pub delay_seconds: f32, // 大厅准备人数达到 min_player_num 后的开局倒计时(秒), 0 表示立即开始, 热更新后立即生效
+    pub spawn_points: Vec<Vec2>, // 玩家出生点, 按加入位置循环使用
+    pub mode: GameMode,     // 比赛结束条件
 
     // 单位外观
     pub player_brick_size: Vec2, // 玩家方块大小
     pub coin_brick_size: Vec2,   // 金币方块大小
 
     // UI
-    pub font_path: &'static str,
+    pub font_path: String,
     pub font_size: f32,
 }
 
-struct GameCorePlugin;
+// 运行中替换了比赛规则, previous 为替换前的规则
+pub struct GameRulesChangedEvent {
+    pub previous: GameRules,
+}
+
+// 玩法使用的碰撞尺寸, 和显示用的 Sprite 分开, 无渲染的服务器也可以判断碰撞
+pub struct Collider {
+    pub size: Vec2,
+}
+
+pub struct GameCorePlugin;
 
 impl Plugin for GameCorePlugin {
     fn build(&self, app: &mut AppBuilder) {
@@ -59,13 +127,181 @@ impl Plugin for GameCorePlugin {
             GameStage::EventHandle,
             SystemStage::parallel(),
         )
-        .add_state(MatchState::WaitingForBegin)
+        // 单线程执行保证每个 tick 内系统的执行顺序固定
+        .add_stage_after(
+            CoreStage::Update,
+            GameStage::Simulation,
+            SystemStage::single_threaded().with_run_criteria(simulation_run_criteria.system()),
+        )
+        // 比赛状态只在 tick 内切换, 所有节点在同一个 tick 进入新状态
+        .add_state_to_stage(GameStage::Simulation, MatchState::WaitingForBegin)
         .init_resource::<GameRules>()
         .init_resource::<GameState>()
         .init_resource::<GameDelayStart>()
+        .init_resource::<MatchAuthority>()
+        .init_resource::<SimulationClock>()
+        .init_resource::<GameSeed>()
+        .init_resource::<GameRng>()
+        .add_event::<ReadyRequestEvent>()
+        .add_simulation_event::<GameRulesChangedEvent>()
         .add_startup_system(game_init_system.system())
-        .add_system(game_state_update_system.system())
-        .add_system(delay_start_update_system.system());
+        .add_system_to_stage(CoreStage::PreUpdate, simulation_clock_system.system())
+        .add_system(ready_input_system.system().label(GameSystem::ReadyInput))
+        .add_system(
+            local_ready_system
+                .system()
+                .after(GameSystem::ReadyInput)
+                .with_run_criteria(match_authority_run_criteria.system()),
+        )
+        .add_system_set_to_stage(
+            GameStage::Simulation,
+            SystemSet::new()
+                .with_run_criteria(match_authority_run_criteria.system())
+                .with_system(delay_start_update_system.system()),
+        )
+        .add_plugin(GameModePlugin);
+    }
+}
+
+// 是否由本进程推进比赛流程, 网络客户端上比赛状态完全由服务器同步
+pub struct MatchAuthority(pub bool);
+
+impl Default for MatchAuthority {
+    fn default() -> Self {
+        MatchAuthority(true)
+    }
+}
+
+// 固定步长的模拟时钟, 决定每帧运行多少个 tick
+pub struct SimulationClock {
+    tick: u32,
+    accumulator: f64,
+    limit: Option<u32>,
+    resimulate: u32, // 回滚后本帧需要立即重新模拟的 tick 数
+    speed: f64,      // 模拟时间相对真实时间的倍率, 0 表示暂停
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        SimulationClock {
+            tick: 0,
+            accumulator: 0.0,
+            limit: None,
+            resimulate: 0,
+            speed: 1.0,
+        }
+    }
+}
+
+impl SimulationClock {
+    // 当前(或最近一次)模拟的 tick, 第一个 tick 为 1
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    // 本帧最多再模拟 ticks 个 tick, 用于等待其它节点的输入
+    pub fn limit_ticks(&mut self, ticks: u32) {
+        self.limit = Some(ticks);
+    }
+
+    // 世界状态已经恢复到 tick 结束时, 本帧从 tick + 1 重新模拟到当前 tick
+    pub fn rewind(&mut self, tick: u32) {
+        self.resimulate += self.tick.saturating_sub(tick);
+        self.tick = self.tick.min(tick);
+    }
+
+    // 本帧额外立即模拟 ticks 个 tick, 用于回放快进
+    pub fn fast_forward(&mut self, ticks: u32) {
+        self.resimulate += ticks;
+    }
+
+    // 回到第一个 tick 之前, 保留速度设置
+    pub fn reset(&mut self) {
+        *self = SimulationClock {
+            speed: self.speed,
+            ..Default::default()
+        };
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(0.0);
+    }
+}
+
+fn simulation_clock_system(time: Res<Time>, mut clock: ResMut<SimulationClock>) {
+    let max = MAX_TICKS_PER_FRAME as f64 * SIMULATION_TICK;
+    let delta = time.delta_seconds_f64() * clock.speed;
+    clock.accumulator = (clock.accumulator + delta).min(max);
+}
+
+fn simulation_run_criteria(mut clock: ResMut<SimulationClock>) -> ShouldRun {
+    // 重新模拟的 tick 不消耗时间, 也不受输入等待的限制
+    if clock.resimulate > 0 {
+        clock.resimulate -= 1;
+        clock.tick += 1;
+        return ShouldRun::YesAndCheckAgain;
+    }
+    let allowed = clock.limit.iter().all(|limit| *limit > 0);
+    if allowed && clock.accumulator >= SIMULATION_TICK {
+        clock.accumulator -= SIMULATION_TICK;
+        clock.tick += 1;
+        if let Some(limit) = clock.limit.as_mut() {
+            *limit -= 1;
+        }
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.limit = None;
+        ShouldRun::No
+    }
+}
+
+// 本局玩法随机数的种子, 录像会记录下来, 回放时用同一个种子
+pub struct GameSeed(pub u64);
+
+impl Default for GameSeed {
+    fn default() -> Self {
+        GameSeed(rand::random())
+    }
+}
+
+// 玩法逻辑使用的随机数, 相同种子在所有平台上产生相同的序列
+#[derive(Clone)]
+pub struct GameRng(pub Pcg32);
+
+impl FromWorld for GameRng {
+    fn from_world(world: &mut World) -> Self {
+        let seed = world.get_resource_or_insert_with(GameSeed::default);
+        GameRng::from_seed(seed.0)
+    }
+}
+
+impl GameRng {
+    pub fn from_seed(seed: u64) -> Self {
+        GameRng(Pcg32::seed_from_u64(seed))
+    }
+}
+
+// 只在模拟 tick 内产生和消费的事件按 tick 清理, 不受每帧 tick 数量的影响
+pub trait AddSimulationEvent {
+    fn add_simulation_event<T: Component>(&mut self) -> &mut Self;
+}
+
+impl AddSimulationEvent for AppBuilder {
+    fn add_simulation_event<T: Component>(&mut self) -> &mut Self {
+        self.insert_resource(Events::<T>::default())
+            .add_system_to_stage(GameStage::Simulation, Events::<T>::update_system.system())
+    }
+}
+
+pub(crate) fn match_authority_run_criteria(authority: Res<MatchAuthority>) -> ShouldRun {
+    if authority.0 {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
     }
 }
 
@@ -79,22 +315,114 @@ impl Default for GameRules {
             min_coin_score_value: 1,
             max_coin_score_value: 5,
             delay_seconds: 0.0,
+            spawn_points: vec![
+                Vec2::new(0.0, -215.0),
+                Vec2::new(-100.0, -215.0),
+                Vec2::new(100.0, -215.0),
+                Vec2::new(-200.0, -215.0),
+                Vec2::new(200.0, -215.0),
+            ],
+            mode: GameMode::FirstToScore,
             player_brick_size: Vec2::new(50.0, 50.0),
             coin_brick_size: Vec2::new(25.0, 25.0),
-            font_path: "fonts/FiraSans-Bold.ttf",
+            font_path: "fonts/FiraSans-Bold.ttf".to_string(),
             font_size: 50.0,
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct GameState {
-    win_team_id: usize,
+    result: Option<MatchResult>, // 比赛结束后的结果
 }
 
 impl GameState {
+    // 唯一获胜的队伍, 比赛未结束或平局时为 0
     pub fn get_win_team_id(&self) -> usize {
-        self.win_team_id
+        self.result
+            .as_ref()
+            .and_then(MatchResult::win_team_id)
+            .unwrap_or(0)
+    }
+
+    pub fn result(&self) -> Option<&MatchResult> {
+        self.result.as_ref()
+    }
+
+    pub fn set_result(&mut self, result: Option<MatchResult>) {
+        self.result = result;
+    }
+}
+
+// 一局比赛的结果, 会同步给客户端并保存在录像中
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MatchResult {
+    pub teams: Vec<TeamResult>,     // 按名次排序
+    pub players: Vec<PlayerResult>, // 比赛结束时在场的玩家, 按分数排序
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TeamResult {
+    pub team_id: usize,
+    pub score: usize, // 包含中途离开的玩家留下的分数
+    pub rank: usize,  // 从 1 开始, 分数相同的队伍名次相同
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerResult {
+    pub name: String,
+    pub team_id: usize,
+    pub score: usize,
+    pub coins: usize,
+}
+
+impl MatchResult {
+    // 按最终分数排名, 同一个 tick 内多个队伍达到目标分数时也按分数比较
+    pub fn new(team_scores: BTreeMap<usize, usize>, mut players: Vec<PlayerResult>) -> Self {
+        let mut teams: Vec<TeamResult> = team_scores
+            .into_iter()
+            .map(|(team_id, score)| TeamResult {
+                team_id,
+                score,
+                rank: 0,
+            })
+            .collect();
+        teams.sort_by(|a, b| b.score.cmp(&a.score).then(a.team_id.cmp(&b.team_id)));
+        let mut previous: Option<(usize, usize)> = None; // 上一个队伍的分数和名次
+        for (index, team) in teams.iter_mut().enumerate() {
+            team.rank = match previous {
+                Some((score, rank)) if score == team.score => rank,
+                _ => index + 1,
+            };
+            previous = Some((team.score, team.rank));
+        }
+        players.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(a.team_id.cmp(&b.team_id))
+                .then(a.name.cmp(&b.name))
+        });
+        MatchResult { teams, players }
+    }
+
+    // 第一名的队伍, 平局时有多个
+    pub fn winners(&self) -> Vec<usize> {
+        self.teams
+            .iter()
+            .filter(|team| team.rank == 1)
+            .map(|team| team.team_id)
+            .collect()
+    }
+
+    pub fn is_draw(&self) -> bool {
+        self.winners().len() > 1
+    }
+
+    pub fn win_team_id(&self) -> Option<usize> {
+        match self.winners().as_slice() {
+            [team_id] => Some(*team_id),
+            _ => None,
+        }
     }
 }
 
@@ -109,33 +437,64 @@ impl FromWorld for GameDelayStart {
 
 fn game_init_system() {}
 
-fn game_state_update_system(
-    mut state: ResMut<State<MatchState>>,
-    mut game_state: ResMut<GameState>,
-    rules: Res<GameRules>,
-    mut team_score_events: EventReader<TeamScoreChangedEvent>,
+// 把比赛流程恢复到刚启动时的状态, 玩家和金币由调用者处理
+pub fn reset_match(world: &mut World) {
+    world.insert_resource(GameState::default());
+    let delay_start = GameDelayStart::from_world(world);
+    world.insert_resource(delay_start);
+    world.get_resource_mut::<SimulationClock>().unwrap().reset();
+    // 不走状态切换, 和刚启动时一样在下一个 tick 进入 WaitingForBegin
+    world.insert_resource(State::new(MatchState::WaitingForBegin));
+}
+
+// 大厅阶段按下准备键切换本地玩家的准备状态
+fn ready_input_system(
+    keyboard: Res<Input<KeyCode>>,
+    state: Res<State<MatchState>>,
+    query: Query<&Ready, With<LocalPlayer>>,
+    mut ready_events: EventWriter<ReadyRequestEvent>,
 ) {
-    for TeamScoreChangedEvent {
-        team_score,
-        team_id,
-    } in team_score_events.iter()
-    {
-        if *team_score >= rules.target_score {
-            game_state.win_team_id = *team_id;
-            state
-                .set_next(MatchState::GameOver)
-                .expect("set match state gameover fail!");
-            info!("team {} win game!", team_id);
-        }
+    if *state.current() != MatchState::WaitingForBegin || !keyboard.just_pressed(READY_KEY) {
+        return;
     }
+    let ready = query.iter().next().iter().all(|ready| !ready.0);
+    ready_events.send(ReadyRequestEvent { ready });
 }
 
+fn local_ready_system(
+    mut events: EventReader<ReadyRequestEvent>,
+    mut query: Query<(&PlayerInfo, &mut Ready), With<LocalPlayer>>,
+) {
+    events.iter().for_each(|ReadyRequestEvent { ready }| {
+        query.iter_mut().for_each(|(info, mut current)| {
+            current.0 = *ready;
+            info!("{} ready: {}", info.name, ready);
+        });
+    });
+}
+
+// 已准备的玩家达到 min_player_num 后开始倒计时, 有人取消准备则重新计时
 fn delay_start_update_system(
-    time: Res<Time>,
     mut state: ResMut<State<MatchState>>,
     mut delay_start_timer: ResMut<GameDelayStart>,
+    rules: Res<GameRules>,
+    query: Query<&Ready, With<Player>>,
 ) {
-    if delay_start_timer.0.tick(time.delta()).just_finished() {
+    if *state.current() != MatchState::WaitingForBegin {
+        return;
+    }
+    // 热更新修改了倒计时
+    let delay = Duration::from_secs_f32(rules.delay_seconds);
+    if delay_start_timer.0.duration() != delay {
+        delay_start_timer.0.set_duration(delay);
+    }
+    let ready_num = query.iter().filter(|ready| ready.0).count();
+    if ready_num < rules.min_player_num {
+        delay_start_timer.0.reset();
+        return;
+    }
+    let tick = Duration::from_secs_f64(SIMULATION_TICK);
+    if delay_start_timer.0.tick(tick).just_finished() {
         state
             .set_next(MatchState::Playing)
             .expect("set match state fail!");
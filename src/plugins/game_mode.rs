@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::coin::{Coin, CoinPool};
+use super::elapsed_time::{ElapsedSeconds, ElapsedTimeSystem};
+use super::game::{
+    match_authority_run_criteria, GameRules, GameStage, GameState, GameSystem, MatchResult,
+    MatchState, PlayerResult,
+};
+use super::player::{
+    team_scores, DepartedScores, Player, PlayerInfo, Score, Team, TeamScoreChangedEvent,
+};
+
+// 比赛的结束条件, 由规则文件的 mode 选择
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GameMode {
+    FirstToScore,                 // 先达到 target_score 的队伍获胜
+    TimeLimit { seconds: usize }, // 时间到时分数最高的队伍获胜, 需要 ElapsedTimePlugin
+    CoinPool { coins: usize },    // 本局一共只生成 coins 枚金币, 全部吃完后分数最高的队伍获胜
+}
+
+// 各个模式的结束判断, 只在有比赛控制权的进程上运行. 由 GameCorePlugin 添加
+pub struct GameModePlugin;
+
+impl Plugin for GameModePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set_to_stage(
+            GameStage::Simulation,
+            SystemSet::new()
+                .with_run_criteria(match_authority_run_criteria.system())
+                .label(GameSystem::MatchStateUpdate)
+                .with_system(first_to_score_system.system())
+                .with_system(time_limit_system.system().after(ElapsedTimeSystem))
+                .with_system(coin_pool_system.system()),
+        );
+    }
+}
+
+// 同一个 tick 内可能有多个队伍达到目标分数, 都处理完后按分数排名
+fn first_to_score_system(
+    mut state: ResMut<State<MatchState>>,
+    mut game_state: ResMut<GameState>,
+    rules: Res<GameRules>,
+    mut team_score_events: EventReader<TeamScoreChangedEvent>,
+    departed_scores: Option<Res<DepartedScores>>,
+    query: Query<(&PlayerInfo, &Team, &Score), With<Player>>,
+) {
+    let reached: Vec<usize> = team_score_events
+        .iter()
+        .filter(|event| event.team_score >= rules.target_score)
+        .map(|event| event.team_id)
+        .collect();
+    if *state.current() != MatchState::Playing
+        || rules.mode != GameMode::FirstToScore
+        || reached.is_empty()
+    {
+        return;
+    }
+    debug!(
+        "teams {:?} reach target score {}",
+        reached, rules.target_score
+    );
+    finish_match(
+        &mut state,
+        &mut game_state,
+        departed_scores.as_deref(),
+        &query,
+    );
+}
+
+// 按在场玩家和离开玩家留下的分数生成比赛结果并结束比赛
+fn finish_match(
+    state: &mut State<MatchState>,
+    game_state: &mut GameState,
+    departed_scores: Option<&DepartedScores>,
+    query: &Query<(&PlayerInfo, &Team, &Score), With<Player>>,
+) {
+    // 同一帧已经切换过状态时保留之前的结果
+    if let Err(e) = state.set_next(MatchState::GameOver) {
+        warn!("can not finish match: {:?}", e);
+        return;
+    }
+    let scores = team_scores(
+        query.iter().map(|(_, team, score)| (team, score)),
+        departed_scores,
+    );
+    let players = query
+        .iter()
+        .map(|(info, team, score)| PlayerResult {
+            name: info.name.clone(),
+            team_id: team.id,
+            score: score.val,
+            coins: score.coins,
+        })
+        .collect();
+    let result = MatchResult::new(scores, players);
+    match result.win_team_id() {
+        Some(team_id) => info!("team {} win game!", team_id),
+        None => info!("draw game, teams {:?}", result.winners()),
+    }
+    game_state.set_result(Some(result));
+}
+
+fn time_limit_system(
+    mut state: ResMut<State<MatchState>>,
+    mut game_state: ResMut<GameState>,
+    rules: Res<GameRules>,
+    elapsed: Option<Res<ElapsedSeconds>>,
+    departed_scores: Option<Res<DepartedScores>>,
+    query: Query<(&PlayerInfo, &Team, &Score), With<Player>>,
+) {
+    let limit = match rules.mode {
+        GameMode::TimeLimit { seconds } => seconds,
+        _ => return,
+    };
+    let time_up = matches!(elapsed, Some(elapsed) if elapsed.seconds() >= limit);
+    if *state.current() != MatchState::Playing || !time_up {
+        return;
+    }
+    info!("time up after {}s", limit);
+    finish_match(
+        &mut state,
+        &mut game_state,
+        departed_scores.as_deref(),
+        &query,
+    );
+}
+
+// 金币池用完并且场上的金币都被吃掉后结束. 被吃掉的金币在 tick 结束时才销毁, 下一个 tick 再判断
+fn coin_pool_system(
+    mut state: ResMut<State<MatchState>>,
+    mut game_state: ResMut<GameState>,
+    pool: Option<Res<CoinPool>>,
+    departed_scores: Option<Res<DepartedScores>>,
+    coin_query: Query<(), With<Coin>>,
+    query: Query<(&PlayerInfo, &Team, &Score), With<Player>>,
+) {
+    let exhausted = matches!(pool, Some(pool) if pool.0 == Some(0));
+    if *state.current() != MatchState::Playing || !exhausted || coin_query.iter().next().is_some() {
+        return;
+    }
+    info!("coin pool is empty");
+    finish_match(
+        &mut state,
+        &mut game_state,
+        departed_scores.as_deref(),
+        &query,
+    );
+}
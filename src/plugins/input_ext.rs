@@ -1,43 +1,351 @@
-use bevy::input::InputSystem;
-use bevy::{prelude::*, utils::HashMap};
+use bevy::input::gamepad::{
+    Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType, GamepadEvent,
+    GamepadEventType,
+};
+use bevy::input::{Axis, InputSystem};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash, SystemLabel)]
 pub struct InputExtSystem;
 
+#[derive(Debug, PartialEq, Eq, Clone, Hash, SystemLabel)]
+enum GamepadSystem {
+    Connection,
+}
+
 pub struct InputExtPlugin;
 
 impl Plugin for InputExtPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<Input<PlayerOperate>>()
+            .init_resource::<SeatInputs>()
+            .init_resource::<HeldOperates>()
             .init_resource::<PlayerInputSettings>()
+            .init_resource::<GamepadInputSettings>()
+            .init_resource::<GamepadSeats>()
+            .init_resource::<AnalogInputs>()
             .add_system_to_stage(
                 CoreStage::PreUpdate,
                 input_ext_update_system
                     .system()
                     .label(InputExtSystem)
                     .after(InputSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                gamepad_connection_system
+                    .system()
+                    .label(GamepadSystem::Connection)
+                    .after(InputExtSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                gamepad_input_system
+                    .system()
+                    .after(GamepadSystem::Connection),
             );
     }
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
 pub enum PlayerOperate {
     MoveFrond,
     MoveBack,
     MoveRight,
     MoveLeft,
+    Sprint, // 疾跑, 默认 Shift+方向键
+    Dash,   // 冲刺, 默认双击方向键
 }
 
-#[derive(Default)]
+// 单个玩家当前按下的操作, 本地输入和网络输入都写入该组件
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct PlayerOperateState {
+    pressed: HashSet<PlayerOperate>,
+    analog: Vec2, // 摇杆方向, 不为 0 时代替按键方向
+}
+
+impl PlayerOperateState {
+    pub fn from_pressed<I: IntoIterator<Item = PlayerOperate>>(ops: I) -> Self {
+        Self {
+            pressed: ops.into_iter().collect(),
+            analog: Vec2::ZERO,
+        }
+    }
+
+    pub fn from_input(input: &Input<PlayerOperate>) -> Self {
+        Self::from_pressed(input.get_pressed().cloned())
+    }
+
+    // 附带摇杆方向, 长度超过 1 时归一化. 可能来自网络, 无效的数值视为没有推摇杆
+    pub fn with_analog(mut self, analog: Vec2) -> Self {
+        self.analog = if !analog.x.is_finite() || !analog.y.is_finite() {
+            Vec2::ZERO
+        } else if analog.length_squared() > 1.0 {
+            analog.normalize()
+        } else {
+            analog
+        };
+        self
+    }
+
+    pub fn analog(&self) -> Vec2 {
+        self.analog
+    }
+
+    pub fn pressed(&self, op: PlayerOperate) -> bool {
+        self.pressed.contains(&op)
+    }
+
+    // 排序后的按键列表, 保证网络传输时内容稳定
+    pub fn to_sorted_vec(&self) -> Vec<PlayerOperate> {
+        let mut ops: Vec<_> = self.pressed.iter().cloned().collect();
+        ops.sort();
+        ops
+    }
+
+    // 根据摇杆或按下的方向键计算移动方向, 摇杆推得越远速度越快
+    pub fn direction(&self) -> Vec2 {
+        if self.analog != Vec2::ZERO {
+            return self.analog;
+        }
+        let mut direction = Vec2::new(0.0, 0.0);
+        if self.pressed(PlayerOperate::MoveFrond) {
+            direction.y += 1.0;
+        }
+        if self.pressed(PlayerOperate::MoveBack) {
+            direction.y += -1.0;
+        }
+        if self.pressed(PlayerOperate::MoveLeft) {
+            direction.x += -1.0;
+        }
+        if self.pressed(PlayerOperate::MoveRight) {
+            direction.x += 1.0;
+        }
+        direction
+    }
+}
+
+// 玩家 1 使用 WASD
+pub const WASD_INPUT_MAP: [(KeyCode, PlayerOperate); 4] = [
+    (KeyCode::W, PlayerOperate::MoveFrond),
+    (KeyCode::S, PlayerOperate::MoveBack),
+    (KeyCode::A, PlayerOperate::MoveLeft),
+    (KeyCode::D, PlayerOperate::MoveRight),
+];
+
+// 同一键盘上的玩家 2 使用方向键
+pub const ARROW_INPUT_MAP: [(KeyCode, PlayerOperate); 4] = [
+    (KeyCode::Up, PlayerOperate::MoveFrond),
+    (KeyCode::Down, PlayerOperate::MoveBack),
+    (KeyCode::Left, PlayerOperate::MoveLeft),
+    (KeyCode::Right, PlayerOperate::MoveRight),
+];
+
+// 每个本地座位一套按键映射, 一台机器上的多个玩家共用一个键盘
+#[derive(Debug, Clone, PartialEq)]
 pub struct PlayerInputSettings {
-    input_map: HashMap<KeyCode, PlayerOperate>,
+    input_maps: Vec<HashMap<KeyCode, PlayerOperate>>,
+}
+
+// 默认本机一个玩家, 使用 WASD
+impl Default for PlayerInputSettings {
+    fn default() -> Self {
+        Self::from_seats(&[&WASD_INPUT_MAP])
+    }
 }
 
+// 按键已经绑定到了其它座位或操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyConflict {
+    pub key: KeyCode,
+    pub seat: usize,
+    pub operate: PlayerOperate,
+}
+
+impl fmt::Display for KeyConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is already bound to {:?} of seat {}",
+            self.key, self.operate, self.seat
+        )
+    }
+}
+
+impl std::error::Error for KeyConflict {}
+
 impl PlayerInputSettings {
-    pub fn from_array(arr: &Vec<(KeyCode, PlayerOperate)>) -> Self {
+    // 只有一个本地座位
+    pub fn from_array(arr: &[(KeyCode, PlayerOperate)]) -> Self {
+        Self::from_seats(&[arr])
+    }
+
+    // 第 i 个映射属于第 i 个座位
+    pub fn from_seats(seats: &[&[(KeyCode, PlayerOperate)]]) -> Self {
         Self {
-            input_map: arr.iter().cloned().collect(),
+            input_maps: seats
+                .iter()
+                .map(|arr| arr.iter().cloned().collect())
+                .collect(),
+        }
+    }
+
+    // 本地座位数量, 也就是本机可以同时参加比赛的玩家数量
+    // 分屏键盘: 同一键盘上两个玩家, WASD 和方向键
+    pub fn split_keyboard() -> Self {
+        Self::from_seats(&[&WASD_INPUT_MAP, &ARROW_INPUT_MAP])
+    }
+
+    pub fn seat_num(&self) -> usize {
+        self.input_maps.len()
+    }
+
+    // 多出的座位连同按键一起移除, 新增的座位没有按键
+    pub fn set_seat_num(&mut self, seat_num: usize) {
+        self.input_maps.resize_with(seat_num, Default::default);
+    }
+
+    // 座位的所有绑定, 按按键排序
+    pub fn bindings(&self, seat: usize) -> Vec<(KeyCode, PlayerOperate)> {
+        let mut bindings: Vec<_> = self
+            .input_maps
+            .get(seat)
+            .iter()
+            .flat_map(|input_map| input_map.iter().map(|(key, op)| (*key, *op)))
+            .collect();
+        bindings.sort();
+        bindings
+    }
+
+    // 触发座位上某个操作的按键
+    pub fn keys_of(&self, seat: usize, operate: PlayerOperate) -> Vec<KeyCode> {
+        self.bindings(seat)
+            .into_iter()
+            .filter(|(_, op)| *op == operate)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    // 按键当前绑定的座位和操作
+    pub fn binding_of(&self, key: KeyCode) -> Option<(usize, PlayerOperate)> {
+        self.input_maps
+            .iter()
+            .enumerate()
+            .find_map(|(seat, input_map)| input_map.get(&key).map(|op| (seat, *op)))
+    }
+
+    // 把座位上的操作改绑到 key, 原来的按键解除绑定. key 已被其它操作使用时返回冲突
+    pub fn rebind(
+        &mut self,
+        seat: usize,
+        operate: PlayerOperate,
+        key: KeyCode,
+    ) -> Result<(), KeyConflict> {
+        match self.binding_of(key) {
+            Some(binding) if binding != (seat, operate) => {
+                return Err(KeyConflict {
+                    key,
+                    seat: binding.0,
+                    operate: binding.1,
+                })
+            }
+            _ => {}
+        }
+        if let Some(input_map) = self.input_maps.get_mut(seat) {
+            input_map.retain(|_, op| *op != operate);
+        }
+        self.bind(seat, operate, key);
+        Ok(())
+    }
+
+    // 给座位上的操作增加一个按键, 不检查冲突
+    pub fn bind(&mut self, seat: usize, operate: PlayerOperate, key: KeyCode) {
+        if self.input_maps.len() <= seat {
+            self.input_maps.resize_with(seat + 1, Default::default);
+        }
+        self.input_maps[seat].insert(key, operate);
+    }
+
+    // 解除按键的绑定, 用于先让出冲突的按键再改绑
+    pub fn unbind(&mut self, key: KeyCode) {
+        self.input_maps.iter_mut().for_each(|input_map| {
+            input_map.remove(&key);
+        });
+    }
+}
+
+// 第 1 个座位起的本地玩家输入, 第 0 个座位仍然使用 Input<PlayerOperate>
+#[derive(Default)]
+pub struct SeatInputs {
+    seats: Vec<Input<PlayerOperate>>,
+}
+
+impl SeatInputs {
+    // seat 为 0 时返回 None, 调用方应该读取 Input<PlayerOperate>
+    pub fn get(&self, seat: usize) -> Option<&Input<PlayerOperate>> {
+        seat.checked_sub(1).and_then(|index| self.seats.get(index))
+    }
+
+    pub fn get_mut(&mut self, seat: usize) -> Option<&mut Input<PlayerOperate>> {
+        seat.checked_sub(1)
+            .and_then(move |index| self.seats.get_mut(index))
+    }
+}
+
+// 按下操作的输入来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InputSource {
+    Keyboard,
+    Gamepad,
+    Action, // 组合键、长按和双击
+}
+
+// 每个座位各输入来源按住的操作. 同一个操作可能被多个来源按住, 全部松开后才松开
+#[derive(Default)]
+pub struct HeldOperates {
+    held: HashMap<(usize, InputSource), HashSet<PlayerOperate>>,
+}
+
+impl HeldOperates {
+    // 座位上所有来源按住的操作
+    pub fn of_seat(&self, seat: usize) -> HashSet<PlayerOperate> {
+        self.held
+            .iter()
+            .filter(|((s, _), _)| *s == seat)
+            .flat_map(|(_, ops)| ops.iter().cloned())
+            .collect()
+    }
+
+    // source 按住了操作的座位
+    pub fn seats_of(&self, source: InputSource) -> impl Iterator<Item = usize> + '_ {
+        self.held
+            .keys()
+            .filter(move |(_, s)| *s == source)
+            .map(|(seat, _)| *seat)
+    }
+
+    // 更新 source 在座位上按住的操作, 只按下/松开合并所有来源后有变化的操作
+    pub(crate) fn update(
+        &mut self,
+        seat: usize,
+        source: InputSource,
+        operates: HashSet<PlayerOperate>,
+        input: &mut Input<PlayerOperate>,
+    ) {
+        let before = self.of_seat(seat);
+        if operates.is_empty() {
+            self.held.remove(&(seat, source));
+        } else {
+            self.held.insert((seat, source), operates);
         }
+        let after = self.of_seat(seat);
+        before.difference(&after).for_each(|op| input.release(*op));
+        after.difference(&before).for_each(|op| input.press(*op));
     }
 }
 
@@ -45,14 +353,196 @@ fn input_ext_update_system(
     settings: Res<PlayerInputSettings>,
     input_key: Res<Input<KeyCode>>,
     mut player_op: ResMut<Input<PlayerOperate>>,
+    mut seat_inputs: ResMut<SeatInputs>,
+    mut held_operates: ResMut<HeldOperates>,
 ) {
-    input_key
-        .get_just_pressed()
-        .filter_map(|k| settings.input_map.get(k))
-        .for_each(|op| player_op.press(*op));
-
-    input_key
-        .get_just_released()
-        .filter_map(|k| settings.input_map.get(k))
-        .for_each(|op| player_op.release(*op));
+    let extra_seats = settings.seat_num().saturating_sub(1);
+    seat_inputs.seats.resize_with(extra_seats, Default::default);
+    settings
+        .input_maps
+        .iter()
+        .enumerate()
+        .for_each(|(seat, input_map)| {
+            let operates = input_key
+                .get_pressed()
+                .filter_map(|k| input_map.get(k))
+                .cloned()
+                .collect();
+            if let Some(input) = seat_input_mut(seat, &mut player_op, &mut seat_inputs) {
+                held_operates.update(seat, InputSource::Keyboard, operates, input);
+            }
+        });
+}
+
+// 第 seat 个座位的操作输入
+pub(crate) fn seat_input_mut<'a>(
+    seat: usize,
+    player_op: &'a mut Input<PlayerOperate>,
+    seat_inputs: &'a mut SeatInputs,
+) -> Option<&'a mut Input<PlayerOperate>> {
+    match seat {
+        0 => Some(player_op),
+        _ => seat_inputs.get_mut(seat),
+    }
+}
+
+// 手柄按键映射, 左摇杆控制移动
+pub struct GamepadInputSettings {
+    button_map: HashMap<GamepadButtonType, PlayerOperate>,
+    // 摇杆超过该值时同时按下对应方向的操作, 网络同步只传输按下的操作
+    stick_press_threshold: f32,
+}
+
+impl Default for GamepadInputSettings {
+    fn default() -> Self {
+        GamepadInputSettings {
+            button_map: [
+                (GamepadButtonType::DPadUp, PlayerOperate::MoveFrond),
+                (GamepadButtonType::DPadDown, PlayerOperate::MoveBack),
+                (GamepadButtonType::DPadLeft, PlayerOperate::MoveLeft),
+                (GamepadButtonType::DPadRight, PlayerOperate::MoveRight),
+                (GamepadButtonType::RightTrigger, PlayerOperate::Sprint),
+                (GamepadButtonType::South, PlayerOperate::Dash),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+            stick_press_threshold: 0.5,
+        }
+    }
+}
+
+impl GamepadInputSettings {
+    fn stick(&self, axes: &Axis<GamepadAxis>, gamepad: Gamepad) -> Vec2 {
+        let axis = |axis_type| axes.get(GamepadAxis(gamepad, axis_type)).unwrap_or(0.0);
+        let stick = Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        if stick.length_squared() > 1.0 {
+            stick.normalize()
+        } else {
+            stick
+        }
+    }
+
+    fn stick_operates(&self, stick: Vec2) -> Vec<PlayerOperate> {
+        let threshold = self.stick_press_threshold;
+        [
+            (stick.y > threshold, PlayerOperate::MoveFrond),
+            (stick.y < -threshold, PlayerOperate::MoveBack),
+            (stick.x < -threshold, PlayerOperate::MoveLeft),
+            (stick.x > threshold, PlayerOperate::MoveRight),
+        ]
+        .iter()
+        .filter(|(pressed, _)| *pressed)
+        .map(|(_, op)| *op)
+        .collect()
+    }
+}
+
+// 手柄按连接顺序分配到空闲的本地座位, 拔出后座位空出来给下一个手柄
+#[derive(Default)]
+pub struct GamepadSeats {
+    seats: HashMap<Gamepad, usize>,
+}
+
+impl GamepadSeats {
+    pub fn seat_of(&self, gamepad: Gamepad) -> Option<usize> {
+        self.seats.get(&gamepad).cloned()
+    }
+
+    pub fn gamepad_of(&self, seat: usize) -> Option<Gamepad> {
+        self.seats
+            .iter()
+            .find(|(_, s)| **s == seat)
+            .map(|(gamepad, _)| *gamepad)
+    }
+}
+
+// 每个本地座位的摇杆方向
+#[derive(Default)]
+pub struct AnalogInputs {
+    seats: Vec<Vec2>,
+}
+
+impl AnalogInputs {
+    pub fn get(&self, seat: usize) -> Vec2 {
+        self.seats.get(seat).cloned().unwrap_or(Vec2::ZERO)
+    }
+
+    fn set(&mut self, seat: usize, analog: Vec2) {
+        if self.seats.len() <= seat {
+            self.seats.resize(seat + 1, Vec2::ZERO);
+        }
+        self.seats[seat] = analog;
+    }
+}
+
+fn gamepad_connection_system(
+    settings: Res<PlayerInputSettings>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut gamepad_seats: ResMut<GamepadSeats>,
+    mut player_op: ResMut<Input<PlayerOperate>>,
+    mut seat_inputs: ResMut<SeatInputs>,
+    mut held_operates: ResMut<HeldOperates>,
+    mut analog_inputs: ResMut<AnalogInputs>,
+) {
+    for GamepadEvent(gamepad, event) in gamepad_events.iter() {
+        match event {
+            GamepadEventType::Connected => {
+                if gamepad_seats.seat_of(*gamepad).is_some() {
+                    continue;
+                }
+                match (0..settings.seat_num())
+                    .find(|seat| gamepad_seats.gamepad_of(*seat).is_none())
+                {
+                    Some(seat) => {
+                        gamepad_seats.seats.insert(*gamepad, seat);
+                        info!("gamepad {} connected, control seat {}", gamepad.0, seat);
+                    }
+                    None => warn!("gamepad {} connected, no free seat", gamepad.0),
+                }
+            }
+            GamepadEventType::Disconnected => {
+                if let Some(seat) = gamepad_seats.seats.remove(gamepad) {
+                    if let Some(input) = seat_input_mut(seat, &mut player_op, &mut seat_inputs) {
+                        held_operates.update(seat, InputSource::Gamepad, HashSet::default(), input);
+                    }
+                    analog_inputs.set(seat, Vec2::ZERO);
+                    info!("gamepad {} disconnected, seat {} free", gamepad.0, seat);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// 把手柄按键和摇杆写入所在座位的输入, 键盘仍按住的操作不会被手柄松开
+#[allow(clippy::too_many_arguments)]
+fn gamepad_input_system(
+    settings: Res<GamepadInputSettings>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepad_seats: Res<GamepadSeats>,
+    mut player_op: ResMut<Input<PlayerOperate>>,
+    mut seat_inputs: ResMut<SeatInputs>,
+    mut held_operates: ResMut<HeldOperates>,
+    mut analog_inputs: ResMut<AnalogInputs>,
+) {
+    gamepad_seats.seats.iter().for_each(|(gamepad, seat)| {
+        let stick = settings.stick(&axes, *gamepad);
+        let mut operates: HashSet<PlayerOperate> = settings
+            .button_map
+            .iter()
+            .filter(|(button, _)| buttons.pressed(GamepadButton(*gamepad, **button)))
+            .map(|(_, op)| *op)
+            .collect();
+        operates.extend(settings.stick_operates(stick));
+
+        if let Some(input) = seat_input_mut(*seat, &mut player_op, &mut seat_inputs) {
+            held_operates.update(*seat, InputSource::Gamepad, operates, input);
+        }
+        analog_inputs.set(*seat, stick);
+    });
 }
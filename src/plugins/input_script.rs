@@ -0,0 +1,268 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::app::{AppExit, Events};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::game::{GameRng, GameSeed, ReadyRequestEvent, SimulationClock};
+use super::input_ext::{seat_input_mut, PlayerInputSettings, PlayerOperate, SeatInputs};
+
+// 录制本地输入到文件: --record-input <file>
+pub const RECORD_INPUT_ARG: &str = "--record-input";
+// 用输入脚本代替键盘: --input-script <file>
+pub const INPUT_SCRIPT_ARG: &str = "--input-script";
+
+// 每帧的本地输入, 配合种子可以在无窗口的测试里重现一局比赛
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputScript {
+    pub seed: u64,
+    pub frames: Vec<ScriptFrame>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScriptFrame {
+    pub ticks: u32,                     // 本帧模拟的 tick 数
+    pub seats: Vec<Vec<PlayerOperate>>, // 每个座位按下的操作
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<bool>, // 本帧发出的准备请求
+}
+
+impl InputScript {
+    pub fn new(seed: u64) -> Self {
+        InputScript {
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    // 和上一帧输入相同且没有准备请求时合并到上一帧, 每个 tick 的输入不变
+    pub fn push(&mut self, frame: ScriptFrame) {
+        match self.frames.last_mut() {
+            Some(last) if frame.ready.is_none() && last.seats == frame.seats => {
+                last.ticks += frame.ticks
+            }
+            _ => self.frames.push(frame),
+        }
+    }
+
+    pub fn tick_num(&self) -> u32 {
+        self.frames.iter().map(|frame| frame.ticks).sum()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, InputScriptError> {
+        let text = fs::read_to_string(path).map_err(InputScriptError::Io)?;
+        ron::from_str(&text).map_err(InputScriptError::Parse)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), InputScriptError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(InputScriptError::Io)?;
+        }
+        // 每帧一行
+        let config = ron::ser::PrettyConfig::new().with_depth_limit(2);
+        let text = ron::ser::to_string_pretty(self, config).map_err(InputScriptError::Parse)?;
+        fs::write(path, text).map_err(InputScriptError::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum InputScriptError {
+    Io(io::Error),
+    Parse(ron::Error),
+}
+
+impl fmt::Display for InputScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputScriptError::Io(e) => write!(f, "input script io error: {}", e),
+            InputScriptError::Parse(e) => write!(f, "malformed input script: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InputScriptError {}
+
+// 每帧结束时记录本地各座位的输入, 程序退出时写入文件
+pub struct InputScriptRecordPlugin;
+
+impl Plugin for InputScriptRecordPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<InputScriptRecorder>()
+            .add_startup_system(input_script_start_system.system())
+            .add_system_to_stage(CoreStage::PostUpdate, input_script_record_system.system())
+            .add_system_to_stage(CoreStage::Last, input_script_exit_save_system.system());
+    }
+}
+
+#[derive(Default)]
+pub struct InputScriptRecorder {
+    path: Option<PathBuf>, // None 时只保存在内存中
+    script: InputScript,
+    last_tick: u32,
+}
+
+impl InputScriptRecorder {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        InputScriptRecorder {
+            path: Some(path.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn script(&self) -> &InputScript {
+        &self.script
+    }
+
+    pub fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        match self.script.save(path) {
+            Ok(()) => info!(
+                "input script saved to {}, {} ticks",
+                path.display(),
+                self.script.tick_num()
+            ),
+            Err(e) => error!("save input script to {} fail: {}", path.display(), e),
+        }
+    }
+}
+
+fn input_script_start_system(seed: Res<GameSeed>, mut recorder: ResMut<InputScriptRecorder>) {
+    recorder.script.seed = seed.0;
+}
+
+fn input_script_record_system(
+    clock: Res<SimulationClock>,
+    settings: Res<PlayerInputSettings>,
+    input: Res<Input<PlayerOperate>>,
+    seat_inputs: Res<SeatInputs>,
+    mut ready_events: EventReader<ReadyRequestEvent>,
+    mut recorder: ResMut<InputScriptRecorder>,
+) {
+    let seats = (0..settings.seat_num().max(1))
+        .map(|seat| {
+            let seat_input = match seat {
+                0 => Some(&*input),
+                seat => seat_inputs.get(seat),
+            };
+            let mut pressed: Vec<_> = seat_input
+                .iter()
+                .flat_map(|input| input.get_pressed().cloned())
+                .collect();
+            pressed.sort();
+            pressed
+        })
+        .collect();
+    let ticks = clock.tick().saturating_sub(recorder.last_tick);
+    recorder.last_tick = clock.tick();
+    recorder.script.push(ScriptFrame {
+        ticks,
+        seats,
+        ready: ready_events.iter().last().map(|event| event.ready),
+    });
+}
+
+fn input_script_exit_save_system(
+    mut exit_events: EventReader<AppExit>,
+    recorder: Res<InputScriptRecorder>,
+) {
+    if exit_events.iter().next().is_some() {
+        recorder.save();
+    }
+}
+
+// 按脚本逐帧设置本地输入并推进模拟, 覆盖键盘和手柄的输入. 需要先插入 InputScriptPlayback 资源
+pub struct InputScriptPlaybackPlugin;
+
+impl Plugin for InputScriptPlaybackPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_startup_system(input_script_playback_start_system.exclusive_system())
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                input_script_playback_system.exclusive_system().at_end(),
+            );
+    }
+}
+
+pub struct InputScriptPlayback {
+    script: InputScript,
+    frame: usize,
+}
+
+impl InputScriptPlayback {
+    pub fn new(script: InputScript) -> Self {
+        InputScriptPlayback { script, frame: 0 }
+    }
+
+    pub fn script(&self) -> &InputScript {
+        &self.script
+    }
+
+    // 所有帧都已经播放
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.script.frames.len()
+    }
+}
+
+// 用脚本的种子, 模拟只由脚本推进
+fn input_script_playback_start_system(world: &mut World) {
+    let seed = world
+        .get_resource::<InputScriptPlayback>()
+        .expect("insert InputScriptPlayback before InputScriptPlaybackPlugin")
+        .script
+        .seed;
+    world.insert_resource(GameSeed(seed));
+    world.insert_resource(GameRng::from_seed(seed));
+    world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+}
+
+fn input_script_playback_system(world: &mut World) {
+    let world = world.cell();
+    let mut playback = world.get_resource_mut::<InputScriptPlayback>().unwrap();
+    let mut player_op = world.get_resource_mut::<Input<PlayerOperate>>().unwrap();
+    let mut seat_inputs = world.get_resource_mut::<SeatInputs>().unwrap();
+    let settings = world.get_resource::<PlayerInputSettings>().unwrap();
+
+    let frame = playback.script.frames.get(playback.frame).cloned();
+    if frame.is_some() {
+        playback.frame += 1;
+        if playback.is_finished() {
+            info!("input script finished");
+        }
+    }
+    let frame = frame.unwrap_or_default();
+    for seat in 0..settings.seat_num().max(1) {
+        let pressed = frame.seats.get(seat).cloned().unwrap_or_default();
+        if let Some(input) = seat_input_mut(seat, &mut player_op, &mut seat_inputs) {
+            let released: Vec<_> = input
+                .get_pressed()
+                .filter(|op| !pressed.contains(op))
+                .cloned()
+                .collect();
+            released.into_iter().for_each(|op| input.release(op));
+            let newly: Vec<_> = pressed
+                .into_iter()
+                .filter(|op| !input.pressed(*op))
+                .collect();
+            newly.into_iter().for_each(|op| input.press(op));
+        }
+    }
+    if let Some(ready) = frame.ready {
+        world
+            .get_resource_mut::<Events<ReadyRequestEvent>>()
+            .unwrap()
+            .send(ReadyRequestEvent { ready });
+    }
+    world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .fast_forward(frame.ticks);
+}
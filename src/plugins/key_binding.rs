@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::input_ext::{InputExtSystem, KeyConflict, PlayerInputSettings, PlayerOperate};
+
+// 用户配置目录下保存按键绑定的位置
+const CONFIG_DIR_NAME: &str = "test_bevy_game";
+const BINDINGS_FILE_NAME: &str = "bindings.ron";
+
+// 等待新按键时按下该键取消改绑
+pub const REBIND_CANCEL_KEY: KeyCode = KeyCode::Escape;
+
+// 运行时改绑按键, 改绑结果保存到用户配置目录, 启动时读取
+pub struct KeyBindingPlugin;
+
+impl Plugin for KeyBindingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<KeyBindingSettings>()
+            .init_resource::<KeyCapture>()
+            .add_event::<RebindRequestEvent>()
+            .add_event::<KeyReboundEvent>()
+            // 在生成本地玩家之前读取, 座位数量不变
+            .add_startup_system_to_stage(
+                StartupStage::PreStartup,
+                load_key_bindings_system.system(),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                key_capture_system
+                    .system()
+                    .after(InputSystem)
+                    .before(InputExtSystem),
+            );
+    }
+}
+
+pub struct KeyBindingSettings {
+    pub path: Option<PathBuf>, // None 时不读取也不保存
+}
+
+impl Default for KeyBindingSettings {
+    fn default() -> Self {
+        KeyBindingSettings {
+            path: default_bindings_path(),
+        }
+    }
+}
+
+// 例如 Linux 上是 ~/.config/test_bevy_game/bindings.ron
+pub fn default_bindings_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME).join(BINDINGS_FILE_NAME))
+}
+
+// 按键绑定文件内容, 每个座位一张 操作 -> 按键 表
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    pub seats: Vec<BTreeMap<PlayerOperate, Vec<KeyCode>>>,
+}
+
+impl From<&PlayerInputSettings> for KeyBindings {
+    fn from(settings: &PlayerInputSettings) -> Self {
+        let seats = (0..settings.seat_num())
+            .map(|seat| {
+                let mut keys = BTreeMap::<PlayerOperate, Vec<KeyCode>>::new();
+                settings
+                    .bindings(seat)
+                    .into_iter()
+                    .for_each(|(key, op)| keys.entry(op).or_default().push(key));
+                keys
+            })
+            .collect();
+        KeyBindings { seats }
+    }
+}
+
+impl KeyBindings {
+    // 转换为输入设置, 同一个按键出现多次时返回冲突
+    pub fn to_settings(&self) -> Result<PlayerInputSettings, KeyConflict> {
+        let seats: Vec<Vec<(KeyCode, PlayerOperate)>> = self
+            .seats
+            .iter()
+            .map(|keys| {
+                keys.iter()
+                    .flat_map(|(op, keys)| keys.iter().map(move |key| (*key, *op)))
+                    .collect()
+            })
+            .collect();
+        let mut settings = PlayerInputSettings::from_seats(&[]);
+        for (seat, bindings) in seats.iter().enumerate() {
+            for (key, op) in bindings {
+                if let Some((other_seat, other_op)) = settings.binding_of(*key) {
+                    return Err(KeyConflict {
+                        key: *key,
+                        seat: other_seat,
+                        operate: other_op,
+                    });
+                }
+                settings.bind(seat, *op, *key);
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, KeyBindingsError> {
+        let text = fs::read_to_string(path).map_err(KeyBindingsError::Io)?;
+        ron::from_str(&text).map_err(KeyBindingsError::Parse)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), KeyBindingsError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(KeyBindingsError::Io)?;
+        }
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(KeyBindingsError::Parse)?;
+        fs::write(path, text).map_err(KeyBindingsError::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyBindingsError {
+    Io(io::Error),
+    Parse(ron::Error),
+    Conflict(KeyConflict),
+}
+
+impl fmt::Display for KeyBindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyBindingsError::Io(e) => write!(f, "key bindings io error: {}", e),
+            KeyBindingsError::Parse(e) => write!(f, "malformed key bindings: {}", e),
+            KeyBindingsError::Conflict(e) => write!(f, "key bindings conflict: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KeyBindingsError {}
+
+// 请求把座位上的操作改绑到下一个按下的按键
+pub struct RebindRequestEvent {
+    pub seat: usize,
+    pub operate: PlayerOperate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebindResult {
+    Bound(KeyCode),
+    Conflict(KeyConflict),
+    Cancelled,
+}
+
+pub struct KeyReboundEvent {
+    pub seat: usize,
+    pub operate: PlayerOperate,
+    pub result: RebindResult,
+}
+
+// 正在等待新按键的改绑请求
+#[derive(Default)]
+pub struct KeyCapture {
+    pending: Option<(usize, PlayerOperate)>,
+}
+
+impl KeyCapture {
+    pub fn is_capturing(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+fn load_key_bindings_system(
+    binding_settings: Res<KeyBindingSettings>,
+    mut input_settings: ResMut<PlayerInputSettings>,
+) {
+    let path = match &binding_settings.path {
+        Some(path) if path.exists() => path,
+        _ => return,
+    };
+    let loaded = KeyBindings::load(path)
+        .and_then(|bindings| bindings.to_settings().map_err(KeyBindingsError::Conflict));
+    match loaded {
+        Ok(mut settings) => {
+            // 座位数量由是否分屏决定: 文件中多出的座位忽略, 缺少的座位保留原来不冲突的按键
+            let missing = settings.seat_num()..input_settings.seat_num();
+            settings.set_seat_num(input_settings.seat_num());
+            for seat in missing {
+                for (key, op) in input_settings.bindings(seat) {
+                    if settings.binding_of(key).is_none() {
+                        settings.bind(seat, op, key);
+                    }
+                }
+            }
+            *input_settings = settings;
+            info!("load key bindings from {}", path.display());
+        }
+        Err(e) => warn!("{}, use default key bindings", e),
+    }
+}
+
+fn key_capture_system(
+    keyboard: Res<Input<KeyCode>>,
+    binding_settings: Res<KeyBindingSettings>,
+    mut requests: EventReader<RebindRequestEvent>,
+    mut capture: ResMut<KeyCapture>,
+    mut input_settings: ResMut<PlayerInputSettings>,
+    mut rebound_events: EventWriter<KeyReboundEvent>,
+) {
+    if let Some(RebindRequestEvent { seat, operate }) = requests.iter().last() {
+        capture.pending = Some((*seat, *operate));
+        info!(
+            "press a key for {:?} of seat {}, {:?} to cancel",
+            operate, seat, REBIND_CANCEL_KEY
+        );
+    }
+    let (seat, operate, key) = match (capture.pending, keyboard.get_just_pressed().next()) {
+        (Some((seat, operate)), Some(key)) => (seat, operate, *key),
+        _ => return,
+    };
+    capture.pending = None;
+
+    let result = if key == REBIND_CANCEL_KEY {
+        RebindResult::Cancelled
+    } else {
+        match input_settings.rebind(seat, operate, key) {
+            Ok(()) => {
+                if let Some(path) = &binding_settings.path {
+                    match KeyBindings::from(&*input_settings).save(path) {
+                        Ok(()) => info!("key bindings saved to {}", path.display()),
+                        Err(e) => error!("save key bindings fail: {}", e),
+                    }
+                }
+                RebindResult::Bound(key)
+            }
+            Err(conflict) => {
+                warn!("{}", conflict);
+                RebindResult::Conflict(conflict)
+            }
+        }
+    };
+    rebound_events.send(KeyReboundEvent {
+        seat,
+        operate,
+        result,
+    });
+}
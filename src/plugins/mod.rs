@@ -1,8 +1,18 @@
-mod coin;
-mod elapsed_time;
-mod game;
-mod input_ext;
-mod player;
-mod ui;
+pub mod action_input;
+pub mod cli;
+pub mod coin;
+pub mod elapsed_time;
+pub mod game;
+pub mod game_mode;
+pub mod input_ext;
+pub mod input_script;
+pub mod key_binding;
+pub mod net;
+pub mod player;
+pub mod presentation;
+pub mod replay;
+pub mod rules;
+pub mod spectator;
+pub mod ui;
 
 pub use game::TestNetGamePlugins;
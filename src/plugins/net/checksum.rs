@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use bevy::prelude::*;
+
+use super::super::coin::{Coin, CoinInfo};
+use super::super::game::{GameStage, GameSystem, MatchState, SimulationClock};
+use super::super::player::{Player, PlayerInfo, PlayerSystem, Score, Team};
+
+// 保留最近多少个 tick 的状态, 用于和较慢的节点比较
+const CHECKSUM_HISTORY: u32 = 600;
+
+// 每个 tick 计算玩法状态的校验值, 和其它节点比较发现不同步
+pub struct ChecksumPlugin;
+
+impl Plugin for ChecksumPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<DesyncDetectedEvent>()
+            .init_resource::<WorldChecksums>()
+            .add_system_to_stage(
+                GameStage::Simulation,
+                checksum_update_system
+                    .system()
+                    .after(PlayerSystem::PlayerScore)
+                    .after(GameSystem::MatchStateUpdate),
+            )
+            .add_system_to_stage(CoreStage::Last, checksum_verify_system.system());
+    }
+}
+
+// 本地和 peer 节点在 tick 时的状态不一致
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesyncDetectedEvent {
+    pub tick: u32,
+    pub peer: usize,
+    pub local: u64,
+    pub remote: u64,
+}
+
+#[derive(Default)]
+pub struct WorldChecksums {
+    history: BTreeMap<u32, (u64, String)>, // tick -> (校验值, 状态文本)
+    outgoing: BTreeSet<u32>,               // 等待发送校验值的 tick
+    confirmed_tick: u32,                   // 该 tick 及之前的状态不会再被回滚修改
+    outgoing_dumps: Vec<u32>,              // 发现不同步后需要发给对方的状态文本
+    remote: Vec<(usize, u32, u64)>,        // 收到的其它节点的校验值, 等本地算出后比较
+    desynced: HashSet<usize>,              // 已经报告过不同步的节点, 只报告第一次
+}
+
+impl WorldChecksums {
+    pub fn checksum(&self, tick: u32) -> Option<u64> {
+        self.history.get(&tick).map(|(hash, _)| *hash)
+    }
+
+    // tick 时的状态文本, 每行一个实体, 可以直接 diff
+    pub fn dump(&self, tick: u32) -> Option<&str> {
+        self.history.get(&tick).map(|(_, dump)| dump.as_str())
+    }
+
+    pub fn is_desynced(&self, peer: usize) -> bool {
+        self.desynced.contains(&peer)
+    }
+
+    // 之后只发送和比较 tick 及之前的校验值, 预测模拟出的状态可能还会被回滚修改
+    pub fn confirm(&mut self, tick: u32) {
+        self.confirmed_tick = self.confirmed_tick.max(tick);
+    }
+
+    pub fn take_outgoing(&mut self) -> Vec<(u32, u64)> {
+        let pending = self.outgoing.split_off(&(self.confirmed_tick + 1));
+        let confirmed = std::mem::replace(&mut self.outgoing, pending);
+        confirmed
+            .into_iter()
+            .filter_map(|tick| Some((tick, self.checksum(tick)?)))
+            .collect()
+    }
+
+    pub fn take_outgoing_dumps(&mut self) -> Vec<(u32, String)> {
+        let ticks = std::mem::take(&mut self.outgoing_dumps);
+        ticks
+            .into_iter()
+            .filter_map(|tick| Some((tick, self.dump(tick)?.to_string())))
+            .collect()
+    }
+
+    pub fn receive(&mut self, peer: usize, tick: u32, hash: u64) {
+        self.remote.push((peer, tick, hash));
+    }
+
+    // 记录 peer 发来的状态文本, 和本地同一 tick 的状态对比输出
+    pub fn report_remote_dump(&self, peer: usize, tick: u32, remote: &str) {
+        match self.dump(tick) {
+            Some(local) => error!(
+                "desync with peer {} at tick {}:\n{}",
+                peer,
+                tick,
+                dump_diff(local, remote)
+            ),
+            None => warn!("state of tick {} is too old to compare", tick),
+        }
+    }
+
+    fn record(&mut self, tick: u32, dump: String) {
+        let hash = fnv1a(dump.as_bytes());
+        self.history.insert(tick, (hash, dump));
+        self.outgoing.insert(tick);
+        if let Some(oldest) = tick.checked_sub(CHECKSUM_HISTORY) {
+            self.history = self.history.split_off(&oldest);
+        }
+    }
+}
+
+// 逐行比较两份状态文本, 只在一边出现的行分别以 - 和 + 开头
+pub fn dump_diff(local: &str, remote: &str) -> String {
+    let local_lines: HashSet<_> = local.lines().collect();
+    let remote_lines: HashSet<_> = remote.lines().collect();
+    let mut diff = String::new();
+    local.lines().for_each(|line| {
+        let mark = if remote_lines.contains(line) {
+            ' '
+        } else {
+            '-'
+        };
+        diff.push_str(&format!("{} {}\n", mark, line));
+    });
+    remote
+        .lines()
+        .filter(|line| !local_lines.contains(line))
+        .for_each(|line| diff.push_str(&format!("+ {}\n", line)));
+    diff
+}
+
+// 和平台无关的 64 位 FNV-1a, 不能用 std 的 DefaultHasher (每个进程随机)
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// 状态文本按行排序, 和实体的遍历顺序无关
+fn checksum_update_system(
+    clock: Res<SimulationClock>,
+    state: Res<State<MatchState>>,
+    mut checksums: ResMut<WorldChecksums>,
+    players: Query<(&PlayerInfo, &Team, &Score, &Transform), With<Player>>,
+    coins: Query<(&CoinInfo, &Transform), With<Coin>>,
+) {
+    let mut lines: Vec<_> = players
+        .iter()
+        .map(|(info, team, score, transform)| {
+            format!(
+                "player {:?} team={} score={} pos={:?}",
+                info.name, team.id, score.val, transform.translation
+            )
+        })
+        .chain(coins.iter().map(|(info, transform)| {
+            format!(
+                "coin value={} pos={:?}",
+                info.score_value, transform.translation
+            )
+        }))
+        .collect();
+    lines.sort();
+    let dump = format!(
+        "tick {}\nmatch_state {:?}\n{}",
+        clock.tick(),
+        state.current(),
+        lines.join("\n")
+    );
+    checksums.record(clock.tick(), dump);
+}
+
+fn checksum_verify_system(
+    mut checksums: ResMut<WorldChecksums>,
+    mut desync_events: EventWriter<DesyncDetectedEvent>,
+) {
+    let remote = std::mem::take(&mut checksums.remote);
+    let pending = remote
+        .into_iter()
+        .filter(|(peer, tick, remote)| {
+            // 本地还没有用确认的输入模拟到该 tick, 之后再比较
+            if *tick > checksums.confirmed_tick {
+                return true;
+            }
+            match checksums.checksum(*tick) {
+                Some(local) if local != *remote && !checksums.is_desynced(*peer) => {
+                    error!(
+                        "desync with peer {} at tick {}: local {:016x}, remote {:016x}",
+                        peer, tick, local, remote
+                    );
+                    desync_events.send(DesyncDetectedEvent {
+                        tick: *tick,
+                        peer: *peer,
+                        local,
+                        remote: *remote,
+                    });
+                    checksums.desynced.insert(*peer);
+                    checksums.outgoing_dumps.push(*tick);
+                    false
+                }
+                _ => false,
+            }
+        })
+        .collect();
+    checksums.remote = pending;
+}
@@ -0,0 +1,529 @@
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use super::super::coin::{spawn_coin, CoinInfo, CoinPickedupEvent};
+use super::super::elapsed_time::ElapsedSecondChangedEvent;
+use super::super::game::{GameRules, GameState, MatchAuthority, MatchState, ReadyRequestEvent};
+use super::super::player::{
+    spawn_player, LocalPlayer, PlayerInfo, PlayerSpawn, Ready, Score, Team, TeamScoreChangedEvent,
+};
+use super::conditions::NetConditions;
+use super::interpolation::{client_interpolation_system, InterpolationSettings, SnapshotBuffer};
+use super::prediction::{client_predict_system, client_reconcile_system, InputBuffer};
+use super::protocol::{
+    ClientMessage, ClientRole, Codec, CodecError, CoinSnapshot, DisconnectReason, InputFrame,
+    PlayerSnapshot, RejectReason, ServerMessage, PROTOCOL_VERSION,
+};
+use super::transport::{bind_socket_with, KcpClock, KcpSession, NetSocket, MAX_PACKET_SIZE};
+use super::NetId;
+
+pub struct NetClientPlugin;
+
+impl Plugin for NetClientPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // 比赛流程, 得分, 金币, 计时都以服务器为准
+        app.insert_resource(MatchAuthority(false))
+            .init_resource::<NetClient>()
+            .add_event::<TeamScoreChangedEvent>()
+            .add_event::<ElapsedSecondChangedEvent>()
+            .add_event::<CoinPickedupEvent>()
+            .init_resource::<InputBuffer>()
+            .init_resource::<InterpolationSettings>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                client_receive_system
+                    .system()
+                    .label(NetClientSystem::Receive),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                client_reconcile_system
+                    .system()
+                    .after(NetClientSystem::Receive),
+            )
+            .add_system(client_predict_system.system())
+            .add_system(client_interpolation_system.system())
+            .add_system(client_ready_system.system())
+            .add_system_to_stage(CoreStage::Last, client_flush_system.system());
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+enum NetClientSystem {
+    Receive,
+}
+
+pub struct NetClientSettings {
+    pub server_addr: SocketAddr,
+    pub player_name: String,
+    pub session_token: Option<u64>, // 带上之前的会话令牌可以找回掉线前的玩家
+    pub reconnect_seconds: f32,     // 超过该时间没有收到服务器数据则用会话令牌重连
+    pub role: ClientRole,
+}
+
+impl Default for NetClientSettings {
+    fn default() -> Self {
+        NetClientSettings {
+            server_addr: ([127, 0, 0, 1], 7878).into(),
+            player_name: "Player".to_string(),
+            session_token: None,
+            reconnect_seconds: 3.0,
+            role: ClientRole::Player,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Rejected(RejectReason),
+    Disconnected(DisconnectReason),
+}
+
+pub struct NetClient {
+    socket: Arc<NetSocket>,
+    session: KcpSession,
+    clock: KcpClock,
+    codec: Codec,
+    state: ConnectionState,
+    player_id: Option<u32>,
+    player_name: String,
+    session_token: Option<u64>,
+    role: ClientRole,
+    mirrors: HashMap<u32, Entity>,     // 服务器 NetId -> 本地镜像实体
+    input_ack: Option<(u32, Vec2)>,    // 最新的输入确认序号和本地玩家的权威位置
+    latest_tick: Option<(u32, f64)>,   // 最新收到的快照 tick 和收到的本地时间
+    samples: Vec<(Entity, u32, Vec3)>, // 等待写入 SnapshotBuffer 的远程实体位置
+    send_queue: Vec<ClientMessage>,
+}
+
+impl FromWorld for NetClient {
+    fn from_world(world: &mut World) -> Self {
+        let conditions = world
+            .get_resource_or_insert_with(NetConditions::default)
+            .clone();
+        let settings = world.get_resource_or_insert_with(NetClientSettings::default);
+        let socket =
+            bind_socket_with(("0.0.0.0", 0), &conditions).expect("bind client socket fail!");
+        // conv 为 0 的包会被当作非法包, 这里保证非 0
+        let conv = rand::random::<u32>().max(1);
+        let session = KcpSession::new(conv, socket.clone(), settings.server_addr);
+        info!(
+            "connect to {} as {}",
+            settings.server_addr, settings.player_name
+        );
+        let handshake = ClientMessage::Handshake {
+            version: PROTOCOL_VERSION,
+            name: settings.player_name.clone(),
+            session_token: settings.session_token,
+            role: settings.role,
+        };
+        NetClient {
+            socket,
+            session,
+            clock: Default::default(),
+            codec: Default::default(),
+            state: ConnectionState::Connecting,
+            player_id: None,
+            player_name: settings.player_name.clone(),
+            session_token: settings.session_token,
+            role: settings.role,
+            mirrors: Default::default(),
+            input_ack: None,
+            latest_tick: None,
+            samples: Vec::new(),
+            send_queue: vec![handshake],
+        }
+    }
+}
+
+impl NetClient {
+    // 服务器分配给本地玩家的 NetId, 加入成功前为 None
+    pub fn player_id(&self) -> Option<u32> {
+        self.player_id
+    }
+
+    pub fn connection_state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    // 观战者没有本地玩家, 也不发送输入
+    pub fn is_spectator(&self) -> bool {
+        self.role == ClientRole::Spectator
+    }
+
+    // 服务器分配的会话令牌, 重连时用来找回原来的玩家
+    pub fn session_token(&self) -> Option<u64> {
+        self.session_token
+    }
+
+    // 换一个新的 KCP 会话重新握手, 有会话令牌时服务器会把原来的玩家交还给本客户端
+    pub fn reconnect(&mut self) {
+        let conv = rand::random::<u32>().max(1);
+        let peer = self.session.peer();
+        self.session = KcpSession::new(conv, self.socket.clone(), peer);
+        self.state = ConnectionState::Connecting;
+        info!("reconnect to {} as {}", peer, self.player_name);
+        let handshake = ClientMessage::Handshake {
+            version: PROTOCOL_VERSION,
+            name: self.player_name.clone(),
+            session_token: self.session_token,
+            role: self.role,
+        };
+        self.send_queue.insert(0, handshake);
+    }
+
+    pub fn queue(&mut self, message: ClientMessage) {
+        self.send_queue.push(message);
+    }
+
+    pub(super) fn connected(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    pub(super) fn send_input(&mut self, frame: InputFrame) {
+        self.queue(ClientMessage::InputFrame(frame));
+    }
+
+    pub(super) fn take_input_ack(&mut self) -> Option<(u32, Vec2)> {
+        self.input_ack.take()
+    }
+
+    pub(super) fn latest_tick(&self) -> Option<(u32, f64)> {
+        self.latest_tick
+    }
+
+    pub(super) fn take_samples(&mut self) -> Vec<(Entity, u32, Vec3)> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn receive_tick(&mut self, tick: u32, now: f64) {
+        if self.latest_tick.iter().all(|(latest, _)| tick > *latest) {
+            self.latest_tick = Some((tick, now));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn client_receive_system(
+    mut commands: Commands,
+    mut client: ResMut<NetClient>,
+    settings: Res<NetClientSettings>,
+    rules: Res<GameRules>,
+    time: Res<Time>,
+    mut state: ResMut<State<MatchState>>,
+    mut game_state: ResMut<GameState>,
+    mut team_score_event: EventWriter<TeamScoreChangedEvent>,
+    mut elapsed_event: EventWriter<ElapsedSecondChangedEvent>,
+    mut coin_event: EventWriter<CoinPickedupEvent>,
+    mut mirror_query: MirrorQuery,
+) {
+    let mut buf = [0; MAX_PACKET_SIZE];
+    loop {
+        match client.socket.recv_from(&mut buf) {
+            Ok((size, _)) => {
+                if let Err(e) = client.session.input(&buf[..size]) {
+                    warn!("invalid packet from server: {:?}", e);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("client socket recv fail: {}", e);
+                break;
+            }
+        }
+    }
+
+    // 服务器长时间没有数据时用会话令牌重连(观战者直接重连), 重连没有响应则继续重试
+    let reconnecting = matches!(
+        client.state,
+        ConnectionState::Connected | ConnectionState::Connecting
+    );
+    if reconnecting
+        && (client.session_token.is_some() || client.is_spectator())
+        && client.session.idle_time().as_secs_f32() > settings.reconnect_seconds
+    {
+        warn!("no data from server for {}s", settings.reconnect_seconds);
+        client.reconnect();
+    }
+
+    while let Some(bytes) = client.session.recv() {
+        let message = match client.codec.decode::<ServerMessage>(&bytes) {
+            Ok(message) => message,
+            Err(CodecError::VersionMismatch { expected, found }) => {
+                // 版本不一致时服务器的拒绝消息本身也无法解码, 从消息头得知服务器版本
+                let reason = RejectReason::VersionMismatch {
+                    server: found,
+                    client: expected,
+                };
+                error!("join game fail: {}", reason);
+                client.state = ConnectionState::Rejected(reason);
+                continue;
+            }
+            Err(e) => {
+                warn!("invalid message from server: {}", e);
+                continue;
+            }
+        };
+        match message {
+            ServerMessage::HandshakeAccepted {
+                player_id,
+                session_token,
+            } => {
+                info!("join game success, player id: {}", player_id);
+                client.state = ConnectionState::Connected;
+                client.player_id = Some(player_id);
+                client.session_token = Some(session_token);
+            }
+            ServerMessage::SpectatorAccepted => {
+                info!("spectate game success");
+                client.state = ConnectionState::Connected;
+            }
+            ServerMessage::HandshakeRejected(reason) => {
+                error!("join game fail: {}", reason);
+                client.state = ConnectionState::Rejected(reason);
+            }
+            ServerMessage::EntitySnapshot(snapshot) => {
+                let tick = snapshot.tick;
+                client.receive_tick(tick, time.seconds_since_startup());
+                sync_match_state(&mut state, snapshot.match_state);
+                game_state.set_result(snapshot.result.clone());
+                let alive: Vec<u32> = snapshot
+                    .players
+                    .iter()
+                    .map(|p| p.id)
+                    .chain(snapshot.coins.iter().map(|c| c.id))
+                    .collect();
+                let removed: Vec<u32> = client
+                    .mirrors
+                    .keys()
+                    .filter(|id| !alive.contains(id))
+                    .cloned()
+                    .collect();
+                removed
+                    .into_iter()
+                    .for_each(|id| despawn_mirror(&mut commands, &mut client, id));
+                snapshot.players.into_iter().for_each(|player| {
+                    upsert_player(
+                        &mut commands,
+                        &mut client,
+                        &rules,
+                        &mut mirror_query,
+                        tick,
+                        player,
+                    )
+                });
+                snapshot.coins.into_iter().for_each(|coin| {
+                    upsert_coin(
+                        &mut commands,
+                        &mut client,
+                        &rules,
+                        &mut mirror_query,
+                        tick,
+                        coin,
+                    )
+                });
+            }
+            ServerMessage::EntityDelta(delta) => {
+                let tick = delta.tick;
+                client.receive_tick(tick, time.seconds_since_startup());
+                delta.players.into_iter().for_each(|player| {
+                    upsert_player(
+                        &mut commands,
+                        &mut client,
+                        &rules,
+                        &mut mirror_query,
+                        tick,
+                        player,
+                    )
+                });
+                delta.coins.into_iter().for_each(|coin| {
+                    upsert_coin(
+                        &mut commands,
+                        &mut client,
+                        &rules,
+                        &mut mirror_query,
+                        tick,
+                        coin,
+                    )
+                });
+                delta
+                    .removed
+                    .into_iter()
+                    .for_each(|id| despawn_mirror(&mut commands, &mut client, id));
+            }
+            ServerMessage::ScoreUpdate {
+                team_id,
+                team_score,
+            } => team_score_event.send(TeamScoreChangedEvent {
+                team_id,
+                team_score,
+            }),
+            ServerMessage::CoinPickedup { coin_id } => {
+                if let Some(coin) = client.mirrors.get(&coin_id) {
+                    coin_event.send(CoinPickedupEvent { coin: *coin });
+                }
+            }
+            ServerMessage::ElapsedSeconds { seconds } => {
+                elapsed_event.send(ElapsedSecondChangedEvent { seconds })
+            }
+            ServerMessage::InputAck { seq, position } => {
+                client.input_ack = Some((seq, position));
+            }
+            ServerMessage::MatchStateChanged {
+                state: match_state,
+                result,
+            } => {
+                sync_match_state(&mut state, match_state);
+                game_state.set_result(result);
+            }
+            ServerMessage::Disconnect(reason) => {
+                warn!("disconnected by server: {:?}", reason);
+                client.state = ConnectionState::Disconnected(reason);
+                client.player_id = None;
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+type MirrorQuery<'a> = Query<
+    'a,
+    (
+        Option<&'static mut PlayerInfo>,
+        Option<&'static mut Team>,
+        Option<&'static mut Score>,
+        Option<&'static mut CoinInfo>,
+        Option<&'static mut Ready>,
+    ),
+>;
+
+fn sync_match_state(state: &mut State<MatchState>, match_state: MatchState) {
+    if *state.current() != match_state {
+        state
+            .overwrite_next(match_state)
+            .expect("sync match state fail!");
+    }
+}
+
+fn upsert_player(
+    commands: &mut Commands,
+    client: &mut NetClient,
+    rules: &GameRules,
+    mirror_query: &mut MirrorQuery,
+    tick: u32,
+    player: PlayerSnapshot,
+) {
+    let position = player.position.extend(0.0);
+    let is_local = client.player_id == Some(player.id);
+    let entity = match client.mirrors.get(&player.id) {
+        Some(entity) => *entity,
+        None => {
+            let [r, g, b, a] = player.color;
+            let spawn = PlayerSpawn {
+                name: player.name,
+                team_id: player.team_id,
+                color: Color::rgba(r, g, b, a),
+                position,
+            };
+            let entity = spawn_player(commands, rules, spawn);
+            commands
+                .entity(entity)
+                .insert_bundle((NetId(player.id), Ready(player.ready)));
+            if is_local {
+                commands.entity(entity).insert(LocalPlayer);
+            } else {
+                let mut buffer = SnapshotBuffer::default();
+                buffer.push(tick, position);
+                commands.entity(entity).insert(buffer);
+            }
+            client.mirrors.insert(player.id, entity);
+            return;
+        }
+    };
+    // 本地玩家的位置由预测和校正决定, 远程玩家在两个快照之间插值
+    if !is_local {
+        client.samples.push((entity, tick, position));
+    }
+    if let Ok((info, team, score, _, ready)) = mirror_query.get_mut(entity) {
+        if let Some(mut info) = info {
+            info.name = player.name;
+        }
+        if let Some(mut team) = team {
+            team.id = player.team_id;
+        }
+        if let Some(mut score) = score {
+            score.val = player.score;
+        }
+        if let Some(mut ready) = ready {
+            ready.0 = player.ready;
+        }
+    }
+}
+
+fn upsert_coin(
+    commands: &mut Commands,
+    client: &mut NetClient,
+    rules: &GameRules,
+    mirror_query: &mut MirrorQuery,
+    tick: u32,
+    coin: CoinSnapshot,
+) {
+    let position = coin.position.extend(0.0);
+    let entity = match client.mirrors.get(&coin.id) {
+        Some(entity) => *entity,
+        None => {
+            // 服务器按生成顺序分配 NetId
+            let entity = spawn_coin(commands, rules, coin.id as u64, coin.score_value, position);
+            let mut buffer = SnapshotBuffer::default();
+            buffer.push(tick, position);
+            commands
+                .entity(entity)
+                .insert_bundle((NetId(coin.id), buffer));
+            client.mirrors.insert(coin.id, entity);
+            return;
+        }
+    };
+    client.samples.push((entity, tick, position));
+    if let Ok((_, _, _, Some(mut info), _)) = mirror_query.get_mut(entity) {
+        info.score_value = coin.score_value;
+    }
+}
+
+fn despawn_mirror(commands: &mut Commands, client: &mut NetClient, id: u32) {
+    if let Some(entity) = client.mirrors.remove(&id) {
+        debug!("net entity {} despawn!", id);
+        commands.entity(entity).despawn();
+    }
+}
+
+// 准备状态由服务器决定, 客户端只转发请求
+fn client_ready_system(mut client: ResMut<NetClient>, mut events: EventReader<ReadyRequestEvent>) {
+    events.iter().for_each(|ReadyRequestEvent { ready }| {
+        if client.connected() && !client.is_spectator() {
+            client.queue(ClientMessage::Ready(*ready));
+        }
+    });
+}
+
+fn client_flush_system(mut client: ResMut<NetClient>) {
+    let NetClient {
+        session,
+        clock,
+        codec,
+        send_queue,
+        ..
+    } = &mut *client;
+    send_queue.drain(..).for_each(|message| {
+        if let Err(e) = session.send(&codec.encode(&message)) {
+            warn!("send to server fail: {:?}", e);
+        }
+    });
+    if let Err(e) = session.update(clock) {
+        warn!("flush to server fail: {:?}", e);
+    }
+}
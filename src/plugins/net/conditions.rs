@@ -0,0 +1,190 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+// 读取网络模拟参数的环境变量和命令行参数, 格式见 NetConditions::from_str. 命令行参数由 cli 模块读取
+pub const NET_SIM_ENV: &str = "NET_SIM";
+pub const NET_SIM_ARG: &str = "--net-sim";
+
+// 模拟的网络状况, 作用在每个 socket 发出的包上. 全部为 0 时不做任何处理
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetConditions {
+    pub latency_ms: u32,   // 单向固定延迟
+    pub jitter_ms: u32,    // 在固定延迟上随机增加 0..=jitter_ms
+    pub loss: f32,         // 丢包概率
+    pub duplicate: f32,    // 重复发送概率
+    pub reorder: f32,      // 乱序概率, 命中的包额外延迟一段时间, 落到后面的包之后到达
+    pub seed: Option<u64>, // 随机数种子, 方便测试复现
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetConditionsParseError(pub String); // 格式错误的项
+
+impl fmt::Display for NetConditionsParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid net conditions: {}", self.0)
+    }
+}
+
+impl std::error::Error for NetConditionsParseError {}
+
+// 格式: "latency=100,jitter=20,loss=0.05,dup=0.01,reorder=0.02,seed=1", 未写的项为 0
+impl FromStr for NetConditions {
+    type Err = NetConditionsParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conditions = NetConditions::default();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (key, value) = match item.find('=') {
+                Some(index) => (item[..index].trim(), item[index + 1..].trim()),
+                None => return Err(NetConditionsParseError(item.to_string())),
+            };
+            let invalid = || NetConditionsParseError(item.to_string());
+            let probability = || match value.parse::<f32>() {
+                Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+                _ => Err(invalid()),
+            };
+            match key {
+                "latency" => conditions.latency_ms = value.parse().map_err(|_| invalid())?,
+                "jitter" => conditions.jitter_ms = value.parse().map_err(|_| invalid())?,
+                "loss" => conditions.loss = probability()?,
+                "dup" | "duplicate" => conditions.duplicate = probability()?,
+                "reorder" => conditions.reorder = probability()?,
+                "seed" => conditions.seed = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+impl NetConditions {
+    // 从 NET_SIM 环境变量读取, 没有设置时不模拟. 格式错误时由调用者决定如何处理
+    pub fn from_env() -> Result<Self, NetConditionsParseError> {
+        match std::env::var(NET_SIM_ENV) {
+            Ok(spec) => spec.parse(),
+            Err(_) => Ok(Default::default()),
+        }
+    }
+
+    pub fn is_ideal(&self) -> bool {
+        self.latency_ms == 0
+            && self.jitter_ms == 0
+            && self.loss <= 0.0
+            && self.duplicate <= 0.0
+            && self.reorder <= 0.0
+    }
+}
+
+struct DelayedPacket {
+    due: Instant,
+    seq: u64, // 同一时刻到期的包保持发送顺序
+    peer: SocketAddr,
+    data: Vec<u8>,
+}
+
+impl PartialEq for DelayedPacket {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl Eq for DelayedPacket {}
+
+impl PartialOrd for DelayedPacket {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DelayedPacket {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+// 按 NetConditions 丢弃, 复制和延迟发出的包, 到期的包在下次收发时真正写入 socket
+pub struct NetSimulator {
+    conditions: NetConditions,
+    rng: Pcg32,
+    queue: BinaryHeap<Reverse<DelayedPacket>>,
+    next_seq: u64,
+}
+
+impl NetSimulator {
+    pub fn new(conditions: NetConditions) -> Self {
+        let rng = Pcg32::seed_from_u64(conditions.seed.unwrap_or_else(rand::random));
+        NetSimulator {
+            conditions,
+            rng,
+            queue: Default::default(),
+            next_seq: 0,
+        }
+    }
+
+    pub fn conditions(&self) -> &NetConditions {
+        &self.conditions
+    }
+
+    pub fn send_to(
+        &mut self,
+        socket: &UdpSocket,
+        buf: &[u8],
+        peer: SocketAddr,
+    ) -> io::Result<usize> {
+        let now = Instant::now();
+        self.flush(socket, now)?;
+        if self.rng.gen::<f32>() < self.conditions.loss {
+            return Ok(buf.len());
+        }
+        let copies = if self.rng.gen::<f32>() < self.conditions.duplicate {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let due = now + self.delay();
+            self.queue.push(Reverse(DelayedPacket {
+                due,
+                seq: self.next_seq,
+                peer,
+                data: buf.to_vec(),
+            }));
+            self.next_seq += 1;
+        }
+        self.flush(socket, now)?;
+        Ok(buf.len())
+    }
+
+    // 发送所有已经到期的包
+    pub fn flush(&mut self, socket: &UdpSocket, now: Instant) -> io::Result<()> {
+        while self.queue.peek().iter().any(|packet| packet.0.due <= now) {
+            if let Some(Reverse(packet)) = self.queue.pop() {
+                socket.send_to(&packet.data, packet.peer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = match self.conditions.jitter_ms {
+            0 => 0,
+            jitter => self.rng.gen_range(0..=jitter),
+        };
+        let mut delay = self.conditions.latency_ms + jitter;
+        if self.rng.gen::<f32>() < self.conditions.reorder {
+            // 额外延迟超过最大抖动, 保证被后面发出的包超过
+            delay += self.conditions.latency_ms + self.conditions.jitter_ms + 20;
+        }
+        Duration::from_millis(delay as u64)
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::client::NetClient;
+use super::protocol::SNAPSHOT_INTERVAL;
+
+// 每个远程实体最多保存的快照数量
+const MAX_SNAPSHOT_SAMPLES: usize = 32;
+
+// 每帧向目标渲染时间追赶的比例, 避免网络抖动导致画面跳动
+const CATCH_UP_RATE: f64 = 0.1;
+
+pub struct InterpolationSettings {
+    pub delay: f32,             // 渲染落后于最新快照的秒数, 至少需要一个快照间隔
+    pub max_extrapolation: f32, // 快照迟到时最多向前外推的秒数
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        InterpolationSettings {
+            delay: 0.1,
+            max_extrapolation: 0.25,
+        }
+    }
+}
+
+// 远程实体最近收到的位置, 按快照 tick 排序
+#[derive(Default)]
+pub struct SnapshotBuffer {
+    samples: VecDeque<(u32, Vec3)>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, tick: u32, position: Vec3) {
+        if let Some(&(last_tick, last_position)) = self.samples.back() {
+            if tick <= last_tick {
+                return;
+            }
+            // 快照只包含变化的实体, 两次变化之间实体保持不动
+            if tick > last_tick + 1 {
+                self.samples.push_back((tick - 1, last_position));
+            }
+        }
+        self.samples.push_back((tick, position));
+        while self.samples.len() > MAX_SNAPSHOT_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    // 计算 render_tick 时刻的位置, latest_tick 是客户端收到的最新快照
+    pub fn sample(
+        &self,
+        render_tick: f64,
+        latest_tick: u32,
+        max_extrapolation_ticks: f64,
+    ) -> Option<Vec3> {
+        let &(last_tick, last_position) = self.samples.back()?;
+        if render_tick >= last_tick as f64 {
+            // 最新快照里没有该实体说明它已经停下, 否则按最后的速度外推
+            if last_tick < latest_tick || self.samples.len() < 2 {
+                return Some(last_position);
+            }
+            let (prev_tick, prev_position) = self.samples[self.samples.len() - 2];
+            let velocity = (last_position - prev_position) / (last_tick - prev_tick) as f32;
+            let ahead = (render_tick - last_tick as f64).min(max_extrapolation_ticks);
+            return Some(last_position + velocity * ahead as f32);
+        }
+
+        let mut prev = self.samples[0];
+        if render_tick <= prev.0 as f64 {
+            return Some(prev.1);
+        }
+        for &(tick, position) in self.samples.iter().skip(1) {
+            if render_tick <= tick as f64 {
+                let t = (render_tick - prev.0 as f64) / (tick - prev.0) as f64;
+                return Some(prev.1.lerp(position, t as f32));
+            }
+            prev = (tick, position);
+        }
+        Some(last_position)
+    }
+
+    // 丢弃插值不再需要的旧快照, 保留 render_tick 之前的一个
+    pub fn discard_before(&mut self, render_tick: f64) {
+        while self.samples.len() > 2 && self.samples[1].0 as f64 <= render_tick {
+            self.samples.pop_front();
+        }
+    }
+}
+
+// 远程玩家和金币按延迟后的时间在快照之间插值
+pub(super) fn client_interpolation_system(
+    time: Res<Time>,
+    settings: Res<InterpolationSettings>,
+    mut client: ResMut<NetClient>,
+    mut render_tick: Local<Option<f64>>,
+    mut query: Query<(&mut SnapshotBuffer, &mut Transform)>,
+) {
+    client
+        .take_samples()
+        .into_iter()
+        .for_each(|(entity, tick, position)| {
+            if let Ok((mut buffer, _)) = query.get_mut(entity) {
+                buffer.push(tick, position);
+            }
+        });
+
+    let (latest_tick, received_at) = match client.latest_tick() {
+        Some(latest) => latest,
+        None => return,
+    };
+    // 根据最新快照估算服务器当前的 tick, 再往回退 delay
+    let delay_ticks = settings.delay as f64 / SNAPSHOT_INTERVAL;
+    let target = latest_tick as f64
+        + (time.seconds_since_startup() - received_at) / SNAPSHOT_INTERVAL
+        - delay_ticks;
+    let tick = match *render_tick {
+        Some(tick) => {
+            let tick = tick + time.delta_seconds_f64() / SNAPSHOT_INTERVAL;
+            if (target - tick).abs() < delay_ticks.max(1.0) {
+                tick + (target - tick) * CATCH_UP_RATE
+            } else {
+                target
+            }
+        }
+        None => target,
+    };
+    *render_tick = Some(tick);
+
+    let max_extrapolation_ticks = settings.max_extrapolation as f64 / SNAPSHOT_INTERVAL;
+    query.iter_mut().for_each(|(mut buffer, mut transform)| {
+        if let Some(position) = buffer.sample(tick, latest_tick, max_extrapolation_ticks) {
+            transform.translation = position;
+        }
+        buffer.discard_before(tick);
+    });
+}
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use super::super::game::{GameRng, GameRules, GameSeed, GameStage, SimulationClock};
+use super::super::input_ext::{PlayerOperate, PlayerOperateState};
+use super::super::player::{PlayerRoster, PlayerSystem, Ready, SpawnLocalPlayer};
+use super::checksum::{ChecksumPlugin, WorldChecksums};
+use super::conditions::NetConditions;
+use super::protocol::{Codec, LockstepMessage};
+use super::transport::{bind_socket_with, KcpClock, KcpSession, NetSocket, MAX_PACKET_SIZE};
+
+// 帧同步节点之间使用同一个 conv, 以对端地址区分会话
+const LOCKSTEP_CONV: u32 = 0x4c53_5450;
+
+// 帧同步模式: 所有节点运行同样的模拟, 只交换每个 tick 的输入
+pub struct LockstepPlugin;
+
+impl Plugin for LockstepPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(ChecksumPlugin)
+            .insert_resource(SpawnLocalPlayer(false))
+            .init_resource::<Lockstep>()
+            .add_startup_system(lockstep_setup_system.system())
+            .add_system_to_stage(CoreStage::PreUpdate, lockstep_receive_system.system())
+            .add_system_to_stage(
+                GameStage::Simulation,
+                lockstep_input_system
+                    .system()
+                    .before(PlayerSystem::PlayerInput),
+            )
+            .add_system_to_stage(CoreStage::Last, lockstep_flush_system.system());
+    }
+}
+
+pub struct LockstepSettings {
+    pub bind_addr: SocketAddr,
+    pub peers: Vec<SocketAddr>, // 所有节点的地址, 下标即玩家编号, 包括本机
+    pub local_slot: usize,
+    pub seed: u64,           // 随机数种子, 所有节点必须相同
+    pub input_delay: u32,    // 本地输入延迟生效的 tick 数, 用来掩盖网络延迟
+    pub max_prediction: u32, // 最多领先已确认输入多少个 tick, 0 表示等待所有输入 (需要 RollbackPlugin)
+}
+
+impl Default for LockstepSettings {
+    fn default() -> Self {
+        LockstepSettings {
+            bind_addr: ([0, 0, 0, 0], 7879).into(),
+            peers: vec![],
+            local_slot: 0,
+            seed: 0,
+            input_delay: 6,
+            max_prediction: 0,
+        }
+    }
+}
+
+// 帧同步玩家的编号, 和 LockstepSettings::peers 的下标对应
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LockstepSlot(pub usize);
+
+pub struct Lockstep {
+    socket: Arc<NetSocket>,
+    clock: KcpClock,
+    codec: Codec,
+    local_slot: usize,
+    seed: u64,
+    input_delay: u32,
+    max_prediction: u32,
+    sessions: Vec<Option<KcpSession>>, // 按玩家编号排列, 本机为 None
+    inputs: BTreeMap<u32, Vec<Option<Vec<PlayerOperate>>>>, // tick -> 每个玩家收到的输入
+    used: BTreeMap<u32, Vec<Vec<PlayerOperate>>>, // tick -> 模拟时实际使用的输入(可能是预测的)
+    last_inputs: Vec<Vec<PlayerOperate>>, // 每个玩家最近收到的输入, 用来预测
+    local_tick: u32,                   // 本地输入已经记录到的 tick
+    confirmed_tick: u32,               // 该 tick 及之前所有玩家的输入都已到齐
+    rollback: Option<u32>,             // 预测错误需要重新模拟的最早 tick
+    send_queue: Vec<LockstepMessage>,
+}
+
+impl FromWorld for Lockstep {
+    fn from_world(world: &mut World) -> Self {
+        let conditions = world
+            .get_resource_or_insert_with(NetConditions::default)
+            .clone();
+        let settings = world.get_resource_or_insert_with(LockstepSettings::default);
+        let socket =
+            bind_socket_with(settings.bind_addr, &conditions).expect("bind lockstep socket fail!");
+        let sessions = settings
+            .peers
+            .iter()
+            .enumerate()
+            .map(|(slot, addr)| {
+                if slot == settings.local_slot {
+                    None
+                } else {
+                    Some(KcpSession::new(LOCKSTEP_CONV, socket.clone(), *addr))
+                }
+            })
+            .collect();
+        info!(
+            "lockstep peer {} listen on {:?}, {} players",
+            settings.local_slot,
+            socket.local_addr(),
+            settings.peers.len()
+        );
+        let mut lockstep = Lockstep {
+            socket,
+            clock: Default::default(),
+            codec: Default::default(),
+            local_slot: settings.local_slot,
+            seed: settings.seed,
+            input_delay: settings.input_delay,
+            max_prediction: settings.max_prediction,
+            last_inputs: vec![vec![]; settings.peers.len()],
+            sessions,
+            inputs: Default::default(),
+            used: Default::default(),
+            local_tick: settings.input_delay,
+            confirmed_tick: 0,
+            rollback: None,
+            send_queue: vec![LockstepMessage::Hello {
+                slot: settings.local_slot,
+                seed: settings.seed,
+            }],
+        };
+        // 输入延迟期间的 tick 没有任何输入, 所有节点都可以直接模拟
+        (1..=lockstep.input_delay).for_each(|tick| {
+            (0..lockstep.slot_num()).for_each(|slot| lockstep.record(slot, tick, vec![]))
+        });
+        let seed = lockstep.seed;
+        world.insert_resource(GameSeed(seed));
+        world.insert_resource(GameRng::from_seed(seed));
+        lockstep
+    }
+}
+
+impl Lockstep {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket.local_addr().expect("get lockstep addr fail!")
+    }
+
+    // 更换对端地址, 例如对端绑定随机端口后才知道实际地址. 需要在开始通信前调用
+    pub fn set_peer_addr(&mut self, slot: usize, addr: SocketAddr) {
+        if let Some(Some(session)) = self.sessions.get_mut(slot) {
+            *session = KcpSession::new(LOCKSTEP_CONV, self.socket.clone(), addr);
+        }
+    }
+
+    pub fn local_slot(&self) -> usize {
+        self.local_slot
+    }
+
+    pub fn slot_num(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn confirmed_tick(&self) -> u32 {
+        self.confirmed_tick
+    }
+
+    // 取出需要回滚重新模拟的最早 tick
+    pub fn take_rollback(&mut self) -> Option<u32> {
+        self.rollback.take()
+    }
+
+    fn record(&mut self, slot: usize, tick: u32, pressed: Vec<PlayerOperate>) {
+        if tick <= self.confirmed_tick {
+            return;
+        }
+        // 已经用预测的输入模拟过该 tick, 预测错误时需要回滚
+        let mispredicted = self
+            .used
+            .get(&tick)
+            .and_then(|used| used.get(slot))
+            .iter()
+            .any(|used| **used != pressed);
+        if mispredicted {
+            self.rollback = Some(self.rollback.map_or(tick, |rollback| rollback.min(tick)));
+        }
+        if let Some(last) = self.last_inputs.get_mut(slot) {
+            *last = pressed.clone();
+        }
+        let slot_num = self.slot_num();
+        if let Some(input) = self
+            .inputs
+            .entry(tick)
+            .or_insert_with(|| vec![None; slot_num])
+            .get_mut(slot)
+        {
+            *input = Some(pressed);
+        }
+        while self
+            .inputs
+            .get(&(self.confirmed_tick + 1))
+            .iter()
+            .any(|inputs| inputs.iter().all(Option::is_some))
+        {
+            self.confirmed_tick += 1;
+        }
+    }
+
+    // 该 tick 每个玩家的输入, 没有收到的重复该玩家最近一次的输入
+    fn inputs_of(&self, tick: u32) -> Vec<Vec<PlayerOperate>> {
+        let inputs = self.inputs.get(&tick);
+        self.last_inputs
+            .iter()
+            .enumerate()
+            .map(|(slot, last)| {
+                inputs
+                    .and_then(|inputs| inputs[slot].clone())
+                    .unwrap_or_else(|| last.clone())
+            })
+            .collect()
+    }
+}
+
+// 每个节点按编号生成所有玩家, 保证各节点上的实体创建顺序一致
+fn lockstep_setup_system(
+    mut commands: Commands,
+    rules: Res<GameRules>,
+    lockstep: Res<Lockstep>,
+    mut roster: ResMut<PlayerRoster>,
+) {
+    let slot_num = lockstep.slot_num();
+    (0..slot_num).for_each(|slot| {
+        let player = roster.join(&mut commands, &rules, None);
+        // 所有节点在启动前已经约定好, 不需要大厅准备
+        commands
+            .entity(player)
+            .insert_bundle((LockstepSlot(slot), Ready(true)));
+    });
+}
+
+fn lockstep_receive_system(
+    mut lockstep: ResMut<Lockstep>,
+    mut clock: ResMut<SimulationClock>,
+    mut checksums: ResMut<WorldChecksums>,
+) {
+    let mut buf = [0; MAX_PACKET_SIZE];
+    loop {
+        match lockstep.socket.recv_from(&mut buf) {
+            Ok((size, addr)) => {
+                let session = lockstep
+                    .sessions
+                    .iter_mut()
+                    .flatten()
+                    .find(|session| session.peer() == addr);
+                match session {
+                    Some(session) => {
+                        if let Err(e) = session.input(&buf[..size]) {
+                            warn!("invalid packet from {}: {:?}", addr, e);
+                        }
+                    }
+                    None => warn!("packet from unknown peer {}", addr),
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("lockstep socket recv fail: {}", e);
+                break;
+            }
+        }
+    }
+
+    let mut messages = Vec::new();
+    let codec = lockstep.codec;
+    lockstep
+        .sessions
+        .iter_mut()
+        .enumerate()
+        .for_each(|(slot, session)| {
+            while let Some(bytes) = session.as_mut().and_then(|session| session.recv()) {
+                match codec.decode::<LockstepMessage>(&bytes) {
+                    Ok(message) => messages.push((slot, message)),
+                    Err(e) => warn!("invalid message from peer {}: {}", slot, e),
+                }
+            }
+        });
+    for (peer, message) in messages {
+        match message {
+            LockstepMessage::Hello { slot, seed } => {
+                if slot != peer {
+                    warn!("peer {} claims to be player {}", peer, slot);
+                }
+                if seed != lockstep.seed {
+                    error!(
+                        "peer {} use seed {}, local seed {}, simulation will desync!",
+                        peer, seed, lockstep.seed
+                    );
+                }
+            }
+            LockstepMessage::Input {
+                slot,
+                tick,
+                pressed,
+            } => {
+                if slot != peer {
+                    warn!("peer {} send input for player {}", peer, slot);
+                } else {
+                    lockstep.record(slot, tick, pressed);
+                }
+            }
+            LockstepMessage::Checksum { slot, tick, hash } => {
+                if slot == peer {
+                    checksums.receive(peer, tick, hash);
+                }
+            }
+            LockstepMessage::Dump { slot, tick, dump } => {
+                if slot == peer {
+                    checksums.report_remote_dump(peer, tick, &dump);
+                }
+            }
+        }
+    }
+
+    // 超出预测范围的 tick 不能模拟, 等待其它节点的输入
+    let limit = lockstep.confirmed_tick + lockstep.max_prediction;
+    let tick = clock.tick();
+    clock.limit_ticks(limit.saturating_sub(tick));
+}
+
+// 每个 tick 记录本地输入, 并把该 tick 所有玩家的输入写入对应的玩家
+fn lockstep_input_system(
+    clock: Res<SimulationClock>,
+    input: Res<Input<PlayerOperate>>,
+    mut lockstep: ResMut<Lockstep>,
+    mut query: Query<(&LockstepSlot, &mut PlayerOperateState)>,
+) {
+    let tick = clock.tick();
+    let target = tick + lockstep.input_delay;
+    // 回滚重新模拟时本地输入已经记录过了
+    if target > lockstep.local_tick {
+        let pressed = PlayerOperateState::from_input(&input).to_sorted_vec();
+        let slot = lockstep.local_slot;
+        lockstep.local_tick = target;
+        lockstep.record(slot, target, pressed.clone());
+        lockstep.send_queue.push(LockstepMessage::Input {
+            slot,
+            tick: target,
+            pressed,
+        });
+    }
+
+    let inputs = lockstep.inputs_of(tick);
+    query.iter_mut().for_each(|(slot, mut state)| {
+        let pressed = inputs.get(slot.0).cloned().unwrap_or_default();
+        *state = PlayerOperateState::from_pressed(pressed);
+    });
+    lockstep.used.insert(tick, inputs);
+
+    // 已确认之前的输入不会再被使用
+    let keep = lockstep.confirmed_tick.min(tick);
+    lockstep.inputs = lockstep.inputs.split_off(&keep);
+    lockstep.used = lockstep.used.split_off(&keep);
+}
+
+fn lockstep_flush_system(
+    clock: Res<SimulationClock>,
+    mut lockstep: ResMut<Lockstep>,
+    mut checksums: ResMut<WorldChecksums>,
+) {
+    // 只比较用确认的输入模拟出的 tick
+    checksums.confirm(lockstep.confirmed_tick.min(clock.tick()));
+    let slot = lockstep.local_slot;
+    let checksum_messages = checksums
+        .take_outgoing()
+        .into_iter()
+        .map(|(tick, hash)| LockstepMessage::Checksum { slot, tick, hash });
+    let dump_messages = checksums
+        .take_outgoing_dumps()
+        .into_iter()
+        .map(|(tick, dump)| LockstepMessage::Dump { slot, tick, dump });
+    lockstep
+        .send_queue
+        .extend(checksum_messages.chain(dump_messages));
+
+    let Lockstep {
+        sessions,
+        clock,
+        codec,
+        send_queue,
+        ..
+    } = &mut *lockstep;
+    send_queue.drain(..).for_each(|message| {
+        let bytes = codec.encode(&message);
+        sessions.iter_mut().flatten().for_each(|session| {
+            if let Err(e) = session.send(&bytes) {
+                warn!("send to {} fail: {:?}", session.peer(), e);
+            }
+        });
+    });
+    sessions.iter_mut().flatten().for_each(|session| {
+        if let Err(e) = session.update(clock) {
+            warn!("flush to {} fail: {:?}", session.peer(), e);
+        }
+    });
+}
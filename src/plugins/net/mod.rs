@@ -0,0 +1,110 @@
+use bevy::app::{PluginGroup, PluginGroupBuilder};
+
+use super::action_input::ActionInputPlugin;
+use super::coin::CoinPlugin;
+use super::elapsed_time::ElapsedTimePlugin;
+use super::game::GameCorePlugin;
+use super::input_ext::InputExtPlugin;
+use super::key_binding::KeyBindingPlugin;
+use super::player::PlayerPlugin;
+use super::presentation::PresentationPlugin;
+use super::rules::RulesHotReloadPlugin;
+use super::spectator::SpectatorCameraPlugin;
+use super::ui::UiPlugin;
+
+mod checksum;
+mod client;
+pub mod conditions;
+mod interpolation;
+mod lockstep;
+mod prediction;
+pub mod protocol;
+mod rollback;
+mod server;
+pub mod transport;
+
+pub use checksum::{dump_diff, ChecksumPlugin, DesyncDetectedEvent, WorldChecksums};
+pub use client::{ConnectionState, NetClient, NetClientPlugin, NetClientSettings};
+pub use conditions::NetConditions;
+pub use interpolation::{InterpolationSettings, SnapshotBuffer};
+pub use lockstep::{Lockstep, LockstepPlugin, LockstepSettings, LockstepSlot};
+pub use prediction::InputBuffer;
+pub use protocol::ClientRole;
+pub use rollback::{RollbackBuffer, RollbackPlugin};
+pub use server::{NetServer, NetServerPlugin, NetServerSettings};
+
+// 网络同步实体的唯一 id, 由服务器分配
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NetId(pub u32);
+
+// 专用服务器插件组: 只运行玩法逻辑和网络同步, 不需要窗口和渲染
+pub struct DedicatedServerPlugins;
+
+impl PluginGroup for DedicatedServerPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(GameCorePlugin);
+        group.add(InputExtPlugin);
+        group.add(PlayerPlugin);
+        group.add(CoinPlugin);
+        group.add(ElapsedTimePlugin);
+        group.add(NetServerPlugin);
+        group.add(RulesHotReloadPlugin);
+    }
+}
+
+// 客户端插件组: 玩家和金币的玩法逻辑都在服务器上运行, 本地只做镜像和显示
+pub struct NetClientPlugins;
+
+impl PluginGroup for NetClientPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(GameCorePlugin);
+        group.add(InputExtPlugin);
+        group.add(KeyBindingPlugin);
+        group.add(ActionInputPlugin);
+        group.add(PresentationPlugin);
+        group.add(UiPlugin);
+        group.add(NetClientPlugin);
+    }
+}
+
+// 观战客户端插件组: 需要同时插入 role 为 Spectator 的 NetClientSettings
+pub struct NetSpectatorPlugins;
+
+impl PluginGroup for NetSpectatorPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        NetClientPlugins.build(group);
+        group.add(SpectatorCameraPlugin);
+    }
+}
+
+// 帧同步插件组: 每个节点都运行完整的玩法逻辑, 只交换输入
+pub struct LockstepPlugins;
+
+impl PluginGroup for LockstepPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(GameCorePlugin);
+        group.add(InputExtPlugin);
+        group.add(PlayerPlugin);
+        group.add(CoinPlugin);
+        group.add(ElapsedTimePlugin);
+        group.add(PresentationPlugin);
+        group.add(UiPlugin);
+        group.add(LockstepPlugin);
+    }
+}
+
+// 回滚插件组: 在帧同步的基础上预测其它玩家的输入
+pub struct RollbackPlugins;
+
+impl PluginGroup for RollbackPlugins {
+    fn build(&mut self, group: &mut PluginGroupBuilder) {
+        group.add(GameCorePlugin);
+        group.add(InputExtPlugin);
+        group.add(PlayerPlugin);
+        group.add(CoinPlugin);
+        group.add(ElapsedTimePlugin);
+        group.add(PresentationPlugin);
+        group.add(UiPlugin);
+        group.add(RollbackPlugin);
+    }
+}
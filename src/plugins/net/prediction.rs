@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::super::input_ext::{AnalogInputs, PlayerOperate, PlayerOperateState};
+use super::super::player::{movement_offset, LocalPlayer, Movement};
+use super::client::NetClient;
+use super::protocol::InputFrame;
+
+// 默认保存的输入帧数量, 60 帧下约 2 秒, 足够覆盖 200ms 以上的延迟
+const DEFAULT_INPUT_BUFFER_SIZE: usize = 128;
+
+// 已发送但服务器还没有确认的输入帧
+pub struct InputBuffer {
+    frames: VecDeque<InputFrame>,
+    capacity: usize,
+    last_seq: u32,
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        InputBuffer::with_capacity(DEFAULT_INPUT_BUFFER_SIZE)
+    }
+}
+
+impl InputBuffer {
+    pub fn with_capacity(capacity: usize) -> Self {
+        InputBuffer {
+            frames: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            last_seq: 0,
+        }
+    }
+
+    // 记录一帧新的输入并分配递增的序号
+    pub fn push(&mut self, dt: f32, state: &PlayerOperateState) -> InputFrame {
+        self.last_seq += 1;
+        let analog = state.analog();
+        let frame = InputFrame {
+            seq: self.last_seq,
+            dt,
+            pressed: state.to_sorted_vec(),
+            analog: [analog.x, analog.y],
+        };
+        if self.frames.len() == self.capacity {
+            // 太久没有收到确认, 最老的输入无法再参与重放
+            warn!("input buffer full, drop input {}", self.frames[0].seq);
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame.clone());
+        frame
+    }
+
+    // 丢弃服务器已经处理过的输入
+    pub fn acknowledge(&mut self, seq: u32) {
+        while self.frames.front().iter().any(|frame| frame.seq <= seq) {
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &InputFrame> {
+        self.frames.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // 从服务器确认的位置开始重放所有未确认的输入, 得到本地预测位置
+    pub fn replay(&self, position: Vec3, speed: f32) -> Vec3 {
+        self.frames.iter().fold(position, |position, frame| {
+            position + movement_offset(&frame.state(), speed, frame.dt)
+        })
+    }
+}
+
+// 本地玩家立即按输入移动, 不等待服务器
+pub(super) fn client_predict_system(
+    time: Res<Time>,
+    input: Res<Input<PlayerOperate>>,
+    analog_inputs: Res<AnalogInputs>,
+    mut client: ResMut<NetClient>,
+    mut buffer: ResMut<InputBuffer>,
+    mut query: Query<(&Movement, &mut Transform), With<LocalPlayer>>,
+) {
+    if !client.connected() || client.is_spectator() {
+        return;
+    }
+    // 网络客户端只有一个本地玩家, 使用第一个座位
+    let state = PlayerOperateState::from_input(&input).with_analog(analog_inputs.get(0));
+    let frame = buffer.push(time.delta_seconds(), &state);
+    if let Ok((movement, mut transform)) = query.single_mut() {
+        transform.translation += movement_offset(&state, movement.speed, frame.dt);
+    }
+    client.send_input(frame);
+}
+
+// 收到服务器的权威位置后, 回退到该位置并重放未确认的输入
+pub(super) fn client_reconcile_system(
+    mut client: ResMut<NetClient>,
+    mut buffer: ResMut<InputBuffer>,
+    mut query: Query<(&Movement, &mut Transform), With<LocalPlayer>>,
+) {
+    let (seq, position) = match client.take_input_ack() {
+        Some(ack) => ack,
+        None => return,
+    };
+    buffer.acknowledge(seq);
+    if let Ok((movement, mut transform)) = query.single_mut() {
+        let predicted = buffer.replay(position.extend(transform.translation.z), movement.speed);
+        if predicted.distance(transform.translation) > 1.0 {
+            debug!(
+                "prediction corrected by {}, pending inputs: {}",
+                predicted.distance(transform.translation),
+                buffer.len()
+            );
+        }
+        transform.translation = predicted;
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::super::game::{MatchResult, MatchState};
+use super::super::input_ext::{PlayerOperate, PlayerOperateState};
+
+// 协议版本号, 消息格式有任何不兼容的修改都需要加一
+pub const PROTOCOL_VERSION: u16 = 11;
+
+// 每条消息的固定头: 2 字节 magic + 2 字节版本号(小端), 任何版本都能读懂
+const MESSAGE_MAGIC: [u8; 2] = *b"TG";
+const HEADER_SIZE: usize = 4;
+
+// 服务器广播快照的间隔(秒), 每次广播快照 tick 加一
+pub const SNAPSHOT_INTERVAL: f64 = 1.0 / 20.0;
+
+// 单条消息的最大长度, 防止恶意数据导致超大内存分配
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+// 客户端 -> 服务器
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Handshake {
+        version: u16,
+        name: String,
+        session_token: Option<u64>, // 断线重连时带上之前分配的会话令牌
+        role: ClientRole,
+    },
+    InputFrame(InputFrame),
+    Ready(bool), // 大厅阶段切换准备状态
+    Disconnect(DisconnectReason),
+}
+
+// 服务器 -> 客户端
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    HandshakeAccepted {
+        player_id: u32,
+        session_token: u64, // 掉线后用来找回同一个玩家
+    },
+    HandshakeRejected(RejectReason),
+    SpectatorAccepted, // 以观战者身份加入, 没有玩家
+    EntitySnapshot(WorldSnapshot),
+    EntityDelta(WorldDelta),
+    ScoreUpdate {
+        team_id: usize,
+        team_score: usize,
+    }, // TeamScoreChangedEvent
+    CoinPickedup {
+        coin_id: u32,
+    }, // CoinPickedupEvent
+    ElapsedSeconds {
+        seconds: usize,
+    }, // ElapsedSecondChangedEvent
+    InputAck {
+        seq: u32,
+        position: Vec2,
+    }, // 已处理到 seq 的输入后该客户端玩家的位置
+    MatchStateChanged {
+        state: MatchState,
+        result: Option<MatchResult>,
+    }, // 比赛结束时带上完整的比赛结果
+    Disconnect(DisconnectReason),
+}
+
+// 帧同步节点之间, 每个节点只发送自己的输入
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LockstepMessage {
+    Hello {
+        slot: usize,
+        seed: u64,
+    },
+    Input {
+        slot: usize,
+        tick: u32, // 该输入生效的 tick
+        pressed: Vec<PlayerOperate>,
+    },
+    Checksum {
+        slot: usize,
+        tick: u32,
+        hash: u64,
+    },
+    Dump {
+        slot: usize,
+        tick: u32,
+        dump: String, // 发现不同步后发送该 tick 的完整状态文本
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputFrame {
+    pub seq: u32, // 客户端递增的输入序号
+    pub dt: f32,  // 该输入持续的秒数
+    pub pressed: Vec<PlayerOperate>,
+    pub analog: [f32; 2], // 摇杆方向, 为 0 时按 pressed 移动
+}
+
+impl InputFrame {
+    pub fn state(&self) -> PlayerOperateState {
+        let [x, y] = self.analog;
+        PlayerOperateState::from_pressed(self.pressed.iter().cloned()).with_analog(Vec2::new(x, y))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    VersionMismatch { server: u16, client: u16 },
+    ServerFull { max_player_num: usize },
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::VersionMismatch { server, client } => write!(
+                f,
+                "protocol version mismatch: server {}, client {}",
+                server, client
+            ),
+            RejectReason::ServerFull { max_player_num } => {
+                write!(f, "server is full: at most {} players", max_player_num)
+            }
+        }
+    }
+}
+
+// 客户端加入的身份
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClientRole {
+    Player,
+    Spectator, // 只接收快照, 不发送输入, 不占用玩家名额
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    ClientLeave,
+    Timeout,
+    Rejected(RejectReason),
+}
+
+// 加入游戏时发送的完整世界状态
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub tick: u32,
+    pub match_state: MatchState,
+    pub result: Option<MatchResult>,
+    pub players: Vec<PlayerSnapshot>,
+    pub coins: Vec<CoinSnapshot>,
+}
+
+// 相对上一次广播的变化量
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldDelta {
+    pub tick: u32,
+    pub players: Vec<PlayerSnapshot>, // 新增或发生变化的玩家
+    pub coins: Vec<CoinSnapshot>,     // 新增或发生变化的金币
+    pub removed: Vec<u32>,            // 已经删除的实体
+}
+
+impl WorldDelta {
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty() && self.coins.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl WorldSnapshot {
+    // 计算从 prev 到 self 的变化
+    pub fn delta_from(&self, prev: &WorldSnapshot) -> WorldDelta {
+        let players = self
+            .players
+            .iter()
+            .filter(|p| !prev.players.contains(p))
+            .cloned()
+            .collect();
+        let coins = self
+            .coins
+            .iter()
+            .filter(|c| !prev.coins.contains(c))
+            .cloned()
+            .collect();
+        let removed = prev
+            .players
+            .iter()
+            .map(|p| p.id)
+            .filter(|id| self.players.iter().all(|p| p.id != *id))
+            .chain(
+                prev.coins
+                    .iter()
+                    .map(|c| c.id)
+                    .filter(|id| self.coins.iter().all(|c| c.id != *id)),
+            )
+            .collect();
+        WorldDelta {
+            tick: self.tick,
+            players,
+            coins,
+            removed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSnapshot {
+    pub id: u32,
+    pub name: String,
+    pub team_id: usize,
+    pub score: usize,
+    pub ready: bool,
+    pub color: [f32; 4], // rgba
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoinSnapshot {
+    pub id: u32,
+    pub score_value: usize,
+    pub position: Vec2,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Truncated(usize),
+    BadMagic([u8; 2]),
+    VersionMismatch { expected: u16, found: u16 },
+    Malformed(bincode::Error),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Truncated(len) => write!(f, "message too short: {} bytes", len),
+            CodecError::BadMagic(magic) => write!(f, "bad message magic: {:?}", magic),
+            CodecError::VersionMismatch { expected, found } => write!(
+                f,
+                "protocol version mismatch: expected {}, found {}",
+                expected, found
+            ),
+            CodecError::Malformed(e) => write!(f, "malformed message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+// 带版本头的 bincode 编解码
+#[derive(Debug, Clone, Copy)]
+pub struct Codec {
+    version: u16,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::new(PROTOCOL_VERSION)
+    }
+}
+
+impl Codec {
+    pub fn new(version: u16) -> Self {
+        Codec { version }
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn encode<T: Serialize>(&self, message: &T) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(&MESSAGE_MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bincode_options()
+            .serialize_into(&mut bytes, message)
+            .expect("serialize net message fail!");
+        bytes
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        let version = read_header(bytes)?;
+        if version != self.version {
+            return Err(CodecError::VersionMismatch {
+                expected: self.version,
+                found: version,
+            });
+        }
+        bincode_options()
+            .deserialize(&bytes[HEADER_SIZE..])
+            .map_err(CodecError::Malformed)
+    }
+}
+
+// 只读取消息头里的协议版本号
+pub fn read_header(bytes: &[u8]) -> Result<u16, CodecError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(CodecError::Truncated(bytes.len()));
+    }
+    if bytes[..2] != MESSAGE_MAGIC {
+        return Err(CodecError::BadMagic([bytes[0], bytes[1]]));
+    }
+    Ok(u16::from_le_bytes([bytes[2], bytes[3]]))
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_limit(MAX_MESSAGE_SIZE)
+        .reject_trailing_bytes()
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+
+use super::super::coin::{spawn_coin, Coin, CoinInfo, CoinPool, CoinSerial};
+use super::super::game::{GameRng, GameRules, GameStage, GameState, MatchState, SimulationClock};
+use super::super::player::{Player, Score};
+use super::lockstep::{Lockstep, LockstepPlugin};
+
+// 回滚模式: 在帧同步的基础上预测其它玩家的输入, 收到真实输入后回滚重新模拟.
+// 玩法系统都在 GameStage::Simulation 内, 和渲染分开, 可以在一帧内重复运行.
+// 需要 LockstepSettings::max_prediction 大于 0
+pub struct RollbackPlugin;
+
+impl Plugin for RollbackPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(LockstepPlugin)
+            .init_resource::<RollbackBuffer>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                rollback_system.exclusive_system().at_end(),
+            )
+            .add_system_to_stage(
+                GameStage::Simulation,
+                rollback_save_system.exclusive_system().at_start(),
+            );
+    }
+}
+
+// 每个 tick 结束时的玩法状态
+#[derive(Default)]
+pub struct RollbackBuffer {
+    snapshots: BTreeMap<u32, GameSnapshot>,
+    rollback_count: usize,
+}
+
+impl RollbackBuffer {
+    // 已经发生的回滚次数
+    pub fn rollback_count(&self) -> usize {
+        self.rollback_count
+    }
+}
+
+#[derive(Clone)]
+struct GameSnapshot {
+    match_state: MatchState,
+    game_state: GameState,
+    rng: GameRng,
+    coin_pool: Option<CoinPool>,
+    coin_serial: Option<CoinSerial>,
+    players: Vec<(Entity, Transform, usize, usize)>, // 位置, 分数, 吃到的金币数
+    coins: Vec<(Entity, u64, usize, Transform)>,     // 生成序号, 分值, 位置
+}
+
+impl GameSnapshot {
+    fn capture(world: &mut World) -> Self {
+        let players = world
+            .query_filtered::<(Entity, &Transform, &Score), With<Player>>()
+            .iter(world)
+            .map(|(entity, transform, score)| (entity, *transform, score.val, score.coins))
+            .collect();
+        let coins = world
+            .query::<(Entity, &Coin, &CoinInfo, &Transform)>()
+            .iter(world)
+            .map(|(entity, coin, info, transform)| {
+                (entity, coin.serial, info.score_value, *transform)
+            })
+            .collect();
+        GameSnapshot {
+            match_state: world
+                .get_resource::<State<MatchState>>()
+                .unwrap()
+                .current()
+                .clone(),
+            game_state: world.get_resource::<GameState>().unwrap().clone(),
+            rng: world.get_resource::<GameRng>().unwrap().clone(),
+            coin_pool: world.get_resource::<CoinPool>().cloned(),
+            coin_serial: world.get_resource::<CoinSerial>().cloned(),
+            players,
+            coins,
+        }
+    }
+
+    fn restore(&self, world: &mut World) {
+        world.insert_resource(self.game_state.clone());
+        world.insert_resource(self.rng.clone());
+        if let Some(coin_pool) = &self.coin_pool {
+            world.insert_resource(coin_pool.clone());
+        }
+        if let Some(coin_serial) = &self.coin_serial {
+            world.insert_resource(coin_serial.clone());
+        }
+        self.players
+            .iter()
+            .for_each(|(entity, transform, score, coins)| {
+                if let Some(mut current) = world.get_mut::<Transform>(*entity) {
+                    *current = *transform;
+                }
+                if let Some(mut current) = world.get_mut::<Score>(*entity) {
+                    current.val = *score;
+                    current.coins = *coins;
+                }
+            });
+
+        // 快照之后生成的金币删除, 快照之后被拾取的金币重新生成
+        let coins: Vec<Entity> = world
+            .query_filtered::<Entity, With<Coin>>()
+            .iter(world)
+            .collect();
+        coins
+            .into_iter()
+            .filter(|coin| self.coins.iter().all(|(entity, _, _, _)| entity != coin))
+            .for_each(|coin| {
+                world.despawn(coin);
+            });
+        let mut missing = Vec::new();
+        self.coins
+            .iter()
+            .for_each(|(entity, serial, score_value, transform)| {
+                match world.get_mut::<Transform>(*entity) {
+                    Some(mut current) => *current = *transform,
+                    None => missing.push((*serial, *score_value, transform.translation)),
+                }
+                if let Some(mut info) = world.get_mut::<CoinInfo>(*entity) {
+                    info.score_value = *score_value;
+                }
+            });
+        let rules = world.get_resource::<GameRules>().unwrap();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+        missing
+            .into_iter()
+            .for_each(|(serial, score_value, position)| {
+                spawn_coin(&mut commands, rules, serial, score_value, position);
+            });
+        queue.apply(world);
+    }
+}
+
+// 在 tick 开始时保存上一个 tick 结束时的状态, 此时上一个 tick 的 Commands 已经生效
+fn rollback_save_system(world: &mut World) {
+    let tick = world.get_resource::<SimulationClock>().unwrap().tick() - 1;
+    let confirmed_tick = world.get_resource::<Lockstep>().unwrap().confirmed_tick();
+    let snapshot = GameSnapshot::capture(world);
+    let mut buffer = world.get_resource_mut::<RollbackBuffer>().unwrap();
+    // 不回滚到比赛状态切换之前, 切换时的 on_enter 等逻辑不能重新执行
+    let state_changed = buffer
+        .snapshots
+        .values()
+        .next_back()
+        .iter()
+        .any(|last| last.match_state != snapshot.match_state);
+    if state_changed {
+        buffer.snapshots.clear();
+    }
+    buffer.snapshots.insert(tick, snapshot);
+    // 只会回滚到已确认输入之后的 tick
+    let keep = confirmed_tick.min(tick);
+    buffer.snapshots = buffer.snapshots.split_off(&keep);
+}
+
+// 收到和预测不同的输入后, 恢复到该 tick 之前的状态, 本帧的模拟阶段会重新模拟到当前 tick
+fn rollback_system(world: &mut World) {
+    let tick = match world
+        .get_resource_mut::<Lockstep>()
+        .unwrap()
+        .take_rollback()
+    {
+        Some(tick) => tick,
+        None => return,
+    };
+    let restore_tick = tick - 1;
+    let state = world
+        .get_resource::<State<MatchState>>()
+        .unwrap()
+        .current()
+        .clone();
+    let snapshot = world
+        .get_resource::<RollbackBuffer>()
+        .unwrap()
+        .snapshots
+        .get(&restore_tick)
+        .cloned();
+    match snapshot {
+        Some(snapshot) if snapshot.match_state == state => {
+            snapshot.restore(world);
+            world
+                .get_resource_mut::<SimulationClock>()
+                .unwrap()
+                .rewind(restore_tick);
+            world
+                .get_resource_mut::<RollbackBuffer>()
+                .unwrap()
+                .rollback_count += 1;
+            debug!("rollback to tick {}", restore_tick);
+        }
+        _ => error!(
+            "can not rollback to tick {}, simulation will desync!",
+            restore_tick
+        ),
+    }
+}
@@ -0,0 +1,684 @@
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bevy::core::FixedTimestep;
+use bevy::{prelude::*, utils::HashMap};
+
+use super::super::coin::{Coin, CoinInfo, CoinPickedupEvent};
+use super::super::elapsed_time::ElapsedSecondChangedEvent;
+use super::super::game::{GameRules, GameState, MatchState};
+use super::super::input_ext::PlayerOperateState;
+use super::super::player::{
+    movement_offset, DepartedScores, Leaving, Movement, Player, PlayerColor, PlayerInfo,
+    PlayerRoster, Ready, RemoteControlled, Score, Team, TeamScoreChangedEvent,
+};
+use super::conditions::NetConditions;
+use super::protocol::{
+    ClientMessage, ClientRole, Codec, CodecError, CoinSnapshot, DisconnectReason, InputFrame,
+    PlayerSnapshot, RejectReason, ServerMessage, WorldSnapshot, PROTOCOL_VERSION,
+    SNAPSHOT_INTERVAL,
+};
+use super::transport::{bind_socket_with, KcpClock, KcpSession, NetSocket, MAX_PACKET_SIZE};
+use super::NetId;
+
+// 单个输入帧允许的最大持续时间, 防止客户端伪造超大 dt 瞬移
+const MAX_INPUT_DT: f32 = 0.25;
+// 输入时长预算在服务器经过的时间之外最多多出的秒数, 用来吸收网络抖动
+const INPUT_BUDGET_SLACK: f32 = 0.5;
+
+pub struct NetServerPlugin;
+
+impl Plugin for NetServerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<NetServer>()
+            .add_system_to_stage(CoreStage::PreUpdate, server_receive_system.system())
+            .add_system(net_id_assign_system.system())
+            .add_system_set_to_stage(
+                CoreStage::Last,
+                SystemSet::new()
+                    .with_run_criteria(FixedTimestep::step(SNAPSHOT_INTERVAL))
+                    .with_system(
+                        snapshot_broadcast_system
+                            .system()
+                            .label(NetServerSystem::Broadcast)
+                            .after(NetServerSystem::Events),
+                    ),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                // 先于快照发送, 客户端删除金币镜像前能收到拾取事件
+                event_broadcast_system
+                    .system()
+                    .label(NetServerSystem::Events)
+                    .label(NetServerSystem::Broadcast),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                server_flush_system
+                    .system()
+                    .after(NetServerSystem::Broadcast),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+enum NetServerSystem {
+    Events,
+    Broadcast,
+}
+
+pub struct NetServerSettings {
+    pub bind_addr: SocketAddr,
+    pub client_timeout_seconds: f32, // 超过该时间没有收到数据则断开客户端
+    pub reconnect_grace_seconds: f32, // 掉线的玩家保留该时间等待重连, 超时后离开比赛
+}
+
+impl Default for NetServerSettings {
+    fn default() -> Self {
+        NetServerSettings {
+            bind_addr: ([0, 0, 0, 0], 7878).into(),
+            client_timeout_seconds: 10.0,
+            reconnect_grace_seconds: 30.0,
+        }
+    }
+}
+
+struct RemoteClient {
+    session: KcpSession,
+    player: Option<Entity>,
+    synced: bool,        // 是否已经收到过完整快照
+    closing: bool,       // 本帧发送完剩余消息后断开
+    last_input_seq: u32, // 已经处理的最新输入序号
+    input_budget: f32,   // 还能接受的输入时长(秒), 防止客户端一次发送大量输入帧加速
+    session_token: Option<u64>,
+    replaced_conv: Option<u32>, // 被本连接替换的旧会话
+    spectator: bool,
+}
+
+// 加入游戏的玩家会话, 客户端掉线后玩家实体保留到宽限期结束
+struct PlayerSession {
+    player: Entity,
+    addr: Option<SocketAddr>,    // 当前连接的客户端, 掉线后为 None
+    dropped_at: Option<Instant>, // 掉线的时间
+}
+
+pub struct NetServer {
+    socket: Arc<NetSocket>,
+    clock: KcpClock,
+    codec: Codec,
+    clients: HashMap<SocketAddr, RemoteClient>,
+    next_net_id: u32,
+    net_ids: HashMap<Entity, NetId>, // 实体销毁后仍能查到 NetId, 用于转发拾取事件
+    sessions: HashMap<u64, PlayerSession>, // 会话令牌 -> 玩家会话
+    tick: u32,
+    last_snapshot: Option<WorldSnapshot>,
+}
+
+impl FromWorld for NetServer {
+    fn from_world(world: &mut World) -> Self {
+        let conditions = world
+            .get_resource_or_insert_with(NetConditions::default)
+            .clone();
+        let settings = world.get_resource_or_insert_with(NetServerSettings::default);
+        let socket =
+            bind_socket_with(settings.bind_addr, &conditions).expect("bind server socket fail!");
+        info!(
+            "net server listen on {:?}, protocol version {}",
+            socket.local_addr(),
+            PROTOCOL_VERSION
+        );
+        NetServer {
+            socket,
+            clock: Default::default(),
+            codec: Default::default(),
+            clients: Default::default(),
+            next_net_id: 0,
+            net_ids: Default::default(),
+            sessions: Default::default(),
+            tick: 0,
+            last_snapshot: None,
+        }
+    }
+}
+
+impl NetServer {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.socket.local_addr().expect("get server addr fail!")
+    }
+
+    // 已经加入游戏的客户端数量
+    pub fn client_count(&self) -> usize {
+        self.clients
+            .values()
+            .filter(|client| client.player.is_some())
+            .count()
+    }
+
+    // 观战中的客户端数量
+    pub fn spectator_count(&self) -> usize {
+        self.clients
+            .values()
+            .filter(|client| client.spectator)
+            .count()
+    }
+
+    fn assign_net_id(&mut self, commands: &mut Commands, entity: Entity) -> NetId {
+        self.next_net_id += 1;
+        let net_id = NetId(self.next_net_id);
+        self.net_ids.insert(entity, net_id);
+        commands.entity(entity).insert(net_id);
+        net_id
+    }
+
+    // 为新加入的玩家创建会话, 返回会话令牌
+    fn open_session(&mut self, addr: SocketAddr, player: Entity) -> u64 {
+        let mut token = rand::random::<u64>();
+        while self.sessions.contains_key(&token) {
+            token = rand::random();
+        }
+        self.sessions.insert(
+            token,
+            PlayerSession {
+                player,
+                addr: Some(addr),
+                dropped_at: None,
+            },
+        );
+        token
+    }
+
+    // 用会话令牌把 addr 的客户端重新关联到原来的玩家, 旧连接如果还在则被替换
+    fn resume_session(&mut self, addr: SocketAddr, token: u64) -> Option<Entity> {
+        let session = self.sessions.get_mut(&token)?;
+        let old_addr = session.addr.replace(addr);
+        session.dropped_at = None;
+        let player = session.player;
+        if let Some(old_addr) = old_addr.filter(|old_addr| *old_addr != addr) {
+            self.clients.remove(&old_addr);
+        }
+        Some(player)
+    }
+
+    // 客户端掉线, 玩家保留等待重连
+    fn drop_session(&mut self, token: u64) {
+        if let Some(session) = self.sessions.get_mut(&token) {
+            session.addr = None;
+            session.dropped_at = Some(Instant::now());
+        }
+    }
+
+    fn handle_packet(&mut self, addr: SocketAddr, packet: &[u8]) {
+        // 不到 KCP 包头长度的数据报不是 KCP 包, 读取 conv 之前丢弃
+        if packet.len() < kcp::KCP_OVERHEAD {
+            debug!("drop short packet from {}, len: {}", addr, packet.len());
+            return;
+        }
+        let conv = kcp::get_conv(packet);
+        let mut replaced_conv = None;
+        if let Some(client) = self.clients.get(&addr) {
+            // 还在路上的旧会话的包直接丢弃
+            if client.replaced_conv == Some(conv) {
+                return;
+            }
+            // 同一地址换了新的 KCP 会话(客户端重连), 旧连接作废, 玩家保留等待重新握手
+            if client.session.conv() != conv {
+                debug!("connection from {} replaced, conv: {}", addr, conv);
+                replaced_conv = Some(client.session.conv());
+                if let Some(token) = client.session_token {
+                    self.drop_session(token);
+                }
+                self.clients.remove(&addr);
+            }
+        }
+        let socket = self.socket.clone();
+        let client = self.clients.entry(addr).or_insert_with(|| {
+            debug!("new connection from {}, conv: {}", addr, conv);
+            RemoteClient {
+                session: KcpSession::new(conv, socket, addr),
+                player: None,
+                synced: false,
+                closing: false,
+                last_input_seq: 0,
+                input_budget: INPUT_BUDGET_SLACK,
+                session_token: None,
+                replaced_conv,
+                spectator: false,
+            }
+        });
+        if let Err(e) = client.session.input(packet) {
+            warn!("invalid packet from {}: {:?}", addr, e);
+        }
+    }
+
+    fn send(&mut self, addr: SocketAddr, message: &ServerMessage) {
+        let bytes = self.codec.encode(message);
+        if let Some(client) = self.clients.get_mut(&addr) {
+            if let Err(e) = client.session.send(&bytes) {
+                warn!("send to {} fail: {:?}", addr, e);
+            }
+        }
+    }
+
+    // 只发给已经收到完整快照的客户端
+    fn broadcast(&mut self, message: &ServerMessage) {
+        let bytes = self.codec.encode(message);
+        self.clients
+            .iter_mut()
+            .filter(|(_, client)| client.synced && !client.closing)
+            .for_each(|(addr, client)| {
+                if let Err(e) = client.session.send(&bytes) {
+                    warn!("send to {} fail: {:?}", addr, e);
+                }
+            });
+    }
+
+    fn reject(&mut self, addr: SocketAddr, reason: RejectReason) {
+        warn!("reject client {}: {}", addr, reason);
+        self.send(addr, &ServerMessage::HandshakeRejected(reason));
+        if let Some(client) = self.clients.get_mut(&addr) {
+            client.closing = true;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn server_receive_system(
+    mut commands: Commands,
+    mut server: ResMut<NetServer>,
+    time: Res<Time>,
+    rules: Res<GameRules>,
+    settings: Res<NetServerSettings>,
+    mut roster: ResMut<PlayerRoster>,
+    mut operate_query: Query<(&mut PlayerOperateState, &mut Movement), With<Player>>,
+    mut ready_query: Query<(&PlayerInfo, &mut Ready), With<Player>>,
+) {
+    let mut buf = [0; MAX_PACKET_SIZE];
+    loop {
+        match server.socket.recv_from(&mut buf) {
+            Ok((size, addr)) => server.handle_packet(addr, &buf[..size]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("server socket recv fail: {}", e);
+                break;
+            }
+        }
+    }
+
+    // 每个客户端累计的输入时长不能超过服务器经过的时间加上 INPUT_BUDGET_SLACK
+    let delta = time.delta_seconds();
+    let mut messages = Vec::new();
+    let mut mismatched = Vec::new();
+    let codec = server.codec;
+    server.clients.iter_mut().for_each(|(addr, client)| {
+        client.input_budget = (client.input_budget + delta).min(delta + INPUT_BUDGET_SLACK);
+        while let Some(bytes) = client.session.recv() {
+            match codec.decode::<ClientMessage>(&bytes) {
+                Ok(message) => messages.push((*addr, message)),
+                Err(CodecError::VersionMismatch { found, .. }) => mismatched.push((*addr, found)),
+                Err(e) => warn!("invalid message from {}: {}", addr, e),
+            }
+        }
+    });
+    for (addr, version) in mismatched {
+        server.reject(
+            addr,
+            RejectReason::VersionMismatch {
+                server: PROTOCOL_VERSION,
+                client: version,
+            },
+        );
+    }
+
+    for (addr, message) in messages {
+        let player = match server.clients.get(&addr) {
+            Some(client) if !client.closing && !client.spectator => client.player,
+            Some(client) if !client.closing => {
+                if let ClientMessage::Disconnect(reason) = message {
+                    server.clients.remove(&addr);
+                    info!("spectator {} disconnect: {:?}", addr, reason);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        match (message, player) {
+            (ClientMessage::Handshake { version, .. }, None) if version != PROTOCOL_VERSION => {
+                server.reject(
+                    addr,
+                    RejectReason::VersionMismatch {
+                        server: PROTOCOL_VERSION,
+                        client: version,
+                    },
+                );
+            }
+            (
+                ClientMessage::Handshake {
+                    name,
+                    role: ClientRole::Spectator,
+                    ..
+                },
+                None,
+            ) => {
+                if let Some(client) = server.clients.get_mut(&addr) {
+                    client.spectator = true;
+                }
+                server.send(addr, &ServerMessage::SpectatorAccepted);
+                info!("{} spectate game from {}", name, addr);
+            }
+            (
+                ClientMessage::Handshake {
+                    name,
+                    session_token: Some(token),
+                    ..
+                },
+                None,
+            ) if server.sessions.contains_key(&token) => {
+                let resumed = server
+                    .resume_session(addr, token)
+                    .and_then(|player| server.net_ids.get(&player).map(|id| (player, *id)));
+                if let Some((player, net_id)) = resumed {
+                    if let Some(client) = server.clients.get_mut(&addr) {
+                        client.player = Some(player);
+                        client.session_token = Some(token);
+                    }
+                    server.send(
+                        addr,
+                        &ServerMessage::HandshakeAccepted {
+                            player_id: net_id.0,
+                            session_token: token,
+                        },
+                    );
+                    info!("{} rejoin game from {}", name, addr);
+                }
+            }
+            (ClientMessage::Handshake { .. }, None)
+                if roster.player_num() >= rules.max_player_num =>
+            {
+                server.reject(
+                    addr,
+                    RejectReason::ServerFull {
+                        max_player_num: rules.max_player_num,
+                    },
+                );
+            }
+            (ClientMessage::Handshake { name, .. }, None) => {
+                let entity = roster.join(&mut commands, &rules, Some(name.clone()));
+                let net_id = server.assign_net_id(&mut commands, entity);
+                commands.entity(entity).insert(RemoteControlled);
+                let token = server.open_session(addr, entity);
+                if let Some(client) = server.clients.get_mut(&addr) {
+                    client.player = Some(entity);
+                    client.session_token = Some(token);
+                }
+                server.send(
+                    addr,
+                    &ServerMessage::HandshakeAccepted {
+                        player_id: net_id.0,
+                        session_token: token,
+                    },
+                );
+                info!("{} join game from {}", name, addr);
+            }
+            (ClientMessage::InputFrame(frame), Some(player)) => {
+                let InputFrame { seq, dt, .. } = frame;
+                let dt = match server.clients.get_mut(&addr) {
+                    Some(client) if seq > client.last_input_seq => {
+                        client.last_input_seq = seq;
+                        let dt = if dt.is_finite() {
+                            dt.clamp(0.0, MAX_INPUT_DT)
+                        } else {
+                            0.0
+                        };
+                        // 超出预算的部分不移动
+                        let accepted = dt.min(client.input_budget);
+                        client.input_budget -= accepted;
+                        if accepted < dt {
+                            debug!(
+                                "input from {} over budget, dt: {} -> {}",
+                                addr, dt, accepted
+                            );
+                        }
+                        accepted
+                    }
+                    _ => continue,
+                };
+                // 每个输入帧按客户端记录的时长移动, 客户端才能准确重放未确认的输入
+                if let Ok((mut state, mut movement)) = operate_query.get_mut(player) {
+                    *state = frame.state();
+                    let offset = movement_offset(&state, movement.speed, dt);
+                    movement.pendding_offset += offset;
+                }
+            }
+            (ClientMessage::Ready(ready), Some(player)) => {
+                if let Ok((info, mut current)) = ready_query.get_mut(player) {
+                    current.0 = ready;
+                    info!("{} ready: {}", info.name, ready);
+                }
+            }
+            (ClientMessage::Disconnect(reason), _) => {
+                if let Some(client) = server.clients.remove(&addr) {
+                    if let Some(player) = client.player {
+                        commands.entity(player).insert(Leaving);
+                    }
+                    if let Some(token) = client.session_token {
+                        server.sessions.remove(&token);
+                    }
+                    info!("client {} disconnect: {:?}", addr, reason);
+                }
+            }
+            (message, _) => warn!("unexpected message from {}: {:?}", addr, message),
+        }
+    }
+
+    let timeout = settings.client_timeout_seconds;
+    let timeout_clients: Vec<SocketAddr> = server
+        .clients
+        .iter()
+        .filter(|(_, client)| client.session.idle_time().as_secs_f32() > timeout)
+        .map(|(addr, _)| *addr)
+        .collect();
+    for addr in timeout_clients {
+        if let Some(client) = server.clients.remove(&addr) {
+            if let Some(token) = client.session_token {
+                server.drop_session(token);
+            }
+            info!(
+                "client {} disconnect: {:?}",
+                addr,
+                DisconnectReason::Timeout
+            );
+        }
+    }
+
+    // 宽限期内没有重连的玩家离开比赛
+    let grace = Duration::from_secs_f32(settings.reconnect_grace_seconds.max(0.0));
+    let expired: Vec<u64> = server
+        .sessions
+        .iter()
+        .filter(|(_, session)| {
+            session
+                .dropped_at
+                .iter()
+                .any(|dropped_at| dropped_at.elapsed() > grace)
+        })
+        .map(|(token, _)| *token)
+        .collect();
+    for token in expired {
+        if let Some(session) = server.sessions.remove(&token) {
+            info!(
+                "player {:?} not reconnect in time, leave game",
+                session.player
+            );
+            commands.entity(session.player).insert(Leaving);
+        }
+    }
+}
+
+// 给需要同步的实体分配 NetId
+#[allow(clippy::type_complexity)]
+fn net_id_assign_system(
+    mut commands: Commands,
+    mut server: ResMut<NetServer>,
+    query: Query<Entity, (Or<(With<Player>, With<Coin>)>, Without<NetId>)>,
+) {
+    query.for_each(|entity| {
+        server.assign_net_id(&mut commands, entity);
+    });
+}
+
+// 新加入的客户端发送完整快照, 其余客户端只发送变化量
+#[allow(clippy::type_complexity)]
+fn snapshot_broadcast_system(
+    mut server: ResMut<NetServer>,
+    state: Res<State<MatchState>>,
+    game_state: Res<GameState>,
+    player_query: Query<
+        (
+            &NetId,
+            &PlayerInfo,
+            &Team,
+            &Score,
+            &Ready,
+            &PlayerColor,
+            &Transform,
+        ),
+        With<Player>,
+    >,
+    movement_query: Query<(&Transform, &Movement), With<Player>>,
+    coin_query: Query<(&NetId, &CoinInfo, &Transform), With<Coin>>,
+    departed_scores: Res<DepartedScores>,
+) {
+    server.tick += 1;
+    let snapshot = WorldSnapshot {
+        tick: server.tick,
+        match_state: state.current().clone(),
+        result: game_state.result().cloned(),
+        players: player_query
+            .iter()
+            .map(
+                |(id, info, team, score, ready, color, transform)| PlayerSnapshot {
+                    id: id.0,
+                    name: info.name.clone(),
+                    team_id: team.id,
+                    score: score.val,
+                    ready: ready.0,
+                    color: [color.0.r(), color.0.g(), color.0.b(), color.0.a()],
+                    position: transform.translation.truncate(),
+                },
+            )
+            .collect(),
+        coins: coin_query
+            .iter()
+            .map(|(id, info, transform)| CoinSnapshot {
+                id: id.0,
+                score_value: info.score_value,
+                position: transform.translation.truncate(),
+            })
+            .collect(),
+    };
+
+    if let Some(prev) = server.last_snapshot.take() {
+        if prev.match_state != snapshot.match_state || prev.result != snapshot.result {
+            server.broadcast(&ServerMessage::MatchStateChanged {
+                state: snapshot.match_state.clone(),
+                result: snapshot.result.clone(),
+            });
+        }
+        // 没有变化也发送, 客户端据此推进快照时间线
+        let delta = snapshot.delta_from(&prev);
+        server.broadcast(&ServerMessage::EntityDelta(delta));
+    }
+
+    // 中途加入的客户端(包括观战者)还需要当前的队伍总分
+    let mut team_scores = departed_scores.0.clone();
+    snapshot.players.iter().for_each(|player| {
+        *team_scores.entry(player.team_id).or_insert(0) += player.score;
+    });
+    let mut full = vec![server
+        .codec
+        .encode(&ServerMessage::EntitySnapshot(snapshot.clone()))];
+    full.extend(team_scores.into_iter().map(|(team_id, team_score)| {
+        server.codec.encode(&ServerMessage::ScoreUpdate {
+            team_id,
+            team_score,
+        })
+    }));
+    server
+        .clients
+        .iter_mut()
+        .filter(|(_, client)| {
+            (client.player.is_some() || client.spectator) && !client.synced && !client.closing
+        })
+        .for_each(|(addr, client)| {
+            match full.iter().try_for_each(|bytes| client.session.send(bytes)) {
+                Ok(_) => client.synced = true,
+                Err(e) => warn!("send to {} fail: {:?}", addr, e),
+            }
+        });
+    server.last_snapshot = Some(snapshot);
+
+    // 告知每个客户端其输入的处理进度, 用于客户端预测的校正
+    let NetServer { clients, codec, .. } = &mut *server;
+    clients
+        .iter_mut()
+        .filter(|(_, client)| client.synced && !client.closing)
+        .for_each(|(addr, client)| {
+            // 已收到但还没到 tick 执行的位移也算在内
+            let position = match client.player.map(|player| movement_query.get(player)) {
+                Some(Ok((transform, movement))) => {
+                    (transform.translation + movement.pendding_offset).truncate()
+                }
+                _ => return,
+            };
+            let ack = ServerMessage::InputAck {
+                seq: client.last_input_seq,
+                position,
+            };
+            if let Err(e) = client.session.send(&codec.encode(&ack)) {
+                warn!("send to {} fail: {:?}", addr, e);
+            }
+        });
+}
+
+// 把服务器上的游戏事件转发给客户端
+fn event_broadcast_system(
+    mut server: ResMut<NetServer>,
+    mut team_score_events: EventReader<TeamScoreChangedEvent>,
+    mut elapsed_events: EventReader<ElapsedSecondChangedEvent>,
+    mut coin_events: EventReader<CoinPickedupEvent>,
+    removed: RemovedComponents<NetId>,
+) {
+    for TeamScoreChangedEvent {
+        team_score,
+        team_id,
+    } in team_score_events.iter()
+    {
+        server.broadcast(&ServerMessage::ScoreUpdate {
+            team_id: *team_id,
+            team_score: *team_score,
+        });
+    }
+    for ElapsedSecondChangedEvent { seconds } in elapsed_events.iter() {
+        server.broadcast(&ServerMessage::ElapsedSeconds { seconds: *seconds });
+    }
+    for CoinPickedupEvent { coin } in coin_events.iter() {
+        if let Some(id) = server.net_ids.get(coin).cloned() {
+            server.broadcast(&ServerMessage::CoinPickedup { coin_id: id.0 });
+        }
+    }
+    removed.iter().for_each(|entity| {
+        server.net_ids.remove(&entity);
+    });
+}
+
+fn server_flush_system(mut server: ResMut<NetServer>) {
+    let NetServer { clients, clock, .. } = &mut *server;
+    clients.iter_mut().for_each(|(addr, client)| {
+        if let Err(e) = client.session.update(clock) {
+            warn!("flush to {} fail: {:?}", addr, e);
+        }
+    });
+    clients.retain(|_, client| !client.closing);
+}
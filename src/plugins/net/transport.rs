@@ -0,0 +1,164 @@
+use std::io::{self, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use kcp::Kcp;
+
+use super::conditions::{NetConditions, NetSimulator};
+
+// 单个 UDP 包的最大长度
+pub const MAX_PACKET_SIZE: usize = 1500;
+
+// 创建非阻塞的 UDP socket, 由 bevy 系统每帧轮询
+pub fn bind_socket<A: ToSocketAddrs>(addr: A) -> io::Result<Arc<NetSocket>> {
+    bind_socket_with(addr, &NetConditions::default())
+}
+
+// 创建发出的包经过网络状况模拟的 socket
+pub fn bind_socket_with<A: ToSocketAddrs>(
+    addr: A,
+    conditions: &NetConditions,
+) -> io::Result<Arc<NetSocket>> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_nonblocking(true)?;
+    let simulator = if conditions.is_ideal() {
+        None
+    } else {
+        Some(Mutex::new(NetSimulator::new(conditions.clone())))
+    };
+    Ok(Arc::new(NetSocket { socket, simulator }))
+}
+
+// KCP 会话和 UDP socket 之间的一层, 可以注入延迟, 抖动, 乱序, 重复和丢包
+pub struct NetSocket {
+    socket: UdpSocket,
+    simulator: Option<Mutex<NetSimulator>>,
+}
+
+impl NetSocket {
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn conditions(&self) -> NetConditions {
+        match &self.simulator {
+            Some(simulator) => simulator.lock().unwrap().conditions().clone(),
+            None => Default::default(),
+        }
+    }
+
+    pub fn send_to(&self, buf: &[u8], peer: SocketAddr) -> io::Result<usize> {
+        match &self.simulator {
+            Some(simulator) => simulator.lock().unwrap().send_to(&self.socket, buf, peer),
+            None => self.socket.send_to(buf, peer),
+        }
+    }
+
+    // 接收前先把到期的延迟包发出去, 各系统每帧都会轮询接收
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        if let Some(simulator) = &self.simulator {
+            simulator
+                .lock()
+                .unwrap()
+                .flush(&self.socket, Instant::now())?;
+        }
+        self.socket.recv_from(buf)
+    }
+}
+
+// KCP 的底层输出, 把 KCP 分片写到 socket 上
+pub struct UdpOutput {
+    socket: Arc<NetSocket>,
+    peer: SocketAddr,
+}
+
+impl Write for UdpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.send_to(buf, self.peer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// KCP 需要的毫秒时钟
+pub struct KcpClock(Instant);
+
+impl Default for KcpClock {
+    fn default() -> Self {
+        KcpClock(Instant::now())
+    }
+}
+
+impl KcpClock {
+    pub fn now_millis(&self) -> u32 {
+        self.0.elapsed().as_millis() as u32
+    }
+}
+
+// 一条 KCP 会话 (一个对端), 以消息为单位收发
+pub struct KcpSession {
+    conv: u32,
+    kcp: Kcp<UdpOutput>,
+    peer: SocketAddr,
+    last_active: Instant,
+}
+
+impl KcpSession {
+    pub fn new(conv: u32, socket: Arc<NetSocket>, peer: SocketAddr) -> Self {
+        let mut kcp = Kcp::new(conv, UdpOutput { socket, peer });
+        // 游戏场景使用极速模式: 10ms 刷新, 快速重传, 关闭拥塞控制
+        kcp.set_nodelay(true, 10, 2, true);
+        kcp.set_wndsize(256, 256);
+        Self {
+            conv,
+            kcp,
+            peer,
+            last_active: Instant::now(),
+        }
+    }
+
+    pub fn conv(&self) -> u32 {
+        self.conv
+    }
+
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    // 距离上次收到对端数据的时间
+    pub fn idle_time(&self) -> Duration {
+        self.last_active.elapsed()
+    }
+
+    // 喂入从 socket 收到的原始 UDP 包
+    pub fn input(&mut self, packet: &[u8]) -> kcp::KcpResult<()> {
+        self.kcp.input(packet)?;
+        self.last_active = Instant::now();
+        Ok(())
+    }
+
+    pub fn send(&mut self, message: &[u8]) -> kcp::KcpResult<()> {
+        self.kcp.send(message).map(|_| ())
+    }
+
+    // 取出一条完整的消息, 没有时返回 None
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        let size = self.kcp.peeksize().ok()?;
+        let mut buf = vec![0; size];
+        match self.kcp.recv(&mut buf) {
+            Ok(n) => {
+                buf.truncate(n);
+                Some(buf)
+            }
+            Err(_) => None,
+        }
+    }
+
+    // 驱动 KCP 的重传与发送, 需要周期性调用
+    pub fn update(&mut self, clock: &KcpClock) -> kcp::KcpResult<()> {
+        self.kcp.update(clock.now_millis())
+    }
+}
@@ -1,38 +1,68 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use super::coin::CoinPickedupEvent;
-use super::game::GameRules;
-use super::{coin::Coin, input_ext::PlayerOperate};
-use super::{coin::CoinInfo, input_ext::PlayerInputSettings};
+use super::coin::{Coin, CoinInfo};
+use super::game::{
+    AddSimulationEvent, Collider, GameRules, GameRulesChangedEvent, GameStage, GameSystem,
+    SIMULATION_TICK,
+};
+use super::input_ext::{
+    AnalogInputs, PlayerInputSettings, PlayerOperate, PlayerOperateState, SeatInputs,
+};
 use bevy::{prelude::*, sprite::collide_aabb::collide};
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.insert_resource(PlayerInputSettings::from_array(&vec![
-            (KeyCode::W, PlayerOperate::MoveFrond),
-            (KeyCode::S, PlayerOperate::MoveBack),
-            (KeyCode::A, PlayerOperate::MoveLeft),
-            (KeyCode::D, PlayerOperate::MoveRight),
-        ]));
-        app.add_event::<IncreasePlayerScoreEvent>()
+        app.add_simulation_event::<IncreasePlayerScoreEvent>()
             .add_event::<TeamScoreChangedEvent>()
+            .add_event::<PlayerJoinedEvent>()
+            .add_event::<PlayerLeftEvent>()
+            .init_resource::<SpawnLocalPlayer>()
+            .init_resource::<LocalPlayerName>()
+            .init_resource::<PlayerRoster>()
+            .init_resource::<DepartedScores>()
             .add_startup_system(setup.system())
-            .add_system(player_score_update_system.system())
-            .add_system(
-                player_movement_system
-                    .system()
-                    .label(PlayerSystem::PlayerMoving),
-            )
-            .add_system(
-                player_input_system
-                    .system()
-                    .before(PlayerSystem::PlayerMoving),
-            )
-            .add_system(
-                player_collision_system
-                    .system()
-                    .after(PlayerSystem::PlayerMoving),
+            .add_system(player_joined_system.system())
+            .add_system(player_leave_system.system())
+            .add_system_set_to_stage(
+                GameStage::Simulation,
+                SystemSet::new()
+                    .with_system(
+                        local_player_input_system
+                            .system()
+                            .label(PlayerSystem::LocalInput)
+                            .before(PlayerSystem::PlayerInput),
+                    )
+                    .with_system(
+                        player_input_system
+                            .system()
+                            .label(PlayerSystem::PlayerInput)
+                            .before(PlayerSystem::PlayerMoving),
+                    )
+                    .with_system(
+                        player_movement_system
+                            .system()
+                            .label(PlayerSystem::PlayerMoving),
+                    )
+                    .with_system(
+                        rules_changed_system
+                            .system()
+                            .before(PlayerSystem::PlayerCollision),
+                    )
+                    .with_system(
+                        player_collision_system
+                            .system()
+                            .label(PlayerSystem::PlayerCollision)
+                            .after(PlayerSystem::PlayerMoving),
+                    )
+                    .with_system(
+                        player_score_update_system
+                            .system()
+                            .label(PlayerSystem::PlayerScore)
+                            .after(PlayerSystem::PlayerCollision)
+                            .before(GameSystem::MatchStateUpdate),
+                    ),
             );
     }
 }
@@ -47,109 +77,351 @@ pub struct TeamScoreChangedEvent {
     pub team_id: usize,
 }
 
+// 玩家实体生成后发送, 本地、服务器和客户端镜像的玩家都会触发
+pub struct PlayerJoinedEvent {
+    pub player: Entity,
+    pub name: String,
+    pub team_id: usize,
+}
+
+// 玩家离开后发送, 此时实体已经销毁, score 为离开时的分数
+pub struct PlayerLeftEvent {
+    pub player: Entity,
+    pub name: String,
+    pub team_id: usize,
+    pub score: usize,
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
-enum PlayerSystem {
+pub enum PlayerSystem {
+    LocalInput,
+    PlayerInput,
     PlayerMoving,
+    PlayerCollision,
+    PlayerScore,
 }
 
 pub struct Player;
 
-struct PlayerInfo {
-    name: String, // 玩家名称
+// 由本机键盘控制的玩家
+pub struct LocalPlayer;
+
+// 本地玩家使用的按键映射座位, 没有该组件的本地玩家使用第 0 个座位
+pub struct LocalSeat(pub usize);
+
+// 由网络输入帧驱动移动的玩家, 不再按模拟 tick 移动
+pub struct RemoteControlled;
+
+// 启动时是否生成本机键盘控制的玩家, 帧同步等模式由各自的插件生成玩家
+pub struct SpawnLocalPlayer(pub bool);
+
+impl Default for SpawnLocalPlayer {
+    fn default() -> Self {
+        SpawnLocalPlayer(true)
+    }
 }
 
-struct Team {
-    id: usize,
+// 第 0 个座位的本地玩家名称, None 时按位置命名
+#[derive(Default)]
+pub struct LocalPlayerName(pub Option<String>);
+
+pub struct PlayerInfo {
+    pub name: String, // 玩家名称
 }
 
-struct Score {
-    val: usize,
+pub struct Team {
+    pub id: usize,
 }
 
-struct Movement {
-    speed: f32,
-    pendding_offset: Vec3,
+pub struct Score {
+    pub val: usize,
+    pub coins: usize, // 本局吃到的金币数
 }
 
-fn setup(
-    mut commands: Commands,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    rules: Res<GameRules>,
-) {
-    let player_color = Color::rgb(0.5, 0.5, 1.0);
+// 玩家颜色, 由 PresentationPlugin 用来显示
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerColor(pub Color);
+
+// 标记玩家要离开比赛, player_leave_system 保留其分数后销毁实体
+pub struct Leaving;
+
+// 大厅阶段玩家是否已准备
+pub struct Ready(pub bool);
+
+pub struct Movement {
+    pub speed: f32,
+    pub pendding_offset: Vec3,
+}
+
+// 按位置轮流使用的玩家颜色, 相邻位置分属两个队伍
+const PLAYER_COLORS: [Color; 6] = [
+    Color::rgb(1.0, 0.4, 0.3),
+    Color::rgb(0.5, 0.5, 1.0),
+    Color::rgb(1.0, 0.65, 0.0),
+    Color::rgb(0.25, 0.9, 0.8),
+    Color::rgb(1.0, 0.5, 0.8),
+    Color::rgb(0.6, 0.4, 1.0),
+];
+
+// 生成玩家需要的信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerSpawn {
+    pub name: String,
+    pub team_id: usize,
+    pub color: Color,
+    pub position: Vec3,
+}
+
+impl PlayerSpawn {
+    // 第 slot 个位置的玩家: 轮流分配到两个队伍, 颜色和出生点按位置循环使用
+    pub fn for_slot(rules: &GameRules, slot: usize, name: Option<String>) -> Self {
+        let position = if rules.spawn_points.is_empty() {
+            Vec2::ZERO
+        } else {
+            rules.spawn_points[slot % rules.spawn_points.len()]
+        };
+        PlayerSpawn {
+            name: name.unwrap_or_else(|| format!("Player {}", slot)),
+            team_id: slot % 2 + 1,
+            color: PLAYER_COLORS[slot % PLAYER_COLORS.len()],
+            position: position.extend(0.0),
+        }
+    }
+}
+
+// 比赛中玩家占用的位置, 玩家离开后空出的位置给下一个加入的玩家
+#[derive(Default)]
+pub struct PlayerRoster {
+    slots: Vec<Option<Entity>>,
+}
+
+impl PlayerRoster {
+    pub fn player_num(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    pub fn slot_of(&self, player: Entity) -> Option<usize> {
+        self.slots.iter().position(|slot| *slot == Some(player))
+    }
+
+    // 按位置顺序遍历在场玩家
+    pub fn players(&self) -> impl Iterator<Item = (usize, Entity)> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, player)| player.map(|player| (slot, player)))
+    }
+
+    // 占用第一个空位置并生成玩家, name 为 None 时使用默认名称
+    pub fn join(
+        &mut self,
+        commands: &mut Commands,
+        rules: &GameRules,
+        name: Option<String>,
+    ) -> Entity {
+        let slot = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(self.slots.len());
+        let player = spawn_player(commands, rules, PlayerSpawn::for_slot(rules, slot, name));
+        if slot == self.slots.len() {
+            self.slots.push(Some(player));
+        } else {
+            self.slots[slot] = Some(player);
+        }
+        player
+    }
+
+    // 让出玩家占用的位置, 不会销毁玩家实体
+    pub fn leave(&mut self, player: Entity) {
+        if let Some(slot) = self.slot_of(player) {
+            self.slots[slot] = None;
+        }
+    }
+}
+
+// 已离开玩家留给队伍的分数, 队伍总分不会因为有人中途离开而减少
+#[derive(Default)]
+pub struct DepartedScores(pub BTreeMap<usize, usize>);
+
+// 各队伍当前的总分, 包含已离开玩家留下的分数, 按队伍 id 排序
+pub fn team_scores<'a>(
+    players: impl Iterator<Item = (&'a Team, &'a Score)>,
+    departed_scores: Option<&DepartedScores>,
+) -> BTreeMap<usize, usize> {
+    let mut scores = departed_scores
+        .map(|departed| departed.0.clone())
+        .unwrap_or_default();
+    players.for_each(|(team, score)| *scores.entry(team.id).or_default() += score.val);
+    scores
+}
+
+// 生成一个玩家实体, 本地玩家和网络玩家共用. 只包含玩法组件, 显示由 PresentationPlugin 添加
+pub fn spawn_player(commands: &mut Commands, rules: &GameRules, spawn: PlayerSpawn) -> Entity {
     commands
         .spawn()
         .insert_bundle((Player,))
         .insert_bundle((
-            PlayerInfo {
-                name: "Player 0".to_string(),
-                // color: player_color,
-            },
-            Team { id: 1 },
-            Score { val: 0 },
+            PlayerInfo { name: spawn.name },
+            Team { id: spawn.team_id },
+            Score { val: 0, coins: 0 },
+            PlayerColor(spawn.color),
+            Ready(false),
             Movement {
                 speed: 500.0,
                 pendding_offset: Default::default(),
             },
+            PlayerOperateState::default(),
+        ))
+        .insert_bundle((
+            Collider {
+                size: rules.player_brick_size,
+            },
+            Transform::from_translation(spawn.position),
+            GlobalTransform::default(),
         ))
-        .insert_bundle(SpriteBundle {
-            material: materials.add(player_color.into()),
-            transform: Transform::from_xyz(0.0, -215.0, 0.0),
-            sprite: Sprite::new(rules.player_brick_size),
-            ..Default::default()
+        .id()
+}
+
+// 每个本地座位生成一个本地玩家
+fn setup(
+    mut commands: Commands,
+    rules: Res<GameRules>,
+    settings: Res<PlayerInputSettings>,
+    spawn_local_player: Res<SpawnLocalPlayer>,
+    local_name: Res<LocalPlayerName>,
+    mut roster: ResMut<PlayerRoster>,
+) {
+    if !spawn_local_player.0 {
+        return;
+    }
+    (0..settings.seat_num().max(1)).for_each(|seat| {
+        let name = match seat {
+            0 => local_name.0.clone(),
+            _ => None,
+        };
+        let player = roster.join(&mut commands, &rules, name);
+        commands
+            .entity(player)
+            .insert_bundle((LocalPlayer, LocalSeat(seat)));
+    });
+}
+
+fn player_joined_system(
+    query: Query<(Entity, &PlayerInfo, &Team), Added<Player>>,
+    mut events: EventWriter<PlayerJoinedEvent>,
+) {
+    query.for_each(|(player, info, team)| {
+        info!("{} join game, team {}", info.name, team.id);
+        events.send(PlayerJoinedEvent {
+            player,
+            name: info.name.clone(),
+            team_id: team.id,
         });
+    });
 }
 
-fn player_input_system(
+// 离开的玩家把分数留给队伍, 让出位置后销毁
+#[allow(clippy::type_complexity)]
+fn player_leave_system(
+    mut commands: Commands,
+    query: Query<(Entity, &PlayerInfo, &Team, &Score), (With<Player>, With<Leaving>)>,
+    mut roster: ResMut<PlayerRoster>,
+    mut departed_scores: ResMut<DepartedScores>,
+    mut events: EventWriter<PlayerLeftEvent>,
+) {
+    query.for_each(|(player, info, team, score)| {
+        info!(
+            "{} leave game, {} score left to team {}",
+            info.name, score.val, team.id
+        );
+        *departed_scores.0.entry(team.id).or_insert(0) += score.val;
+        roster.leave(player);
+        commands.entity(player).despawn();
+        events.send(PlayerLeftEvent {
+            player,
+            name: info.name.clone(),
+            team_id: team.id,
+            score: score.val,
+        });
+    });
+}
+
+fn local_player_input_system(
     input: Res<Input<PlayerOperate>>,
-    time: Res<Time>,
-    mut query: Query<&mut Movement, With<Player>>,
+    seat_inputs: Res<SeatInputs>,
+    analog_inputs: Res<AnalogInputs>,
+    mut query: Query<(Option<&LocalSeat>, &mut PlayerOperateState), With<LocalPlayer>>,
 ) {
-    if let Ok(mut movement) = query.single_mut() {
-        let mut direction = Vec2::new(0.0, 0.0);
-        if input.pressed(PlayerOperate::MoveFrond) {
-            direction.y += 1.0;
-        }
-        if input.pressed(PlayerOperate::MoveBack) {
-            direction.y += -1.0;
-        }
-        if input.pressed(PlayerOperate::MoveLeft) {
-            direction.x += -1.0;
-        }
-        if input.pressed(PlayerOperate::MoveRight) {
-            direction.x += 1.0;
-        }
-        let speed = movement.speed;
+    query.iter_mut().for_each(|(seat, mut state)| {
+        let seat = seat.map_or(0, |seat| seat.0);
+        let seat_input = match seat {
+            0 => Some(&*input),
+            seat => seat_inputs.get(seat),
+        };
+        *state = seat_input
+            .map(PlayerOperateState::from_input)
+            .unwrap_or_default()
+            .with_analog(analog_inputs.get(seat));
+    });
+}
 
-        movement.pendding_offset +=
-            Vec3::new(direction.x, direction.y, 0.0) * speed * time.delta_seconds();
-    }
+// 疾跑和冲刺时的速度倍数, 同时按下时取冲刺
+pub const SPRINT_SPEED_SCALE: f32 = 1.5;
+pub const DASH_SPEED_SCALE: f32 = 2.5;
+
+// 按下 state 中的操作持续 dt 秒产生的位移, 摇杆没推到底时速度按比例减小. 服务器和客户端预测共用
+pub fn movement_offset(state: &PlayerOperateState, speed: f32, dt: f32) -> Vec3 {
+    let direction = state.direction();
+    let scale = if state.pressed(PlayerOperate::Dash) {
+        DASH_SPEED_SCALE
+    } else if state.pressed(PlayerOperate::Sprint) {
+        SPRINT_SPEED_SCALE
+    } else {
+        1.0
+    };
+    Vec3::new(direction.x, direction.y, 0.0) * speed * scale * dt
+}
+
+#[allow(clippy::type_complexity)]
+fn player_input_system(
+    mut query: Query<
+        (&PlayerOperateState, &mut Movement),
+        (With<Player>, Without<RemoteControlled>),
+    >,
+) {
+    query.iter_mut().for_each(|(state, mut movement)| {
+        let offset = movement_offset(state, movement.speed, SIMULATION_TICK as f32);
+        movement.pendding_offset += offset;
+    });
 }
 
 fn player_movement_system(mut query: Query<(&mut Movement, &mut Transform), With<Player>>) {
-    if let Ok((mut movement, mut transform)) = query.single_mut() {
+    query.iter_mut().for_each(|(mut movement, mut transform)| {
         transform.translation += movement.pendding_offset;
         movement.pendding_offset = Vec3::new(0.0, 0.0, 0.0);
-    }
+    });
 }
 
 fn player_collision_system(
-    player_query: Query<(Entity, &PlayerInfo, &Transform, &Team, &Sprite), With<Player>>,
-    collision_query: Query<(Entity, &Transform, &Sprite, &CoinInfo), With<Coin>>,
+    player_query: Query<(Entity, &PlayerInfo, &Transform, &Team, &Collider), With<Player>>,
+    collision_query: Query<(Entity, &Transform, &Collider, &CoinInfo), With<Coin>>,
     mut coin_pickedup_event: EventWriter<CoinPickedupEvent>,
     mut increase_score_event: EventWriter<IncreasePlayerScoreEvent>,
 ) {
     collision_query.for_each(
-        |(coin_entity, pickup_transform, pickup_sprite, coin_info)| {
-            for (player_entity, player, player_transform, _, player_sprite) in player_query.iter() {
+        |(coin_entity, pickup_transform, pickup_collider, coin_info)| {
+            for (player_entity, player, player_transform, _, player_collider) in player_query.iter()
+            {
                 let collision = collide(
                     player_transform.translation,
-                    player_sprite.size,
+                    player_collider.size,
                     pickup_transform.translation,
-                    pickup_sprite.size,
+                    pickup_collider.size,
                 );
 
-                if let Some(_) = collision {
+                if collision.is_some() {
                     debug!("{} collect the coin({:?})!", player.name, coin_entity);
 
                     increase_score_event.send(IncreasePlayerScoreEvent {
@@ -169,8 +441,10 @@ fn player_score_update_system(
     mut query: Query<(Entity, &mut Score, &PlayerInfo, &Team), With<Player>>,
     mut events: EventReader<IncreasePlayerScoreEvent>,
     mut team_score_changed_event: EventWriter<TeamScoreChangedEvent>,
+    departed_scores: Res<DepartedScores>,
 ) {
-    let mut team_score_map = HashMap::new();
+    // 按队伍 id 排序, 保证事件顺序在所有节点上一致
+    let mut team_score_map = BTreeMap::new();
 
     events.iter().for_each(
         |IncreasePlayerScoreEvent {
@@ -180,14 +454,16 @@ fn player_score_update_system(
             match query.get_mut(*player) {
                 Ok((_, mut score, _, team)) => {
                     score.val += score_to_increase;
-                    team_score_map.insert(team.id, 0);
+                    score.coins += 1;
+                    let departed = departed_scores.0.get(&team.id).cloned().unwrap_or(0);
+                    team_score_map.insert(team.id, departed);
                 }
                 Err(e) => error!("{}", e),
             }
         },
     );
 
-    query.for_each_mut(|(_, score, player_info, team)| {
+    query.iter_mut().for_each(|(_, score, player_info, team)| {
         if let Some(team_score) = team_score_map.get_mut(&team.id) {
             *team_score += score.val;
             info!("{} get coin, {} score now!", player_info.name, score.val);
@@ -201,3 +477,16 @@ fn player_score_update_system(
         });
     });
 }
+
+// 规则重新读取后玩家换成新的尺寸
+fn rules_changed_system(
+    rules: Res<GameRules>,
+    mut rules_events: EventReader<GameRulesChangedEvent>,
+    mut query: Query<&mut Collider, With<Player>>,
+) {
+    if rules_events.iter().last().is_some() {
+        query
+            .iter_mut()
+            .for_each(|mut collider| collider.size = rules.player_brick_size);
+    }
+}
@@ -0,0 +1,80 @@
+use super::coin::Coin;
+use super::game::Collider;
+use super::player::{Player, PlayerColor};
+use bevy::prelude::*;
+
+// 给玩法实体加上精灵显示, 无渲染的服务器不添加该插件
+pub struct PresentationPlugin;
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<PresentationMaterials>()
+            .add_system_to_stage(CoreStage::PostUpdate, player_sprite_system.system())
+            .add_system_to_stage(CoreStage::PostUpdate, coin_sprite_system.system())
+            .add_system_to_stage(CoreStage::PostUpdate, sprite_size_system.system());
+    }
+}
+
+pub struct PresentationMaterials {
+    pub coin: Handle<ColorMaterial>,
+}
+
+impl FromWorld for PresentationMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        PresentationMaterials {
+            coin: materials.add(Color::GOLD.into()),
+        }
+    }
+}
+
+fn sprite_bundle(
+    material: Handle<ColorMaterial>,
+    collider: &Collider,
+    transform: &Transform,
+) -> SpriteBundle {
+    SpriteBundle {
+        material,
+        sprite: Sprite::new(collider.size),
+        transform: *transform,
+        ..Default::default()
+    }
+}
+
+// 每个玩家按自己的颜色显示
+fn player_sprite_system(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<(Entity, &PlayerColor, &Collider, &Transform), Added<Player>>,
+) {
+    query.for_each(|(entity, color, collider, transform)| {
+        commands.entity(entity).insert_bundle(sprite_bundle(
+            materials.add(color.0.into()),
+            collider,
+            transform,
+        ));
+    });
+}
+
+fn coin_sprite_system(
+    mut commands: Commands,
+    materials: Res<PresentationMaterials>,
+    query: Query<(Entity, &Collider, &Transform), Added<Coin>>,
+) {
+    query.for_each(|(entity, collider, transform)| {
+        commands.entity(entity).insert_bundle(sprite_bundle(
+            materials.coin.clone(),
+            collider,
+            transform,
+        ));
+    });
+}
+
+// 碰撞尺寸随规则变化时精灵跟着变化
+fn sprite_size_system(mut query: Query<(&Collider, &mut Sprite), Changed<Collider>>) {
+    query.iter_mut().for_each(|(collider, mut sprite)| {
+        if sprite.size != collider.size {
+            sprite.size = collider.size;
+        }
+    });
+}
@@ -0,0 +1,865 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::app::{AppExit, Events};
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use super::coin::Coin;
+use super::game::{
+    reset_match, GameRng, GameRules, GameRulesChangedEvent, GameSeed, GameStage, GameState,
+    MatchResult, MatchState, SimulationClock, SIMULATION_TICK,
+};
+use super::game_mode::GameMode;
+use super::input_ext::{PlayerOperate, PlayerOperateState};
+use super::player::{
+    DepartedScores, Movement, Player, PlayerInfo, PlayerLeftEvent, PlayerRoster, PlayerSpawn,
+    PlayerSystem, Ready, RemoteControlled, Score, SpawnLocalPlayer, Team, TeamScoreChangedEvent,
+};
+use super::rules::RulesReloadSettings;
+
+// 命令行参数 --replay <file> 回放录像
+pub const REPLAY_ARG: &str = "--replay";
+// 录像文件格式版本, 格式变化后旧录像不能回放
+pub const REPLAY_VERSION: u16 = 6;
+
+// 录像文件的最大字节数, 超过的文件不读取也不写入
+pub const MAX_REPLAY_SIZE: u64 = 16 * 1024 * 1024;
+
+const REPLAY_MAGIC: [u8; 4] = *b"TBGR";
+const HEADER_SIZE: usize = 6;
+const REPLAY_DIR: &str = "replays";
+
+// 回放控制按键
+pub const REPLAY_PAUSE_KEY: KeyCode = KeyCode::Space;
+pub const REPLAY_SEEK_BACK_KEY: KeyCode = KeyCode::Left;
+pub const REPLAY_SEEK_FORWARD_KEY: KeyCode = KeyCode::Right;
+pub const REPLAY_SPEED_UP_KEY: KeyCode = KeyCode::Up;
+pub const REPLAY_SPEED_DOWN_KEY: KeyCode = KeyCode::Down;
+pub const REPLAY_RESTART_KEY: KeyCode = KeyCode::Home;
+
+// 每次快进/后退的秒数
+const REPLAY_SEEK_SECONDS: f64 = 5.0;
+pub const MIN_REPLAY_SPEED: f64 = 0.25;
+pub const MAX_REPLAY_SPEED: f64 = 8.0;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+pub enum ReplaySystem {
+    Record,
+}
+
+// 录像需要的玩法规则, 不包含字体等显示设置
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayRules {
+    pub max_coin_num: usize,
+    pub min_player_num: usize,
+    pub max_player_num: usize,
+    pub target_score: usize,
+    pub min_coin_score_value: usize,
+    pub max_coin_score_value: usize,
+    pub delay_seconds: f32,
+    pub spawn_points: Vec<[f32; 2]>,
+    pub player_brick_size: [f32; 2],
+    pub coin_brick_size: [f32; 2],
+    pub mode: GameMode,
+}
+
+impl From<&GameRules> for ReplayRules {
+    fn from(rules: &GameRules) -> Self {
+        ReplayRules {
+            max_coin_num: rules.max_coin_num,
+            min_player_num: rules.min_player_num,
+            max_player_num: rules.max_player_num,
+            target_score: rules.target_score,
+            min_coin_score_value: rules.min_coin_score_value,
+            max_coin_score_value: rules.max_coin_score_value,
+            delay_seconds: rules.delay_seconds,
+            spawn_points: rules.spawn_points.iter().map(|p| [p.x, p.y]).collect(),
+            player_brick_size: [rules.player_brick_size.x, rules.player_brick_size.y],
+            coin_brick_size: [rules.coin_brick_size.x, rules.coin_brick_size.y],
+            mode: rules.mode.clone(),
+        }
+    }
+}
+
+impl ReplayRules {
+    pub fn apply(&self, rules: &mut GameRules) {
+        rules.max_coin_num = self.max_coin_num;
+        rules.min_player_num = self.min_player_num;
+        rules.max_player_num = self.max_player_num;
+        rules.target_score = self.target_score;
+        rules.min_coin_score_value = self.min_coin_score_value;
+        rules.max_coin_score_value = self.max_coin_score_value;
+        rules.delay_seconds = self.delay_seconds;
+        rules.spawn_points = self
+            .spawn_points
+            .iter()
+            .map(|[x, y]| Vec2::new(*x, *y))
+            .collect();
+        rules.player_brick_size = self.player_brick_size.into();
+        rules.coin_brick_size = self.coin_brick_size.into();
+        rules.mode = self.mode.clone();
+    }
+}
+
+// 比赛中热更新的规则, 从 tick 开始生效
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayRulesChange {
+    pub tick: u32,
+    pub rules: ReplayRules,
+}
+
+// 录像中的一个玩家, 按加入顺序编号
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayPlayer {
+    pub name: String,
+    pub slot: usize,
+    pub join_tick: u32,
+    pub leave_tick: Option<u32>,
+}
+
+// 一个玩家在一个 tick 内的输入
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayInput {
+    pub player: usize, // Replay::players 中的序号
+    pub ready: bool,
+    pub pressed: Vec<PlayerOperate>,
+    pub analog: [f32; 2],
+    // 远程玩家本 tick 实际移动的距离. 服务器按客户端输入帧的时长移动, 不能由 pressed 推算
+    pub offset: Option<[f32; 2]>,
+}
+
+// 一局比赛的录像: 规则, 随机数种子和每个 tick 所有玩家的输入
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub rules: ReplayRules,
+    pub seed: u64,
+    pub players: Vec<ReplayPlayer>,
+    pub ticks: Vec<Vec<ReplayInput>>, // 第 i 个元素是 tick i + 1 的输入
+    pub rule_changes: Vec<ReplayRulesChange>, // 按 tick 排序
+    pub result: Option<MatchResult>,
+}
+
+impl Replay {
+    pub fn new(rules: &GameRules, seed: u64) -> Self {
+        Replay {
+            rules: rules.into(),
+            seed,
+            players: Vec::new(),
+            ticks: Vec::new(),
+            rule_changes: Vec::new(),
+            result: None,
+        }
+    }
+
+    // 录像包含的 tick 数
+    pub fn tick_num(&self) -> u32 {
+        self.ticks.len() as u32
+    }
+
+    // 最后一次生效的规则
+    pub fn latest_rules(&self) -> &ReplayRules {
+        self.rule_changes
+            .last()
+            .map_or(&self.rules, |change| &change.rules)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.ticks.len() * 8);
+        bytes.extend_from_slice(&REPLAY_MAGIC);
+        bytes.extend_from_slice(&REPLAY_VERSION.to_le_bytes());
+        bincode_options()
+            .serialize_into(&mut bytes, self)
+            .expect("serialize replay fail!");
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(ReplayError::Truncated(bytes.len()));
+        }
+        if bytes[..4] != REPLAY_MAGIC {
+            return Err(ReplayError::BadMagic([
+                bytes[0], bytes[1], bytes[2], bytes[3],
+            ]));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::VersionMismatch {
+                expected: REPLAY_VERSION,
+                found: version,
+            });
+        }
+        if bytes.len() as u64 > MAX_REPLAY_SIZE {
+            return Err(ReplayError::TooLarge(bytes.len() as u64));
+        }
+        bincode_options()
+            .with_limit(MAX_REPLAY_SIZE)
+            .deserialize(&bytes[HEADER_SIZE..])
+            .map_err(ReplayError::Malformed)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(ReplayError::Io)?;
+        }
+        let bytes = self.encode();
+        if bytes.len() as u64 > MAX_REPLAY_SIZE {
+            return Err(ReplayError::TooLarge(bytes.len() as u64));
+        }
+        fs::write(path, bytes).map_err(ReplayError::Io)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let len = fs::metadata(path).map_err(ReplayError::Io)?.len();
+        if len > MAX_REPLAY_SIZE {
+            return Err(ReplayError::TooLarge(len));
+        }
+        let bytes = fs::read(path).map_err(ReplayError::Io)?;
+        Replay::decode(&bytes)
+    }
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new().reject_trailing_bytes()
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Truncated(usize),
+    TooLarge(u64),
+    BadMagic([u8; 4]),
+    VersionMismatch { expected: u16, found: u16 },
+    Malformed(bincode::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "replay io error: {}", e),
+            ReplayError::Truncated(len) => write!(f, "replay too short: {} bytes", len),
+            ReplayError::TooLarge(len) => write!(
+                f,
+                "replay too large: {} bytes, max {}",
+                len, MAX_REPLAY_SIZE
+            ),
+            ReplayError::BadMagic(magic) => write!(f, "bad replay magic: {:?}", magic),
+            ReplayError::VersionMismatch { expected, found } => write!(
+                f,
+                "replay version mismatch: expected {}, found {}",
+                expected, found
+            ),
+            ReplayError::Malformed(e) => write!(f, "malformed replay: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+// 回放的结果和录像中的不一致, 玩法逻辑或规则和录制时不同
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayDesync {
+    pub tick: u32,
+    pub result: Option<MatchResult>, // 回放到这个 tick 时的结果, 比赛没有结束时为 None
+    pub recorded: MatchResult,
+}
+
+impl fmt::Display for ReplayDesync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replay desync at tick {}: {}, recorded {}",
+            self.tick,
+            result_summary(self.result.as_ref()),
+            result_summary(Some(&self.recorded))
+        )
+    }
+}
+
+fn result_summary(result: Option<&MatchResult>) -> String {
+    match result.map(MatchResult::win_team_id) {
+        Some(Some(team_id)) => format!("team {} win", team_id),
+        Some(None) => "draw".to_string(),
+        None => "no result".to_string(),
+    }
+}
+
+// 显示回放不同步的文字
+pub struct ReplayDesyncUI;
+
+// 默认录像路径: replays/match-<unix 秒>.replay
+pub fn default_replay_path() -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Path::new(REPLAY_DIR).join(format!("match-{}.replay", secs))
+}
+
+// 录制本进程模拟的比赛, 比赛结束或程序退出时写入文件
+pub struct ReplayRecordPlugin;
+
+impl Plugin for ReplayRecordPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ReplayRecorder>()
+            .add_system_to_stage(
+                GameStage::Simulation,
+                replay_record_system
+                    .system()
+                    .label(ReplaySystem::Record)
+                    .after(PlayerSystem::LocalInput)
+                    .before(PlayerSystem::PlayerInput),
+            )
+            .add_system_set_to_stage(
+                GameStage::Simulation,
+                SystemSet::on_enter(MatchState::GameOver)
+                    .with_system(replay_finish_system.system().after(ReplaySystem::Record)),
+            )
+            .add_system_to_stage(CoreStage::Last, replay_exit_save_system.system());
+    }
+}
+
+pub struct ReplayRecorder {
+    path: PathBuf,
+    replay: Option<Replay>, // 第一个 tick 时根据当时的规则和种子创建
+    recording: HashMap<Entity, usize>,
+    finished: bool,
+}
+
+impl Default for ReplayRecorder {
+    fn default() -> Self {
+        ReplayRecorder::new(default_replay_path())
+    }
+}
+
+impl ReplayRecorder {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        ReplayRecorder {
+            path: path.into(),
+            replay: None,
+            recording: HashMap::default(),
+            finished: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+
+    // 录像已经写入文件, 之后的 tick 不再录制
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn save(&mut self) {
+        let replay = match (&self.replay, self.finished) {
+            (Some(replay), false) => replay,
+            _ => return,
+        };
+        self.finished = true;
+        match replay.save(&self.path) {
+            Ok(()) => info!(
+                "replay saved to {}, {} ticks",
+                self.path.display(),
+                replay.tick_num()
+            ),
+            Err(e) => error!("save replay to {} fail: {}", self.path.display(), e),
+        }
+    }
+}
+
+// 在玩家输入生效前记录本 tick 在场玩家的输入和准备状态
+#[allow(clippy::type_complexity)]
+fn replay_record_system(
+    mut recorder: ResMut<ReplayRecorder>,
+    clock: Res<SimulationClock>,
+    rules: Res<GameRules>,
+    seed: Res<GameSeed>,
+    roster: Res<PlayerRoster>,
+    query: Query<
+        (
+            &PlayerInfo,
+            &PlayerOperateState,
+            &Ready,
+            &Movement,
+            Option<&RemoteControlled>,
+        ),
+        With<Player>,
+    >,
+) {
+    if recorder.finished {
+        return;
+    }
+    let tick = clock.tick();
+    let recorder = &mut *recorder;
+    let replay = recorder
+        .replay
+        .get_or_insert_with(|| Replay::new(&rules, seed.0));
+    // 回滚重新模拟时覆盖之前录制的 tick
+    replay.ticks.truncate(tick as usize - 1);
+    replay.rule_changes.retain(|change| change.tick < tick);
+    let current_rules = ReplayRules::from(&*rules);
+    if *replay.latest_rules() != current_rules {
+        debug!("record rules change at tick {}", tick);
+        replay.rule_changes.push(ReplayRulesChange {
+            tick,
+            rules: current_rules,
+        });
+    }
+
+    let players = &mut replay.players;
+    recorder.recording.retain(|player, index| {
+        let present = roster.slot_of(*player).is_some();
+        if !present {
+            players[*index].leave_tick = Some(tick);
+        }
+        present
+    });
+    let recording = &mut recorder.recording;
+    let inputs = roster
+        .players()
+        .filter_map(|(slot, player)| {
+            let (info, state, ready, movement, remote) = query.get(player).ok()?;
+            let index = *recording.entry(player).or_insert_with(|| {
+                players.push(ReplayPlayer {
+                    name: info.name.clone(),
+                    slot,
+                    join_tick: tick,
+                    leave_tick: None,
+                });
+                players.len() - 1
+            });
+            Some(ReplayInput {
+                player: index,
+                ready: ready.0,
+                pressed: state.to_sorted_vec(),
+                analog: state.analog().into(),
+                offset: remote.map(|_| {
+                    let offset = movement.pendding_offset;
+                    [offset.x, offset.y]
+                }),
+            })
+        })
+        .collect();
+    replay.ticks.push(inputs);
+}
+
+fn replay_finish_system(mut recorder: ResMut<ReplayRecorder>, game_state: Res<GameState>) {
+    if let Some(replay) = recorder.replay.as_mut() {
+        replay.result = game_state.result().cloned();
+    }
+    recorder.save();
+}
+
+// 比赛没有结束就退出时也保存已经录制的部分
+fn replay_exit_save_system(
+    mut exit_events: EventReader<AppExit>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    if exit_events.iter().next().is_some() {
+        recorder.save();
+    }
+}
+
+// 用录像中的规则, 种子和输入驱动玩法系统重演比赛. 需要先插入 ReplayPlayback 资源
+pub struct ReplayPlaybackPlugin;
+
+impl Plugin for ReplayPlaybackPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // 玩家和规则全部来自录像, 不热更新规则文件
+        app.insert_resource(SpawnLocalPlayer(false))
+            .insert_resource(RulesReloadSettings::default())
+            .add_startup_system(replay_start_system.exclusive_system())
+            .add_system(replay_control_system.system())
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                replay_seek_system.exclusive_system().at_end(),
+            )
+            .add_system_to_stage(
+                GameStage::Simulation,
+                replay_input_system.exclusive_system().at_start(),
+            )
+            .add_system_set_to_stage(
+                GameStage::Simulation,
+                SystemSet::on_enter(MatchState::GameOver)
+                    .with_system(replay_result_system.system()),
+            )
+            .add_system(replay_desync_ui_system.system());
+    }
+}
+
+pub struct ReplayPlayback {
+    replay: Replay,
+    players: HashMap<usize, Entity>, // 录像中的玩家序号 -> 回放中的玩家实体
+    paused: bool,
+    speed: f64,
+    seek: Option<u32>,
+    desync: Option<ReplayDesync>,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        ReplayPlayback {
+            replay,
+            players: HashMap::default(),
+            paused: false,
+            speed: 1.0,
+            seek: None,
+            desync: None,
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_REPLAY_SPEED, MAX_REPLAY_SPEED);
+    }
+
+    // 下一帧跳到 tick 结束时, 往回跳会从头重新模拟
+    pub fn seek(&mut self, tick: u32) {
+        self.seek = Some(tick.min(self.replay.tick_num()));
+    }
+
+    // 回放中的玩家实体
+    pub fn player_entity(&self, index: usize) -> Option<Entity> {
+        self.players.get(&index).cloned()
+    }
+
+    // 本次回放发现的第一个不同步, 从头重新模拟时清除
+    pub fn desync(&self) -> Option<&ReplayDesync> {
+        self.desync.as_ref()
+    }
+
+    fn report_desync(&mut self, desync: ReplayDesync) {
+        if self.desync.is_none() {
+            error!("{}: {:?}", desync, desync.result);
+            self.desync = Some(desync);
+        }
+    }
+}
+
+fn replay_start_system(world: &mut World) {
+    restart_replay(world);
+    let playback = world.get_resource::<ReplayPlayback>().unwrap();
+    info!(
+        "play replay: {} players, {} ticks",
+        playback.replay.players.len(),
+        playback.replay.tick_num()
+    );
+}
+
+// 清空玩家和金币, 用录像的规则和种子回到比赛开始前
+fn restart_replay(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, Or<(With<Player>, With<Coin>)>>()
+        .iter(world)
+        .collect();
+    entities.into_iter().for_each(|entity| {
+        world.despawn(entity);
+    });
+
+    let mut playback = world.get_resource_mut::<ReplayPlayback>().unwrap();
+    playback.players.clear();
+    playback.desync = None;
+    let seed = playback.replay.seed;
+    let replay_rules = playback.replay.rules.clone();
+    let slots: Vec<usize> = playback.replay.players.iter().map(|p| p.slot).collect();
+
+    let mut rules = world.get_resource_mut::<GameRules>().unwrap();
+    replay_rules.apply(&mut rules);
+    let team_ids: BTreeSet<usize> = slots
+        .into_iter()
+        .map(|slot| PlayerSpawn::for_slot(&rules, slot, None).team_id)
+        .collect();
+    world.insert_resource(GameSeed(seed));
+    world.insert_resource(GameRng::from_seed(seed));
+    world.insert_resource(PlayerRoster::default());
+    world.insert_resource(DepartedScores::default());
+    reset_match(world);
+
+    // 计分板回到 0 分
+    let mut events = world
+        .get_resource_mut::<Events<TeamScoreChangedEvent>>()
+        .unwrap();
+    team_ids.into_iter().for_each(|team_id| {
+        events.send(TeamScoreChangedEvent {
+            team_score: 0,
+            team_id,
+        })
+    });
+}
+
+// 在模拟开始前处理跳转, 并限制模拟不超过录像的最后一个 tick
+fn replay_seek_system(world: &mut World) {
+    // 录像最后一个 tick 比赛已经结束, 模拟到最后还没结束说明不同步
+    let mut tick = world.get_resource::<SimulationClock>().unwrap().tick();
+    let game_over =
+        world.get_resource::<State<MatchState>>().unwrap().current() == &MatchState::GameOver;
+    let mut playback = world.get_resource_mut::<ReplayPlayback>().unwrap();
+    match playback.replay.result.clone() {
+        Some(recorded) if tick == playback.replay.tick_num() && !game_over => playback
+            .report_desync(ReplayDesync {
+                tick,
+                result: None,
+                recorded,
+            }),
+        _ => {}
+    }
+
+    let seek = playback.seek.take();
+    let speed = if playback.paused { 0.0 } else { playback.speed };
+    let tick_num = playback.replay.tick_num();
+
+    if let Some(target) = seek {
+        if target < tick {
+            restart_replay(world);
+            tick = 0;
+        }
+        let mut clock = world.get_resource_mut::<SimulationClock>().unwrap();
+        clock.fast_forward(target - tick);
+        tick = target;
+        debug!("replay seek to tick {}", target);
+    }
+    let mut clock = world.get_resource_mut::<SimulationClock>().unwrap();
+    clock.set_speed(speed);
+    clock.limit_ticks(tick_num.saturating_sub(tick));
+}
+
+// 每个 tick 开始时按录像加入/移除玩家, 并写入玩家输入
+fn replay_input_system(world: &mut World) {
+    let tick = world.get_resource::<SimulationClock>().unwrap().tick();
+    let mut playback = world.remove_resource::<ReplayPlayback>().unwrap();
+
+    // 和录制时一样在这个 tick 开始使用热更新的规则
+    let change = playback
+        .replay
+        .rule_changes
+        .iter()
+        .find(|change| change.tick == tick);
+    if let Some(change) = change {
+        let mut rules = world.get_resource_mut::<GameRules>().unwrap();
+        let previous = rules.clone();
+        change.rules.apply(&mut rules);
+        world
+            .get_resource_mut::<Events<GameRulesChangedEvent>>()
+            .unwrap()
+            .send(GameRulesChangedEvent { previous });
+    }
+
+    let ReplayPlayback {
+        replay, players, ..
+    } = &mut playback;
+    let leaving: Vec<Entity> = replay
+        .players
+        .iter()
+        .enumerate()
+        .filter(|(_, player)| player.leave_tick == Some(tick))
+        .filter_map(|(index, _)| players.remove(&index))
+        .collect();
+    leaving
+        .into_iter()
+        .for_each(|player| replay_leave(world, player));
+
+    let mut joining: Vec<(usize, &ReplayPlayer)> = playback
+        .replay
+        .players
+        .iter()
+        .enumerate()
+        .filter(|(_, player)| player.join_tick == tick)
+        .collect();
+    joining.sort_by_key(|(_, player)| player.slot);
+    if !joining.is_empty() {
+        let mut roster = world.remove_resource::<PlayerRoster>().unwrap();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+        let rules = world.get_resource::<GameRules>().unwrap();
+        let joined: Vec<(usize, Entity)> = joining
+            .into_iter()
+            .map(|(index, player)| {
+                let entity = roster.join(&mut commands, rules, Some(player.name.clone()));
+                if roster.slot_of(entity) != Some(player.slot) {
+                    warn!("replay player {} join wrong slot", player.name);
+                }
+                (index, entity)
+            })
+            .collect();
+        queue.apply(world);
+        world.insert_resource(roster);
+        playback.players.extend(joined);
+    }
+
+    if let Some(inputs) = playback.replay.ticks.get(tick as usize - 1) {
+        inputs.iter().for_each(|input| {
+            let player = match playback.players.get(&input.player) {
+                Some(player) => *player,
+                None => return,
+            };
+            if let Some(mut state) = world.get_mut::<PlayerOperateState>(player) {
+                *state = PlayerOperateState::from_pressed(input.pressed.iter().cloned())
+                    .with_analog(input.analog.into());
+            }
+            if let Some(mut ready) = world.get_mut::<Ready>(player) {
+                ready.0 = input.ready;
+            }
+            // 和录制时一样不按 pressed 移动, 直接使用记录的距离
+            if let Some([x, y]) = input.offset {
+                world.entity_mut(player).insert(RemoteControlled);
+                if let Some(mut movement) = world.get_mut::<Movement>(player) {
+                    movement.pendding_offset = Vec3::new(x, y, 0.0);
+                }
+            }
+        });
+    }
+    world.insert_resource(playback);
+}
+
+// 和 PlayerPlugin 处理离开的玩家一样, 分数留给队伍
+fn replay_leave(world: &mut World, player: Entity) {
+    let (name, team_id, score) = match (
+        world.get::<PlayerInfo>(player),
+        world.get::<Team>(player),
+        world.get::<Score>(player),
+    ) {
+        (Some(info), Some(team), Some(score)) => (info.name.clone(), team.id, score.val),
+        _ => return,
+    };
+    *world
+        .get_resource_mut::<DepartedScores>()
+        .unwrap()
+        .0
+        .entry(team_id)
+        .or_insert(0) += score;
+    world
+        .get_resource_mut::<PlayerRoster>()
+        .unwrap()
+        .leave(player);
+    world.despawn(player);
+    world
+        .get_resource_mut::<Events<PlayerLeftEvent>>()
+        .unwrap()
+        .send(PlayerLeftEvent {
+            player,
+            name,
+            team_id,
+            score,
+        });
+}
+
+fn replay_result_system(
+    mut playback: ResMut<ReplayPlayback>,
+    game_state: Res<GameState>,
+    clock: Res<SimulationClock>,
+) {
+    let result = game_state.result();
+    match playback.replay.result.clone() {
+        Some(recorded) if Some(&recorded) != result => playback.report_desync(ReplayDesync {
+            tick: clock.tick(),
+            result: result.cloned(),
+            recorded,
+        }),
+        _ => match result.and_then(MatchResult::win_team_id) {
+            Some(team_id) => info!("replay: team {} win game", team_id),
+            None => info!("replay: draw game"),
+        },
+    }
+}
+
+// 不同步时在屏幕上方显示提示, 重新模拟后移除
+fn replay_desync_ui_system(
+    mut commands: Commands,
+    playback: Res<ReplayPlayback>,
+    asset_server: Option<Res<AssetServer>>,
+    rules: Res<GameRules>,
+    query: Query<Entity, With<ReplayDesyncUI>>,
+) {
+    let desync = match (playback.desync(), query.iter().next()) {
+        (Some(desync), None) => desync,
+        (None, Some(entity)) => return commands.entity(entity).despawn(),
+        _ => return,
+    };
+    let font = asset_server
+        .map(|asset_server| asset_server.load(rules.font_path.as_str()))
+        .unwrap_or_default();
+    commands
+        .spawn()
+        .insert_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Percent(10.0),
+                    left: Val::Percent(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                desync.to_string(),
+                TextStyle {
+                    font,
+                    font_size: rules.font_size,
+                    color: Color::RED,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(ReplayDesyncUI);
+}
+
+fn replay_control_system(
+    keyboard: Res<Input<KeyCode>>,
+    clock: Res<SimulationClock>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    let seek_ticks = (REPLAY_SEEK_SECONDS / SIMULATION_TICK) as u32;
+    if keyboard.just_pressed(REPLAY_PAUSE_KEY) {
+        let paused = !playback.paused;
+        playback.set_paused(paused);
+        info!("replay paused: {}", paused);
+    }
+    if keyboard.just_pressed(REPLAY_SEEK_BACK_KEY) {
+        playback.seek(clock.tick().saturating_sub(seek_ticks));
+    }
+    if keyboard.just_pressed(REPLAY_SEEK_FORWARD_KEY) {
+        playback.seek(clock.tick() + seek_ticks);
+    }
+    if keyboard.just_pressed(REPLAY_RESTART_KEY) {
+        playback.seek(0);
+    }
+    if keyboard.just_pressed(REPLAY_SPEED_UP_KEY) {
+        let speed = playback.speed * 2.0;
+        playback.set_speed(speed);
+        info!("replay speed: {}", playback.speed);
+    }
+    if keyboard.just_pressed(REPLAY_SPEED_DOWN_KEY) {
+        let speed = playback.speed / 2.0;
+        playback.set_speed(speed);
+        info!("replay speed: {}", playback.speed);
+    }
+}
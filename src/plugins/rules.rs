@@ -0,0 +1,237 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use bevy::prelude::*;
+
+use super::game::{GameRules, GameRulesChangedEvent};
+use super::game_mode::GameMode;
+
+// 从文件读取比赛规则: --rules <file>
+pub const RULES_ARG: &str = "--rules";
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(PathBuf, io::Error),
+    UnknownFormat(PathBuf), // 只支持 .ron 和 .toml
+    Parse(String),          // 解析错误, 包含出错的位置或字段
+    Invalid { field: &'static str, reason: String },
+}
+
+impl fmt::Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(path, e) => write!(f, "read rules {} fail: {}", path.display(), e),
+            RulesError::UnknownFormat(path) => write!(
+                f,
+                "unknown rules format {}, expected .ron or .toml",
+                path.display()
+            ),
+            RulesError::Parse(e) => write!(f, "malformed rules: {}", e),
+            RulesError::Invalid { field, reason } => {
+                write!(f, "invalid rules: `{}` {}", field, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+fn invalid(field: &'static str, reason: String) -> RulesError {
+    RulesError::Invalid { field, reason }
+}
+
+impl GameRules {
+    // 按扩展名选择格式, 文件中没有写的字段使用默认值
+    pub fn load(path: &Path) -> Result<Self, RulesError> {
+        let text = fs::read_to_string(path).map_err(|e| RulesError::Io(path.to_path_buf(), e))?;
+        let rules = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ron") => Self::from_ron(&text),
+            Some("toml") => Self::from_toml(&text),
+            _ => Err(RulesError::UnknownFormat(path.to_path_buf())),
+        }
+        .map_err(|e| match e {
+            RulesError::Parse(e) => RulesError::Parse(format!("{}: {}", path.display(), e)),
+            e => e,
+        })?;
+        info!("load rules from {}", path.display());
+        Ok(rules)
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, RulesError> {
+        let rules: GameRules = ron::from_str(text).map_err(|e| RulesError::Parse(e.to_string()))?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, RulesError> {
+        let rules: GameRules =
+            toml::from_str(text).map_err(|e| RulesError::Parse(e.to_string()))?;
+        rules.validate()?;
+        Ok(rules)
+    }
+
+    // 检查会让玩法系统出错的取值
+    pub fn validate(&self) -> Result<(), RulesError> {
+        // gen_range 在范围为空时 panic
+        if self.min_coin_score_value >= self.max_coin_score_value {
+            return Err(invalid(
+                "min_coin_score_value",
+                format!(
+                    "({}) must be less than `max_coin_score_value` ({})",
+                    self.min_coin_score_value, self.max_coin_score_value
+                ),
+            ));
+        }
+        if self.min_player_num > self.max_player_num {
+            return Err(invalid(
+                "min_player_num",
+                format!(
+                    "({}) must not be greater than `max_player_num` ({})",
+                    self.min_player_num, self.max_player_num
+                ),
+            ));
+        }
+        if self.max_player_num == 0 {
+            return Err(invalid("max_player_num", "must be at least 1".to_string()));
+        }
+        if self.target_score == 0 {
+            return Err(invalid("target_score", "must be at least 1".to_string()));
+        }
+        if self.delay_seconds < 0.0 || !self.delay_seconds.is_finite() {
+            return Err(invalid(
+                "delay_seconds",
+                format!("({}) must be a non-negative number", self.delay_seconds),
+            ));
+        }
+        if self.spawn_points.is_empty() {
+            return Err(invalid(
+                "spawn_points",
+                "needs at least one point".to_string(),
+            ));
+        }
+        for (field, size) in [
+            ("player_brick_size", self.player_brick_size),
+            ("coin_brick_size", self.coin_brick_size),
+        ]
+        .iter()
+        {
+            if size.min_element() <= 0.0 || size.is_nan() {
+                return Err(invalid(
+                    field,
+                    format!("({}, {}) must be positive", size.x, size.y),
+                ));
+            }
+        }
+        match self.mode {
+            GameMode::TimeLimit { seconds: 0 } => {
+                return Err(invalid(
+                    "mode",
+                    "TimeLimit needs at least 1 second".to_string(),
+                ));
+            }
+            GameMode::CoinPool { coins: 0 } => {
+                return Err(invalid(
+                    "mode",
+                    "CoinPool needs at least 1 coin".to_string(),
+                ));
+            }
+            _ => {}
+        }
+        if self.font_path.is_empty() {
+            return Err(invalid("font_path", "must not be empty".to_string()));
+        }
+        if self.font_size <= 0.0 || self.font_size.is_nan() {
+            return Err(invalid(
+                "font_size",
+                format!("({}) must be positive", self.font_size),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// 开发时修改规则文件后不用重启: 定期检查文件, 校验通过后替换 GameRules 并发送 GameRulesChangedEvent.
+// 帧同步的各个节点不会同时重新读取, 只在单机或服务器上使用
+pub struct RulesHotReloadPlugin;
+
+impl Plugin for RulesHotReloadPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<RulesReloadSettings>()
+            .init_resource::<RulesFileWatcher>()
+            .add_system_to_stage(CoreStage::PreUpdate, rules_reload_system.system());
+    }
+}
+
+pub struct RulesReloadSettings {
+    pub path: Option<PathBuf>, // None 时不检查
+    pub poll_seconds: f32,     // 检查文件修改时间的间隔
+}
+
+impl Default for RulesReloadSettings {
+    fn default() -> Self {
+        RulesReloadSettings {
+            path: None,
+            poll_seconds: 1.0,
+        }
+    }
+}
+
+// 文件的修改时间和长度, 任意一个变化就重新读取
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn file_stamp(path: &Path) -> FileStamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+struct RulesFileWatcher {
+    elapsed: f32,
+    stamp: FileStamp,
+}
+
+// 启动时已经读取过当前的文件
+impl FromWorld for RulesFileWatcher {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world.get_resource_or_insert_with(RulesReloadSettings::default);
+        RulesFileWatcher {
+            elapsed: 0.0,
+            stamp: settings.path.as_deref().and_then(file_stamp),
+        }
+    }
+}
+
+fn rules_reload_system(
+    time: Res<Time>,
+    settings: Res<RulesReloadSettings>,
+    mut watcher: ResMut<RulesFileWatcher>,
+    mut rules: ResMut<GameRules>,
+    mut rules_events: EventWriter<GameRulesChangedEvent>,
+) {
+    let path = match &settings.path {
+        Some(path) => path,
+        None => return,
+    };
+    watcher.elapsed += time.delta_seconds();
+    if watcher.elapsed < settings.poll_seconds {
+        return;
+    }
+    watcher.elapsed = 0.0;
+    let stamp = file_stamp(path);
+    if stamp.is_none() || stamp == watcher.stamp {
+        return;
+    }
+    watcher.stamp = stamp;
+    // 编辑器保存到一半时可能读到不完整的文件, 保留当前规则等下一次修改
+    match GameRules::load(path) {
+        Ok(new_rules) if new_rules != *rules => {
+            let previous = std::mem::replace(&mut *rules, new_rules);
+            rules_events.send(GameRulesChangedEvent { previous });
+            info!("rules reloaded from {}", path.display());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("{}, keep current rules", e),
+    }
+}
@@ -0,0 +1,100 @@
+use super::player::Player;
+use super::ui::GameCamera;
+use bevy::prelude::*;
+
+// 切换观战视角的按键: 全场 -> 依次跟随每个玩家 -> 全场
+pub const SPECTATOR_NEXT_VIEW_KEY: KeyCode = KeyCode::Tab;
+
+// 跟随玩家时相机的缩放, 小于 1 表示放大
+const FOLLOW_SCALE: f32 = 0.5;
+
+// 观战相机: 可以看全场, 也可以跟随某个玩家
+pub struct SpectatorCameraPlugin;
+
+impl Plugin for SpectatorCameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SpectatorCamera>()
+            .add_system(
+                spectator_view_input_system
+                    .system()
+                    .label(SpectatorSystem::ViewInput),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, spectator_camera_system.system());
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, SystemLabel)]
+pub enum SpectatorSystem {
+    ViewInput,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectatorView {
+    Arena,          // 显示整个场地
+    Follow(Entity), // 跟随一个玩家
+}
+
+pub struct SpectatorCamera {
+    pub view: SpectatorView,
+}
+
+impl Default for SpectatorCamera {
+    fn default() -> Self {
+        SpectatorCamera {
+            view: SpectatorView::Arena,
+        }
+    }
+}
+
+impl SpectatorCamera {
+    // 按 players 的顺序切到下一个视角, 最后一个玩家之后回到全场
+    pub fn next_view(&mut self, players: &[Entity]) {
+        let next = match self.view {
+            SpectatorView::Arena => 0,
+            SpectatorView::Follow(player) => players
+                .iter()
+                .position(|p| *p == player)
+                .map_or(players.len(), |i| i + 1),
+        };
+        self.view = players.get(next).map_or(SpectatorView::Arena, |player| {
+            SpectatorView::Follow(*player)
+        });
+    }
+}
+
+fn spectator_view_input_system(
+    keyboard: Res<Input<KeyCode>>,
+    mut camera: ResMut<SpectatorCamera>,
+    query: Query<Entity, With<Player>>,
+) {
+    if !keyboard.just_pressed(SPECTATOR_NEXT_VIEW_KEY) {
+        return;
+    }
+    // 按实体 id 排序, 切换顺序不受查询顺序影响
+    let mut players: Vec<Entity> = query.iter().collect();
+    players.sort_by_key(|player| player.id());
+    camera.next_view(&players);
+}
+
+fn spectator_camera_system(
+    mut spectator: ResMut<SpectatorCamera>,
+    player_query: Query<&Transform, (With<Player>, Without<GameCamera>)>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<GameCamera>>,
+) {
+    let (target, scale) = match spectator.view {
+        SpectatorView::Arena => (Vec2::ZERO, 1.0),
+        SpectatorView::Follow(player) => match player_query.get(player) {
+            Ok(transform) => (transform.translation.truncate(), FOLLOW_SCALE),
+            Err(_) => {
+                // 跟随的玩家已经离开, 回到全场视角
+                spectator.view = SpectatorView::Arena;
+                (Vec2::ZERO, 1.0)
+            }
+        },
+    };
+    for (mut transform, mut projection) in camera_query.iter_mut() {
+        transform.translation.x = target.x;
+        transform.translation.y = target.y;
+        projection.scale = scale;
+    }
+}
@@ -1,7 +1,12 @@
 use super::{
     elapsed_time::ElapsedSecondChangedEvent,
-    game::{GameRules, GameState, MatchState},
-    player::TeamScoreChangedEvent,
+    game::{
+        GameRules, GameRulesChangedEvent, GameStage, GameState, MatchResult, MatchState, READY_KEY,
+    },
+    game_mode::GameMode,
+    player::{
+        team_scores, DepartedScores, LocalPlayer, Player, Ready, Score, Team, TeamScoreChangedEvent,
+    },
 };
 use bevy::prelude::*;
 
@@ -12,22 +17,41 @@ impl Plugin for UiPlugin {
         app.add_startup_system(setup.system())
             .add_system(score_ui_system.system())
             .add_system(elapsed_time_ui_system.system())
-            .add_system_set(
+            .add_system(lobby_ui_system.system())
+            .add_system(rules_changed_ui_system.system())
+            .add_system_set_to_stage(
+                GameStage::Simulation,
                 SystemSet::on_enter(MatchState::GameOver).with_system(gameover_ui_system.system()),
+            )
+            // 回放跳回比赛开始时会重新进入 WaitingForBegin
+            .add_system_set_to_stage(
+                GameStage::Simulation,
+                SystemSet::on_enter(MatchState::WaitingForBegin)
+                    .with_system(gameover_ui_clear_system.system()),
             );
     }
 }
 
+// 显示玩法场景的 2D 相机, 观战相机会移动它
+pub struct GameCamera;
+
 struct GameOverUI;
-struct ScoreUI;
+
+// 结果界面中排名和玩家数据相对规则字号的比例
+const RESULT_DETAILS_FONT_SCALE: f32 = 0.5;
+
+pub struct ScoreUI;
 
 struct ElapsedTimeUI;
 
+struct LobbyUI;
+
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>, rules: Res<GameRules>) {
-    let font = asset_server.load(rules.font_path);
+    let font = asset_server.load(rules.font_path.as_str());
     commands
         .spawn()
-        .insert_bundle(OrthographicCameraBundle::new_2d());
+        .insert_bundle(OrthographicCameraBundle::new_2d())
+        .insert(GameCamera);
     commands.spawn().insert_bundle(UiCameraBundle::default());
     commands
         .spawn()
@@ -93,26 +117,61 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, rules: Res<Game
             ..Default::default()
         })
         .insert(ElapsedTimeUI);
+
+    commands
+        .spawn()
+        .insert_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(5.0),
+                    left: Val::Px(15.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font,
+                    font_size: rules.font_size,
+                    color: Color::YELLOW,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(LobbyUI);
 }
 
+// 按队伍 id 排序, 每个队伍一行
 fn score_ui_system(
     mut events: EventReader<TeamScoreChangedEvent>,
+    departed_scores: Option<Res<DepartedScores>>,
+    players: Query<(&Team, &Score), With<Player>>,
+    changed: Query<(), (With<Player>, Changed<Score>)>,
     mut query: Query<&mut Text, With<ScoreUI>>,
 ) {
+    if events.iter().count() == 0 && changed.iter().next().is_none() {
+        return;
+    }
+    let scores = team_scores(players.iter(), departed_scores.as_deref());
     if let Ok(mut text) = query.single_mut() {
-        events.iter().enumerate().for_each(
-            |(
-                i,
-                TeamScoreChangedEvent {
-                    team_score,
-                    team_id,
-                },
-            )| {
-                if let Some(section) = text.sections.get_mut(i) {
-                    section.value = format!(" |team {}: {} ", team_id, team_score);
-                }
-            },
-        );
+        let style = text.sections[0].style.clone();
+        text.sections = scores
+            .into_iter()
+            .map(|(team_id, team_score)| TextSection {
+                value: format!(" |team {}: {} \n", team_id, team_score),
+                style: style.clone(),
+            })
+            .collect();
+        if text.sections.is_empty() {
+            text.sections.push(TextSection {
+                value: String::new(),
+                style,
+            });
+        }
     }
 }
 
@@ -122,7 +181,11 @@ fn gameover_ui_system(
     asset_server: Res<AssetServer>,
     rules: Res<GameRules>,
 ) {
-    let font = asset_server.load(rules.font_path);
+    let font = asset_server.load(rules.font_path.as_str());
+    let (title, details) = match game_state.result() {
+        Some(result) => (result_title(result), result_details(result)),
+        None => ("game over".to_string(), String::new()),
+    };
     commands
         .spawn()
         .insert_bundle(TextBundle {
@@ -130,31 +193,71 @@ fn gameover_ui_system(
                 align_self: AlignSelf::FlexEnd,
                 position_type: PositionType::Absolute,
                 position: Rect {
-                    top: Val::Percent(50.0),
+                    top: Val::Percent(30.0),
                     left: Val::Percent(40.0),
                     ..Default::default()
                 },
                 ..Default::default()
             },
-            // Use `Text` directly
-            text: Text::with_section(
-                format!("team {} win game", game_state.get_win_team_id()),
-                TextStyle {
-                    font: font.clone(),
-                    font_size: rules.font_size,
-                    color: Color::RED,
-                },
-                TextAlignment {
+            // 第一段是胜负, 第二段是队伍排名和玩家数据
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: title,
+                        style: TextStyle {
+                            font: font.clone(),
+                            font_size: rules.font_size,
+                            color: Color::RED,
+                        },
+                    },
+                    TextSection {
+                        value: details,
+                        style: TextStyle {
+                            font: font.clone(),
+                            font_size: rules.font_size * RESULT_DETAILS_FONT_SCALE,
+                            color: Color::WHITE,
+                        },
+                    },
+                ],
+                alignment: TextAlignment {
                     horizontal: HorizontalAlign::Right,
                     ..Default::default()
                 },
-            ),
+            },
             ..Default::default()
         })
         .insert(GameOverUI);
 }
 
+fn result_title(result: &MatchResult) -> String {
+    match result.win_team_id() {
+        Some(team_id) => format!("team {} win game", team_id),
+        None if result.is_draw() => format!("draw game: teams {:?}", result.winners()),
+        None => "draw game".to_string(),
+    }
+}
+
+fn result_details(result: &MatchResult) -> String {
+    let teams = result
+        .teams
+        .iter()
+        .map(|team| format!("\n#{} team {}: {}", team.rank, team.team_id, team.score));
+    let players = result.players.iter().map(|player| {
+        format!(
+            "\n{} (team {}): {} points, {} coins",
+            player.name, player.team_id, player.score, player.coins
+        )
+    });
+    teams.chain(players).collect()
+}
+
+fn gameover_ui_clear_system(mut commands: Commands, query: Query<Entity, With<GameOverUI>>) {
+    query.for_each(|e| commands.entity(e).despawn());
+}
+
+// 限时模式显示剩余时间
 fn elapsed_time_ui_system(
+    rules: Res<GameRules>,
     mut events: EventReader<ElapsedSecondChangedEvent>,
     mut query: Query<&mut Text, With<ElapsedTimeUI>>,
 ) {
@@ -163,8 +266,79 @@ fn elapsed_time_ui_system(
             .iter()
             .for_each(|ElapsedSecondChangedEvent { seconds }| {
                 if let Some(section) = text.sections.get_mut(0) {
-                    section.value = format!("elapsed time: {}", seconds);
+                    section.value = match rules.mode {
+                        GameMode::TimeLimit { seconds: limit } => {
+                            format!("time left: {}", limit.saturating_sub(*seconds))
+                        }
+                        _ => format!("elapsed time: {}", seconds),
+                    };
                 }
             });
     }
 }
+
+// 大厅中提示准备按键和已准备人数, 比赛开始后清空
+fn lobby_ui_system(
+    match_state: Res<State<MatchState>>,
+    rules: Res<GameRules>,
+    player_query: Query<(&Ready, Option<&LocalPlayer>), With<Player>>,
+    mut query: Query<&mut Text, With<LobbyUI>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        let value = if *match_state.current() == MatchState::WaitingForBegin {
+            let ready_num = player_query.iter().filter(|(ready, _)| ready.0).count();
+            let status = format!(
+                "{} ({}/{} ready)",
+                if ready_num < rules.min_player_num {
+                    "waiting for players"
+                } else {
+                    "starting"
+                },
+                ready_num,
+                rules.min_player_num,
+            );
+            // 观战者没有本地玩家, 不提示准备按键
+            match player_query.iter().find(|(_, local)| local.is_some()) {
+                Some((ready, _)) => format!(
+                    "{} - press {:?} to {}",
+                    status,
+                    READY_KEY,
+                    if ready.0 { "cancel" } else { "ready" }
+                ),
+                None => status,
+            }
+        } else {
+            String::new()
+        };
+        if let Some(section) = text.sections.get_mut(0) {
+            if section.value != value {
+                section.value = value;
+            }
+        }
+    }
+}
+
+// 规则重新读取后所有文字换成新的字体和字号, 结果界面的排名和玩家数据保持较小的字号
+fn rules_changed_ui_system(
+    asset_server: Res<AssetServer>,
+    rules: Res<GameRules>,
+    mut rules_events: EventReader<GameRulesChangedEvent>,
+    mut query: Query<(&mut Text, Option<&GameOverUI>)>,
+) {
+    if rules_events.iter().last().is_none() {
+        return;
+    }
+    let font = asset_server.load(rules.font_path.as_str());
+    query.iter_mut().for_each(|(mut text, game_over)| {
+        text.sections
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, section)| {
+                section.style.font = font.clone();
+                section.style.font_size = match (game_over, index) {
+                    (Some(_), 1) => rules.font_size * RESULT_DETAILS_FONT_SCALE,
+                    _ => rules.font_size,
+                };
+            });
+    });
+}
@@ -0,0 +1,247 @@
+use bevy::input::ElementState::{self, Pressed, Released};
+use bevy::prelude::*;
+use test_bevy_game::plugins::action_input::{
+    ActionBinding, ActionDetector, ActionInputPlugin, ActionInputSettings, ActionTrigger,
+};
+use test_bevy_game::plugins::game::SimulationClock;
+use test_bevy_game::plugins::input_ext::{PlayerInputSettings, PlayerOperate, SeatInputs};
+use test_bevy_game::plugins::player::{
+    LocalPlayer, LocalSeat, SpawnLocalPlayer, SPRINT_SPEED_SCALE,
+};
+
+mod common;
+use common::{game_app_builder, key};
+
+// 按时间顺序的按键事件: (距上一个事件的秒数, 按键, 按下/松开)
+type KeySequence<'a> = &'a [(f32, KeyCode, ElementState)];
+
+fn settings(trigger: ActionTrigger) -> ActionInputSettings {
+    ActionInputSettings {
+        bindings: vec![ActionBinding {
+            seat: 0,
+            trigger,
+            operate: PlayerOperate::Dash,
+        }],
+        follow_key_bindings: false,
+    }
+}
+
+// 逐个事件更新识别器, 返回每个事件之后动作是否触发
+fn feed(settings: &ActionInputSettings, sequence: KeySequence) -> Vec<bool> {
+    let mut detector = ActionDetector::default();
+    let mut keys = Input::<KeyCode>::default();
+    sequence
+        .iter()
+        .map(|(delta, key, state)| {
+            keys.update();
+            match state {
+                Pressed => keys.press(*key),
+                Released => keys.release(*key),
+            }
+            detector.update(settings, &keys, *delta);
+            detector.active(settings, 0).contains(&PlayerOperate::Dash)
+        })
+        .collect()
+}
+
+#[test]
+fn chord_needs_every_key_held() {
+    let chord = settings(ActionTrigger::Chord(vec![KeyCode::LShift, KeyCode::W]));
+    assert_eq!(
+        feed(
+            &chord,
+            &[
+                (0.0, KeyCode::W, Pressed),
+                (0.1, KeyCode::LShift, Pressed),
+                (0.1, KeyCode::W, Released),
+                (0.1, KeyCode::W, Pressed),
+                (0.1, KeyCode::LShift, Released),
+            ]
+        ),
+        vec![false, true, false, true, false]
+    );
+}
+
+#[test]
+fn hold_triggers_after_duration_until_released() {
+    let hold = settings(ActionTrigger::Hold {
+        key: KeyCode::Space,
+        seconds: 0.5,
+    });
+    assert_eq!(
+        feed(
+            &hold,
+            &[
+                (0.0, KeyCode::Space, Pressed),
+                (0.3, KeyCode::Space, Released),
+                (0.1, KeyCode::Space, Pressed),
+                (0.4, KeyCode::Q, Pressed),
+                (0.2, KeyCode::Q, Released),
+                (0.1, KeyCode::Space, Released),
+            ]
+        ),
+        vec![false, false, false, false, true, false]
+    );
+}
+
+#[test]
+fn double_tap_needs_two_presses_inside_window() {
+    let double_tap = settings(ActionTrigger::DoubleTap {
+        key: KeyCode::D,
+        window: 0.3,
+    });
+    assert_eq!(
+        feed(
+            &double_tap,
+            &[
+                // 间隔太长
+                (0.0, KeyCode::D, Pressed),
+                (0.1, KeyCode::D, Released),
+                (0.5, KeyCode::D, Pressed),
+                (0.1, KeyCode::D, Released),
+                // 窗口内第二次按下, 松开前一直触发
+                (0.1, KeyCode::D, Pressed),
+                (0.5, KeyCode::A, Pressed),
+                (0.1, KeyCode::D, Released),
+                // 第三次按下重新计数
+                (0.1, KeyCode::D, Pressed),
+            ]
+        ),
+        vec![false, false, false, false, true, true, false, false]
+    );
+}
+
+fn action_app() -> App {
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(SpawnLocalPlayer(true))
+        .insert_resource(PlayerInputSettings::split_keyboard())
+        .add_plugin(ActionInputPlugin);
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    app.update();
+    app
+}
+
+fn pressed(app: &App, seat: usize, op: PlayerOperate) -> bool {
+    match seat {
+        0 => app
+            .world
+            .get_resource::<Input<PlayerOperate>>()
+            .unwrap()
+            .pressed(op),
+        _ => app
+            .world
+            .get_resource::<SeatInputs>()
+            .unwrap()
+            .get(seat)
+            .unwrap()
+            .pressed(op),
+    }
+}
+
+#[test]
+fn split_keyboard_actions_press_sprint_and_dash_for_their_seat() {
+    let mut app = action_app();
+    key(&mut app, KeyCode::LShift, Pressed);
+    app.update();
+    key(&mut app, KeyCode::W, Pressed);
+    app.update();
+    assert!(pressed(&app, 0, PlayerOperate::Sprint));
+    assert!(pressed(&app, 0, PlayerOperate::MoveFrond));
+    assert!(!pressed(&app, 1, PlayerOperate::Sprint));
+
+    // 座位 0 疾跑, 座位 1 正常速度
+    key(&mut app, KeyCode::Up, Pressed);
+    app.update();
+    let start = local_positions(&mut app);
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .fast_forward(10);
+    app.update();
+    let moved: Vec<_> = local_positions(&mut app)
+        .into_iter()
+        .zip(start)
+        .map(|(end, start)| end - start)
+        .collect();
+    assert!((moved[0].y / moved[1].y - SPRINT_SPEED_SCALE).abs() < 0.001);
+    key(&mut app, KeyCode::Up, Released);
+    app.update();
+
+    key(&mut app, KeyCode::LShift, Released);
+
+    app.update();
+    assert!(!pressed(&app, 0, PlayerOperate::Sprint));
+    assert!(pressed(&app, 0, PlayerOperate::MoveFrond));
+
+    // 座位 1 双击方向键冲刺
+    key(&mut app, KeyCode::Right, Pressed);
+    app.update();
+    key(&mut app, KeyCode::Right, Released);
+    app.update();
+    key(&mut app, KeyCode::Right, Pressed);
+    app.update();
+    assert!(pressed(&app, 1, PlayerOperate::Dash));
+    assert!(!pressed(&app, 0, PlayerOperate::Dash));
+    key(&mut app, KeyCode::Right, Released);
+    app.update();
+    assert!(!pressed(&app, 1, PlayerOperate::Dash));
+}
+
+#[test]
+fn rebound_move_key_moves_sprint_and_dash() {
+    let mut app = action_app();
+    app.world
+        .get_resource_mut::<PlayerInputSettings>()
+        .unwrap()
+        .rebind(0, PlayerOperate::MoveFrond, KeyCode::I)
+        .unwrap();
+    app.update();
+
+    // 旧按键双击不再冲刺
+    key(&mut app, KeyCode::W, Pressed);
+    app.update();
+    key(&mut app, KeyCode::W, Released);
+    app.update();
+    key(&mut app, KeyCode::W, Pressed);
+    app.update();
+    assert!(!pressed(&app, 0, PlayerOperate::Dash));
+    key(&mut app, KeyCode::W, Released);
+    app.update();
+
+    key(&mut app, KeyCode::I, Pressed);
+
+    app.update();
+    key(&mut app, KeyCode::I, Released);
+    app.update();
+    key(&mut app, KeyCode::I, Pressed);
+    app.update();
+    assert!(pressed(&app, 0, PlayerOperate::Dash));
+    key(&mut app, KeyCode::I, Released);
+    app.update();
+
+    key(&mut app, KeyCode::LShift, Pressed);
+
+    app.update();
+    key(&mut app, KeyCode::W, Pressed);
+    app.update();
+    assert!(!pressed(&app, 0, PlayerOperate::Sprint));
+    key(&mut app, KeyCode::I, Pressed);
+    app.update();
+    assert!(pressed(&app, 0, PlayerOperate::Sprint));
+}
+
+fn local_positions(app: &mut App) -> Vec<Vec3> {
+    let mut players: Vec<_> = app
+        .world
+        .query_filtered::<(&LocalSeat, &Transform), With<LocalPlayer>>()
+        .iter(&app.world)
+        .map(|(seat, transform)| (seat.0, transform.translation))
+        .collect();
+    players.sort_by_key(|(seat, _)| *seat);
+    players.into_iter().map(|(_, position)| position).collect()
+}
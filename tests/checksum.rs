@@ -0,0 +1,97 @@
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use test_bevy_game::plugins::net::{dump_diff, DesyncDetectedEvent, LockstepSlot, WorldChecksums};
+
+mod common;
+use common::{lockstep_apps, tick_of};
+
+#[derive(Default)]
+struct DesyncLog(Vec<DesyncDetectedEvent>);
+
+fn collect_desync_system(mut log: ResMut<DesyncLog>, mut events: EventReader<DesyncDetectedEvent>) {
+    log.0.extend(events.iter().cloned());
+}
+
+fn peers() -> Vec<App> {
+    let mut apps = lockstep_apps(2, 7);
+    apps.iter_mut().for_each(|app| {
+        app.world.insert_resource(DesyncLog::default());
+        app.schedule
+            .add_system_to_stage(CoreStage::Last, collect_desync_system.system());
+    });
+    apps
+}
+
+// 运行到两个节点都模拟过 tick, 再多跑几帧让校验值交换完
+fn run_until(apps: &mut [App], tick: u32) {
+    for _ in 0..2000 {
+        apps.iter_mut().for_each(|app| app.update());
+        if apps.iter().all(|app| tick_of(app) >= tick) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert!(apps.iter().all(|app| tick_of(app) >= tick));
+    for _ in 0..20 {
+        apps.iter_mut().for_each(|app| app.update());
+        thread::sleep(Duration::from_millis(2));
+    }
+}
+
+fn desync_log(app: &App) -> &[DesyncDetectedEvent] {
+    &app.world.get_resource::<DesyncLog>().unwrap().0
+}
+
+#[test]
+fn peers_in_sync_have_equal_checksums() {
+    let mut apps = peers();
+    run_until(&mut apps, 60);
+
+    let checksums: Vec<_> = apps
+        .iter()
+        .map(|app| app.world.get_resource::<WorldChecksums>().unwrap())
+        .collect();
+    (1..=60).for_each(|tick| {
+        assert!(checksums[0].checksum(tick).is_some());
+        assert_eq!(checksums[0].checksum(tick), checksums[1].checksum(tick));
+        assert_eq!(checksums[0].dump(tick), checksums[1].dump(tick));
+    });
+    assert!(apps.iter().all(|app| desync_log(app).is_empty()));
+}
+
+#[test]
+fn moved_player_raises_desync_on_both_peers() {
+    let mut apps = peers();
+    run_until(&mut apps, 30);
+
+    // 绕过输入直接修改一个节点上的玩家位置
+    let mut query = apps[1].world.query::<(&LockstepSlot, &mut Transform)>();
+    query
+        .iter_mut(&mut apps[1].world)
+        .filter(|(slot, _)| slot.0 == 0)
+        .for_each(|(_, mut transform)| transform.translation.x += 1.0);
+    let moved_at = tick_of(&apps[1]);
+    run_until(&mut apps, moved_at + 30);
+
+    for (local, app) in apps.iter().enumerate() {
+        let log = desync_log(app);
+        assert_eq!(log.len(), 1, "peer {} should report once", local);
+        assert_eq!(log[0].peer, 1 - local);
+        assert!(log[0].tick > moved_at);
+        assert_ne!(log[0].local, log[0].remote);
+        let checksums = app.world.get_resource::<WorldChecksums>().unwrap();
+        assert!(checksums.is_desynced(1 - local));
+    }
+}
+
+#[test]
+fn dump_diff_marks_changed_lines() {
+    let local = "tick 3\nplayer \"Player 0\" pos=1\ncoin value=2";
+    let remote = "tick 3\nplayer \"Player 0\" pos=2\ncoin value=2";
+    assert_eq!(
+        dump_diff(local, remote),
+        "  tick 3\n- player \"Player 0\" pos=1\n  coin value=2\n+ player \"Player 0\" pos=2\n"
+    );
+}
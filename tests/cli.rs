@@ -0,0 +1,323 @@
+use std::path::PathBuf;
+
+use bevy::input::InputPlugin;
+use bevy::log::Level;
+use bevy::prelude::*;
+use test_bevy_game::plugins::cli::{CliError, LaunchOptions, LaunchRole};
+use test_bevy_game::plugins::game::{GameRules, GameSeed};
+use test_bevy_game::plugins::input_ext::PlayerInputSettings;
+use test_bevy_game::plugins::net::{
+    ClientRole, ConnectionState, NetClient, NetClientSettings, NetServer, NetServerSettings,
+};
+use test_bevy_game::plugins::player::{LocalPlayer, PlayerInfo, PlayerRoster};
+
+mod common;
+use common::{client_app, run_until, server_app};
+
+#[test]
+fn flags_fill_launch_options() {
+    let options = LaunchOptions::parse(vec![
+        "--role",
+        "client",
+        "--addr",
+        "10.0.0.2",
+        "--port",
+        "9000",
+        "--name",
+        "Bob",
+        "--log-level",
+        "warn",
+        "--net-sim",
+        "latency=100",
+        "--headless",
+    ])
+    .unwrap();
+    assert_eq!(options.role, LaunchRole::Client);
+    assert_eq!(options.server_addr(), "10.0.0.2:9000".parse().unwrap());
+    assert_eq!(options.player_name.as_deref(), Some("Bob"));
+    assert_eq!(options.log_level(), Level::WARN);
+    assert!(options.headless);
+    assert_eq!(options.net_conditions().latency_ms, 100);
+    assert_eq!(options.replay, None);
+
+    // 没有指定的项使用默认值
+    let options = LaunchOptions::parse(Vec::<String>::new()).unwrap();
+    assert_eq!(options.role, LaunchRole::Offline);
+    assert_eq!(options.log_level(), Level::DEBUG);
+    assert_eq!(options.server_addr(), "127.0.0.1:7878".parse().unwrap());
+    let options = LaunchOptions::parse(vec!["--seed", "5"]).unwrap();
+    assert_eq!(options.seed, Some(5));
+    let options = LaunchOptions::parse(vec!["--role", "server", "--port", "7000"]).unwrap();
+    assert_eq!(options.log_level(), Level::INFO);
+    assert_eq!(options.bind_addr(), "0.0.0.0:7000".parse().unwrap());
+}
+
+#[test]
+fn bad_flags_are_reported() {
+    assert_eq!(
+        LaunchOptions::parse(vec!["--role", "observer"]),
+        Err(CliError::InvalidValue {
+            arg: "--role".to_string(),
+            value: "observer".to_string()
+        })
+    );
+    assert_eq!(
+        LaunchOptions::parse(vec!["--port", "70000"]),
+        Err(CliError::InvalidValue {
+            arg: "--port".to_string(),
+            value: "70000".to_string()
+        })
+    );
+    assert_eq!(
+        LaunchOptions::parse(vec!["--headless", "--seed"]),
+        Err(CliError::MissingValue("--seed".to_string()))
+    );
+    assert_eq!(
+        LaunchOptions::parse(vec!["--replay"]),
+        Err(CliError::MissingValue("--replay".to_string()))
+    );
+    assert_eq!(
+        LaunchOptions::parse(vec!["--net-sim", "loss=2"]),
+        Err(CliError::InvalidValue {
+            arg: "--net-sim".to_string(),
+            value: "loss=2".to_string()
+        })
+    );
+    // 规则和录像文件在解析参数时读取
+    let missing = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("missing.ron");
+    let missing = missing.display().to_string();
+    for arg in ["--rules", "--replay", "--input-script"].iter() {
+        match LaunchOptions::parse(vec![arg.to_string(), missing.clone()]) {
+            Err(CliError::InvalidFile {
+                arg: name, path, ..
+            }) => {
+                assert_eq!(&name, arg);
+                assert_eq!(path, missing);
+            }
+            other => panic!("expected invalid file for {}, got {:?}", arg, other),
+        }
+    }
+    let rules_path =
+        std::env::temp_dir().join(format!("test_bevy_game_cli_{}.ron", std::process::id()));
+    std::fs::write(&rules_path, "(min_coin_score_value: 9)").unwrap();
+    let result = LaunchOptions::parse(vec![
+        "--rules".to_string(),
+        rules_path.display().to_string(),
+    ]);
+    std::fs::remove_file(&rules_path).unwrap();
+    match result {
+        Err(CliError::InvalidFile { reason, .. }) => {
+            assert!(reason.contains("min_coin_score_value"), "{}", reason)
+        }
+        other => panic!("expected invalid rules, got {:?}", other),
+    }
+    assert_eq!(
+        LaunchOptions::parse(vec!["--fullscreen"]),
+        Err(CliError::UnknownArg("--fullscreen".to_string()))
+    );
+}
+
+#[test]
+fn headless_host_runs_with_given_seed_rules_and_name() {
+    let rules_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules.ron");
+    let options = LaunchOptions::parse(vec![
+        "--role".to_string(),
+        "host".to_string(),
+        "--headless".to_string(),
+        "--addr".to_string(),
+        "127.0.0.1".to_string(),
+        "--port".to_string(),
+        "0".to_string(),
+        "--seed".to_string(),
+        "42".to_string(),
+        "--name".to_string(),
+        "Alice".to_string(),
+        "--rules".to_string(),
+        rules_path.display().to_string(),
+    ])
+    .unwrap();
+    // LogPlugin 在一个进程里只能添加一次, 测试里自己添加引擎插件
+    let mut builder = App::build();
+    options.insert_resources(&mut builder);
+    builder.add_plugins(MinimalPlugins).add_plugin(InputPlugin);
+    options.add_game_plugins(&mut builder);
+    let mut app = builder.app;
+    app.update();
+
+    assert_eq!(app.world.get_resource::<GameSeed>().unwrap().0, 42);
+    assert_eq!(
+        app.world.get_resource::<GameRules>().unwrap(),
+        &GameRules::load(&rules_path).unwrap()
+    );
+    assert_eq!(
+        app.world
+            .get_resource::<NetServerSettings>()
+            .unwrap()
+            .bind_addr,
+        "127.0.0.1:0".parse().unwrap()
+    );
+    assert!(app.world.get_resource::<NetServer>().is_some());
+    let names: Vec<String> = app
+        .world
+        .query_filtered::<&PlayerInfo, With<LocalPlayer>>()
+        .iter(&app.world)
+        .map(|info| info.name.clone())
+        .collect();
+    assert!(names.contains(&"Alice".to_string()), "{:?}", names);
+}
+
+#[test]
+fn default_host_keeps_a_slot_for_remote_clients() {
+    let options = LaunchOptions::parse(vec![
+        "--role",
+        "host",
+        "--headless",
+        "--addr",
+        "127.0.0.1",
+        "--port",
+        "0",
+    ])
+    .unwrap();
+    let mut builder = App::build();
+    options.insert_resources(&mut builder);
+    builder.add_plugins(MinimalPlugins).add_plugin(InputPlugin);
+    options.add_game_plugins(&mut builder);
+    let mut host = builder.app;
+    host.update();
+    assert_eq!(
+        host.world
+            .query_filtered::<Entity, With<LocalPlayer>>()
+            .iter(&host.world)
+            .count(),
+        1
+    );
+
+    let server_addr = host.world.get_resource::<NetServer>().unwrap().local_addr();
+    let mut client = client_app(server_addr, "Bob");
+    run_until(&mut host, &mut client, |host, client| {
+        host.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count()
+            == 1
+            && client
+                .world
+                .get_resource::<NetClient>()
+                .unwrap()
+                .connection_state()
+                == &ConnectionState::Connected
+    });
+    assert_eq!(
+        host.world
+            .get_resource::<PlayerRoster>()
+            .unwrap()
+            .player_num(),
+        2
+    );
+}
+
+#[test]
+fn split_keyboard_is_offline_only() {
+    let options = LaunchOptions::parse(vec!["--split-keyboard"]).unwrap();
+    assert!(options.split_keyboard);
+    let mut builder = App::build();
+    options.insert_resources(&mut builder);
+    assert_eq!(
+        builder
+            .app
+            .world
+            .get_resource::<PlayerInputSettings>()
+            .unwrap()
+            .seat_num(),
+        2
+    );
+    assert_eq!(PlayerInputSettings::default().seat_num(), 1);
+
+    assert_eq!(
+        LaunchOptions::parse(vec!["--role", "host", "--split-keyboard"]),
+        Err(CliError::RoleConflict {
+            arg: "--split-keyboard".to_string(),
+            role: LaunchRole::Host,
+        })
+    );
+}
+
+#[test]
+fn clients_use_the_server_seed() {
+    for role in ["client", "spectator"].iter() {
+        assert_eq!(
+            LaunchOptions::parse(vec!["--role", role, "--seed", "5"]),
+            Err(CliError::RoleConflict {
+                arg: "--seed".to_string(),
+                role: role.parse().unwrap(),
+            })
+        );
+    }
+}
+
+#[test]
+fn spectator_role_joins_without_taking_a_player_slot() {
+    let mut server = server_app();
+    let server_addr = server
+        .world
+        .get_resource::<NetServer>()
+        .unwrap()
+        .local_addr();
+    let options = LaunchOptions::parse(vec![
+        "--role".to_string(),
+        "spectator".to_string(),
+        "--headless".to_string(),
+        "--addr".to_string(),
+        server_addr.ip().to_string(),
+        "--port".to_string(),
+        server_addr.port().to_string(),
+        "--name".to_string(),
+        "Eve".to_string(),
+    ])
+    .unwrap();
+    assert_eq!(options.role, LaunchRole::Spectator);
+    let mut builder = App::build();
+    options.insert_resources(&mut builder);
+    builder.add_plugins(MinimalPlugins).add_plugin(InputPlugin);
+    options.add_game_plugins(&mut builder);
+    let mut spectator = builder.app;
+    assert_eq!(
+        spectator
+            .world
+            .get_resource::<NetClientSettings>()
+            .unwrap()
+            .role,
+        ClientRole::Spectator
+    );
+
+    run_until(&mut server, &mut spectator, |_, spectator| {
+        spectator
+            .world
+            .get_resource::<NetClient>()
+            .unwrap()
+            .connection_state()
+            == &ConnectionState::Connected
+    });
+    assert!(spectator
+        .world
+        .get_resource::<NetClient>()
+        .unwrap()
+        .is_spectator());
+    // 观战者不算作玩家
+    assert_eq!(
+        server
+            .world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count(),
+        0
+    );
+    assert_eq!(
+        server
+            .world
+            .get_resource::<PlayerRoster>()
+            .unwrap()
+            .player_num(),
+        0
+    );
+}
@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use bevy::app::Events;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::{ElementState, InputPlugin};
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::CoinPlugin;
+use test_bevy_game::plugins::elapsed_time::ElapsedTimePlugin;
+use test_bevy_game::plugins::game::{GameCorePlugin, MatchState, SimulationClock};
+use test_bevy_game::plugins::input_ext::InputExtPlugin;
+use test_bevy_game::plugins::net::{
+    ClientRole, Lockstep, LockstepPlugin, LockstepSettings, NetClientPlugin, NetClientSettings,
+    NetConditions, NetServerPlugin, NetServerSettings, RollbackPlugin,
+};
+use test_bevy_game::plugins::player::{PlayerPlugin, SpawnLocalPlayer};
+
+// 不带窗口和渲染的基础 App, 和专用服务器一样没有 Assets<ColorMaterial>
+fn headless_app() -> AppBuilder {
+    let mut builder = App::build();
+    builder.add_plugins(MinimalPlugins).add_plugin(InputPlugin);
+    builder
+}
+
+// 只有玩法逻辑的 App, 玩家由测试自己加入
+pub fn game_app() -> App {
+    game_app_builder().app
+}
+
+pub fn game_app_builder() -> AppBuilder {
+    let mut builder = headless_app();
+    builder
+        .insert_resource(SpawnLocalPlayer(false))
+        .add_plugin(GameCorePlugin)
+        .add_plugin(InputExtPlugin)
+        .add_plugin(PlayerPlugin)
+        .add_plugin(CoinPlugin);
+    builder
+}
+
+// 监听 127.0.0.1 随机端口的服务器
+pub fn server_app() -> App {
+    server_app_with(NetConditions::default())
+}
+
+// 发出的包经过网络状况模拟的服务器
+pub fn server_app_with(conditions: NetConditions) -> App {
+    server_app_builder(conditions).app
+}
+
+// 需要在服务器上添加其它插件时使用
+pub fn server_app_builder(conditions: NetConditions) -> AppBuilder {
+    let mut builder = headless_app();
+    builder
+        .insert_resource(conditions)
+        // 和专用服务器一样没有本地玩家
+        .insert_resource(SpawnLocalPlayer(false))
+        .insert_resource(NetServerSettings {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        })
+        .add_plugin(GameCorePlugin)
+        .add_plugin(InputExtPlugin)
+        .add_plugin(PlayerPlugin)
+        .add_plugin(CoinPlugin)
+        .add_plugin(ElapsedTimePlugin)
+        .add_plugin(NetServerPlugin);
+    builder
+}
+
+pub fn client_app(server_addr: SocketAddr, player_name: &str) -> App {
+    client_app_with(server_addr, player_name, NetConditions::default())
+}
+
+pub fn client_app_with(
+    server_addr: SocketAddr,
+    player_name: &str,
+    conditions: NetConditions,
+) -> App {
+    net_client_app(server_addr, player_name, ClientRole::Player, conditions)
+}
+
+pub fn spectator_app(server_addr: SocketAddr, name: &str) -> App {
+    net_client_app(
+        server_addr,
+        name,
+        ClientRole::Spectator,
+        NetConditions::default(),
+    )
+}
+
+fn net_client_app(
+    server_addr: SocketAddr,
+    player_name: &str,
+    role: ClientRole,
+    conditions: NetConditions,
+) -> App {
+    let mut builder = headless_app();
+    builder
+        .insert_resource(conditions)
+        .insert_resource(NetClientSettings {
+            server_addr,
+            player_name: player_name.to_string(),
+            role,
+            ..Default::default()
+        })
+        .add_plugin(GameCorePlugin)
+        .add_plugin(InputExtPlugin)
+        .add_plugin(NetClientPlugin);
+    builder.app
+}
+
+// 一组在 127.0.0.1 上互联的帧同步节点, 下标即玩家编号
+pub fn lockstep_apps(peer_num: usize, seed: u64) -> Vec<App> {
+    peer_apps(peer_num, seed, 3, 0)
+}
+
+// 不加输入延迟, 靠预测和回滚运行的节点
+pub fn rollback_apps(peer_num: usize, seed: u64) -> Vec<App> {
+    peer_apps(peer_num, seed, 0, 8)
+}
+
+fn peer_apps(peer_num: usize, seed: u64, input_delay: u32, max_prediction: u32) -> Vec<App> {
+    // 各节点绑定随机端口, 创建后再把实际地址告诉其它节点
+    let placeholder: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut apps: Vec<App> = (0..peer_num)
+        .map(|slot| {
+            let mut builder = headless_app();
+            builder
+                .insert_resource(LockstepSettings {
+                    bind_addr: placeholder,
+                    peers: vec![placeholder; peer_num],
+                    local_slot: slot,
+                    seed,
+                    input_delay,
+                    max_prediction,
+                })
+                .add_plugin(GameCorePlugin)
+                .add_plugin(InputExtPlugin)
+                .add_plugin(PlayerPlugin)
+                .add_plugin(CoinPlugin);
+            if max_prediction > 0 {
+                builder.add_plugin(RollbackPlugin);
+            } else {
+                builder.add_plugin(LockstepPlugin);
+            }
+            builder.app
+        })
+        .collect();
+    let peers: Vec<SocketAddr> = apps
+        .iter()
+        .map(|app| app.world.get_resource::<Lockstep>().unwrap().local_addr())
+        .collect();
+    apps.iter_mut().for_each(|app| {
+        let mut lockstep = app.world.get_resource_mut::<Lockstep>().unwrap();
+        peers
+            .iter()
+            .enumerate()
+            .for_each(|(slot, addr)| lockstep.set_peer_addr(slot, *addr));
+        app.world
+            .get_resource_mut::<LockstepSettings>()
+            .unwrap()
+            .peers = peers.clone();
+    });
+    apps
+}
+
+// 同时更新服务器和客户端, 直到 done 返回 true
+pub fn run_until<F: FnMut(&mut App, &mut App) -> bool>(
+    server: &mut App,
+    client: &mut App,
+    mut done: F,
+) {
+    for _ in 0..1000 {
+        server.update();
+        client.update();
+        if done(server, client) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    panic!("condition not reached in time");
+}
+
+// 模拟 ticks 个 tick
+pub fn run(app: &mut App, ticks: u32) {
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .fast_forward(ticks);
+    app.update();
+}
+
+pub fn tick_of(app: &App) -> u32 {
+    app.world.get_resource::<SimulationClock>().unwrap().tick()
+}
+
+pub fn match_state(app: &App) -> MatchState {
+    app.world
+        .get_resource::<State<MatchState>>()
+        .unwrap()
+        .current()
+        .clone()
+}
+
+// 发送按键事件, 下一次 update 时生效
+pub fn key(app: &mut App, key_code: KeyCode, state: ElementState) {
+    app.world
+        .get_resource_mut::<Events<KeyboardInput>>()
+        .unwrap()
+        .send(KeyboardInput {
+            scan_code: 0,
+            key_code: Some(key_code),
+            state,
+        });
+}
@@ -0,0 +1,225 @@
+use bevy::app::Events;
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::Coin;
+use test_bevy_game::plugins::elapsed_time::{ElapsedSeconds, ElapsedTimePlugin};
+use test_bevy_game::plugins::game::{
+    GameRules, GameStage, GameState, MatchResult, MatchState, SimulationClock,
+};
+use test_bevy_game::plugins::game_mode::GameMode;
+use test_bevy_game::plugins::player::{PlayerRoster, Ready, Score, Team, TeamScoreChangedEvent};
+
+mod common;
+use common::{game_app_builder, match_state, run};
+
+// 本局生成的金币数量
+#[derive(Default)]
+struct SpawnedCoins(usize);
+
+fn spawned_coins_system(mut spawned: ResMut<SpawnedCoins>, query: Query<(), Added<Coin>>) {
+    spawned.0 += query.iter().count();
+}
+
+// 玩家出生在金币范围之外, 不移动就不会吃到金币
+fn mode_app(mode: GameMode) -> App {
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(GameRules {
+            mode,
+            spawn_points: vec![Vec2::new(1000.0, 1000.0), Vec2::new(-1000.0, 1000.0)],
+            ..Default::default()
+        })
+        .init_resource::<SpawnedCoins>()
+        .add_plugin(ElapsedTimePlugin)
+        .add_system_to_stage(GameStage::Simulation, spawned_coins_system.system());
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    app
+}
+
+fn join(app: &mut App) -> Entity {
+    let mut roster = app.world.remove_resource::<PlayerRoster>().unwrap();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let rules = app.world.get_resource::<GameRules>().unwrap();
+    let player = roster.join(&mut commands, rules, None);
+    queue.apply(&mut app.world);
+    app.world.insert_resource(roster);
+    app.world.get_mut::<Ready>(player).unwrap().0 = true;
+    player
+}
+
+fn result(app: &App) -> MatchResult {
+    app.world
+        .get_resource::<GameState>()
+        .unwrap()
+        .result()
+        .cloned()
+        .unwrap()
+}
+
+fn win_team_id(app: &App) -> usize {
+    app.world
+        .get_resource::<GameState>()
+        .unwrap()
+        .get_win_team_id()
+}
+
+// 两个队伍各一个玩家, 限时 2 秒, 开局时给第一个玩家 first_score 分
+fn play_time_limit(first_score: usize) -> App {
+    let mut app = mode_app(GameMode::TimeLimit { seconds: 2 });
+    let first = join(&mut app);
+    join(&mut app);
+    run(&mut app, 1);
+    run(&mut app, 1);
+    assert_eq!(match_state(&app), MatchState::Playing);
+    app.world.get_mut::<Score>(first).unwrap().val = first_score;
+
+    run(&mut app, 110);
+    assert_eq!(match_state(&app), MatchState::Playing);
+    assert_eq!(
+        app.world
+            .get_resource::<ElapsedSeconds>()
+            .unwrap()
+            .seconds(),
+        1
+    );
+    run(&mut app, 15);
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    app
+}
+
+#[test]
+fn time_limit_ends_with_highest_score_or_draw() {
+    assert_eq!(win_team_id(&play_time_limit(3)), 1);
+    // 分数相同时没有获胜队伍
+    assert_eq!(win_team_id(&play_time_limit(0)), 0);
+}
+
+#[test]
+fn coin_pool_ends_when_every_coin_is_eaten() {
+    let mut app = mode_app(GameMode::CoinPool { coins: 5 });
+    let player = join(&mut app);
+    run(&mut app, 1);
+    run(&mut app, 1);
+    assert_eq!(match_state(&app), MatchState::Playing);
+
+    let mut rounds = 0;
+    while match_state(&app) == MatchState::Playing {
+        // 把场上所有金币移到玩家身上
+        let position = app.world.get::<Transform>(player).unwrap().translation;
+        let coins: Vec<Entity> = app
+            .world
+            .query_filtered::<Entity, With<Coin>>()
+            .iter(&app.world)
+            .collect();
+        assert!(coins.len() <= 3);
+        coins.into_iter().for_each(|coin| {
+            app.world.get_mut::<Transform>(coin).unwrap().translation = position;
+        });
+        run(&mut app, 1);
+        rounds += 1;
+        assert!(rounds < 20);
+    }
+
+    assert_eq!(app.world.get_resource::<SpawnedCoins>().unwrap().0, 5);
+    assert!(app.world.get::<Score>(player).unwrap().val >= 5);
+    let team_id = app.world.get::<Team>(player).unwrap().id;
+    assert_eq!(win_team_id(&app), team_id);
+    // 每个玩家吃到的金币数也记录在结果里
+    assert_eq!(result(&app).players[0].coins, 5);
+}
+
+#[test]
+fn tied_teams_share_rank() {
+    let scores = vec![(1, 5), (2, 7), (3, 5), (4, 1)].into_iter().collect();
+    let result = MatchResult::new(scores, Vec::new());
+    let ranks: Vec<(usize, usize)> = result
+        .teams
+        .iter()
+        .map(|team| (team.team_id, team.rank))
+        .collect();
+    assert_eq!(ranks, vec![(2, 1), (1, 2), (3, 2), (4, 4)]);
+    assert_eq!(result.win_team_id(), Some(2));
+
+    let scores = vec![(1, 5), (2, 5)].into_iter().collect();
+    let result = MatchResult::new(scores, Vec::new());
+    assert!(result.is_draw());
+    assert_eq!(result.winners(), vec![1, 2]);
+    assert_eq!(result.win_team_id(), None);
+}
+
+// 两个队伍在同一个 tick 达到目标分数
+fn reach_target_together(first_score: usize, second_score: usize) -> App {
+    let mut app = mode_app(GameMode::FirstToScore);
+    let first = join(&mut app);
+    let second = join(&mut app);
+    run(&mut app, 1);
+    run(&mut app, 1);
+    assert_eq!(match_state(&app), MatchState::Playing);
+
+    let target = app.world.get_resource::<GameRules>().unwrap().target_score;
+    let mut events = app
+        .world
+        .get_resource_mut::<Events<TeamScoreChangedEvent>>()
+        .unwrap();
+    events.send(TeamScoreChangedEvent {
+        team_id: 1,
+        team_score: target + first_score,
+    });
+    events.send(TeamScoreChangedEvent {
+        team_id: 2,
+        team_score: target + second_score,
+    });
+    app.world.get_mut::<Score>(first).unwrap().val = target + first_score;
+    app.world.get_mut::<Score>(second).unwrap().val = target + second_score;
+    run(&mut app, 1);
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    app
+}
+
+#[test]
+fn teams_reaching_target_on_the_same_tick_are_ranked_by_score() {
+    let app = reach_target_together(0, 1);
+    assert_eq!(win_team_id(&app), 2);
+    let players: Vec<(usize, usize)> = result(&app)
+        .players
+        .iter()
+        .map(|player| (player.team_id, player.score))
+        .collect();
+    let target = app.world.get_resource::<GameRules>().unwrap().target_score;
+    assert_eq!(players, vec![(2, target + 1), (1, target)]);
+
+    let app = reach_target_together(0, 0);
+    assert_eq!(win_team_id(&app), 0);
+    assert!(result(&app).is_draw());
+}
+
+#[test]
+fn reaching_target_score_outside_of_play_does_not_end_the_match() {
+    let mut app = mode_app(GameMode::FirstToScore);
+    let player = join(&mut app);
+    app.world.get_mut::<Ready>(player).unwrap().0 = false;
+    run(&mut app, 1);
+    assert_eq!(match_state(&app), MatchState::WaitingForBegin);
+
+    // 大厅里收到达到目标分数的事件
+    let target_score = app.world.get_resource::<GameRules>().unwrap().target_score;
+    app.world
+        .get_resource_mut::<Events<TeamScoreChangedEvent>>()
+        .unwrap()
+        .send(TeamScoreChangedEvent {
+            team_id: 1,
+            team_score: target_score,
+        });
+    run(&mut app, 2);
+    assert_eq!(match_state(&app), MatchState::WaitingForBegin);
+    assert!(app
+        .world
+        .get_resource::<GameState>()
+        .unwrap()
+        .result()
+        .is_none());
+}
@@ -0,0 +1,200 @@
+use bevy::app::Events;
+use bevy::input::gamepad::{
+    Gamepad, GamepadAxisType, GamepadButtonType, GamepadEventRaw, GamepadEventType,
+};
+use bevy::input::ElementState;
+use bevy::prelude::*;
+use test_bevy_game::plugins::action_input::ActionInputPlugin;
+use test_bevy_game::plugins::game::SimulationClock;
+use test_bevy_game::plugins::input_ext::{
+    GamepadSeats, PlayerInputSettings, PlayerOperate, ARROW_INPUT_MAP, WASD_INPUT_MAP,
+};
+use test_bevy_game::plugins::player::{LocalPlayer, LocalSeat, SpawnLocalPlayer};
+
+mod common;
+use common::{game_app_builder, key};
+
+fn two_seat_app() -> App {
+    two_seat_app_with(game_app_builder())
+}
+
+fn two_seat_app_with(mut builder: AppBuilder) -> App {
+    builder
+        .insert_resource(SpawnLocalPlayer(true))
+        .insert_resource(PlayerInputSettings::from_seats(&[
+            &WASD_INPUT_MAP,
+            &ARROW_INPUT_MAP,
+        ]));
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    app.update();
+    app
+}
+
+fn gamepad_event(app: &mut App, gamepad: usize, event: GamepadEventType) {
+    app.world
+        .get_resource_mut::<Events<GamepadEventRaw>>()
+        .unwrap()
+        .send(GamepadEventRaw(Gamepad(gamepad), event));
+}
+
+// 模拟 ticks 个 tick, 返回各座位本地玩家的位移
+fn simulate(app: &mut App, ticks: u32) -> Vec<Vec3> {
+    let start = positions(app);
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .fast_forward(ticks);
+    app.update();
+    positions(app)
+        .into_iter()
+        .zip(start)
+        .map(|(end, start)| end - start)
+        .collect()
+}
+
+fn positions(app: &mut App) -> Vec<Vec3> {
+    let mut players: Vec<_> = app
+        .world
+        .query_filtered::<(&LocalSeat, &Transform), With<LocalPlayer>>()
+        .iter(&app.world)
+        .map(|(seat, transform)| (seat.0, transform.translation))
+        .collect();
+    players.sort_by_key(|(seat, _)| *seat);
+    players.into_iter().map(|(_, position)| position).collect()
+}
+
+fn seat_of(app: &App, gamepad: usize) -> Option<usize> {
+    app.world
+        .get_resource::<GamepadSeats>()
+        .unwrap()
+        .seat_of(Gamepad(gamepad))
+}
+
+#[test]
+fn hot_plugged_gamepads_take_free_seats() {
+    let mut app = two_seat_app();
+    gamepad_event(&mut app, 3, GamepadEventType::Connected);
+    gamepad_event(&mut app, 5, GamepadEventType::Connected);
+    app.update();
+    assert_eq!(seat_of(&app, 3), Some(0));
+    assert_eq!(seat_of(&app, 5), Some(1));
+
+    // 座位都被占用时不分配
+    gamepad_event(&mut app, 7, GamepadEventType::Connected);
+    app.update();
+    assert_eq!(seat_of(&app, 7), None);
+
+    // 拔出后空出的座位给新连接的手柄
+    gamepad_event(&mut app, 3, GamepadEventType::Disconnected);
+    app.update();
+    assert_eq!(seat_of(&app, 3), None);
+    gamepad_event(&mut app, 7, GamepadEventType::Disconnected);
+    gamepad_event(&mut app, 7, GamepadEventType::Connected);
+    app.update();
+    assert_eq!(seat_of(&app, 7), Some(0));
+}
+
+#[test]
+fn analog_stick_moves_proportionally_and_dpad_moves_full_speed() {
+    let mut app = two_seat_app();
+    gamepad_event(&mut app, 0, GamepadEventType::Connected);
+    gamepad_event(&mut app, 1, GamepadEventType::Connected);
+    app.update();
+
+    gamepad_event(
+        &mut app,
+        0,
+        GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, 0.6),
+    );
+    gamepad_event(
+        &mut app,
+        1,
+        GamepadEventType::ButtonChanged(GamepadButtonType::DPadRight, 1.0),
+    );
+    app.update();
+    let offsets = simulate(&mut app, 30);
+    assert!(offsets[0].x > 0.0);
+    assert_eq!(offsets[0].y, 0.0);
+    assert!((offsets[0].x / offsets[1].x - 0.6).abs() < 0.001);
+
+    // 摇杆推过阈值时也按下对应方向的操作
+    let input = app.world.get_resource::<Input<PlayerOperate>>().unwrap();
+    assert!(input.pressed(PlayerOperate::MoveRight));
+
+    // 拔出手柄后松开它按下的操作
+    gamepad_event(&mut app, 0, GamepadEventType::Disconnected);
+    gamepad_event(
+        &mut app,
+        1,
+        GamepadEventType::ButtonChanged(GamepadButtonType::DPadRight, 0.0),
+    );
+    app.update();
+    let input = app.world.get_resource::<Input<PlayerOperate>>().unwrap();
+    assert!(!input.pressed(PlayerOperate::MoveRight));
+    let offsets = simulate(&mut app, 10);
+    assert_eq!(offsets, vec![Vec3::ZERO, Vec3::ZERO]);
+}
+
+fn pressed(app: &App, op: PlayerOperate) -> bool {
+    app.world
+        .get_resource::<Input<PlayerOperate>>()
+        .unwrap()
+        .pressed(op)
+}
+
+#[test]
+fn releasing_gamepad_keeps_operates_held_on_keyboard() {
+    let mut builder = game_app_builder();
+    builder.add_plugin(ActionInputPlugin);
+    let mut app = two_seat_app_with(builder);
+    gamepad_event(&mut app, 0, GamepadEventType::Connected);
+    app.update();
+
+    // 按住 W 的同时点一下十字键上
+    key(&mut app, KeyCode::W, ElementState::Pressed);
+    app.update();
+    gamepad_event(
+        &mut app,
+        0,
+        GamepadEventType::ButtonChanged(GamepadButtonType::DPadUp, 1.0),
+    );
+    app.update();
+    assert!(pressed(&app, PlayerOperate::MoveFrond));
+    gamepad_event(
+        &mut app,
+        0,
+        GamepadEventType::ButtonChanged(GamepadButtonType::DPadUp, 0.0),
+    );
+    app.update();
+    assert!(pressed(&app, PlayerOperate::MoveFrond));
+    let offsets = simulate(&mut app, 10);
+    assert!(offsets[0].y > 0.0);
+
+    // Shift+W 疾跑时松开手柄的疾跑键也不停止
+    key(&mut app, KeyCode::LShift, ElementState::Pressed);
+    gamepad_event(
+        &mut app,
+        0,
+        GamepadEventType::ButtonChanged(GamepadButtonType::RightTrigger, 1.0),
+    );
+    app.update();
+    assert!(pressed(&app, PlayerOperate::Sprint));
+    gamepad_event(
+        &mut app,
+        0,
+        GamepadEventType::ButtonChanged(GamepadButtonType::RightTrigger, 0.0),
+    );
+    app.update();
+    assert!(pressed(&app, PlayerOperate::Sprint));
+
+    // 所有来源都松开后才松开
+    key(&mut app, KeyCode::W, ElementState::Released);
+    key(&mut app, KeyCode::LShift, ElementState::Released);
+    app.update();
+    assert!(!pressed(&app, PlayerOperate::MoveFrond));
+    assert!(!pressed(&app, PlayerOperate::Sprint));
+}
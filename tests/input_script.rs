@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use bevy::input::ElementState;
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::Coin;
+use test_bevy_game::plugins::game::{
+    GameRng, GameRules, GameSeed, GameStage, GameState, MatchState, SimulationClock, READY_KEY,
+    SIMULATION_TICK,
+};
+use test_bevy_game::plugins::input_ext::PlayerInputSettings;
+use test_bevy_game::plugins::input_script::{
+    InputScript, InputScriptPlayback, InputScriptPlaybackPlugin, InputScriptRecordPlugin,
+    InputScriptRecorder,
+};
+use test_bevy_game::plugins::player::{
+    IncreasePlayerScoreEvent, LocalPlayer, LocalSeat, PlayerSystem, SpawnLocalPlayer, Team,
+};
+
+mod common;
+use common::{game_app_builder, key, match_state};
+
+// 比赛结果: 结束的 tick, 每个队伍吃到的金币数
+#[derive(Debug, Default, Clone, PartialEq)]
+struct MatchLog {
+    game_over_tick: Option<u32>,
+    coins: BTreeMap<usize, usize>,
+}
+
+fn coin_log_system(
+    mut log: ResMut<MatchLog>,
+    mut score_events: EventReader<IncreasePlayerScoreEvent>,
+    query: Query<&Team>,
+) {
+    score_events.iter().for_each(|event| {
+        let team = query.get(event.player).unwrap().id;
+        *log.coins.entry(team).or_default() += 1;
+    });
+}
+
+fn game_over_log_system(clock: Res<SimulationClock>, mut log: ResMut<MatchLog>) {
+    log.game_over_tick = Some(clock.tick());
+}
+
+fn scripted_app(mut builder: AppBuilder) -> App {
+    builder
+        .insert_resource(SpawnLocalPlayer(true))
+        // 脚本是两个座位录制的
+        .insert_resource(PlayerInputSettings::split_keyboard())
+        .insert_resource(MatchLog::default())
+        .add_system_to_stage(
+            GameStage::Simulation,
+            coin_log_system
+                .system()
+                .after(PlayerSystem::PlayerCollision),
+        )
+        .add_system_set_to_stage(
+            GameStage::Simulation,
+            SystemSet::on_enter(MatchState::GameOver).with_system(game_over_log_system.system()),
+        );
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    app
+}
+
+// 各座位的方向键: 上, 下, 左, 右
+const SEAT_KEYS: [[KeyCode; 4]; 2] = [
+    [KeyCode::W, KeyCode::S, KeyCode::A, KeyCode::D],
+    [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right],
+];
+
+// 按下让每个本地玩家朝最近金币移动的按键
+fn steer_keys(app: &mut App) -> HashSet<KeyCode> {
+    let coins: Vec<Vec3> = app
+        .world
+        .query_filtered::<&Transform, With<Coin>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation)
+        .collect();
+    app.world
+        .query_filtered::<(&LocalSeat, &Transform), With<LocalPlayer>>()
+        .iter(&app.world)
+        .flat_map(|(seat, transform)| {
+            let keys = SEAT_KEYS[seat.0];
+            let position = transform.translation;
+            let target = coins.iter().cloned().min_by(|a, b| {
+                let da = a.distance(position);
+                let db = b.distance(position);
+                da.partial_cmp(&db).unwrap()
+            });
+            let offset = target.map_or(Vec3::ZERO, |target| target - position);
+            vec![
+                (offset.y > 5.0, keys[0]),
+                (offset.y < -5.0, keys[1]),
+                (offset.x < -5.0, keys[2]),
+                (offset.x > 5.0, keys[3]),
+            ]
+        })
+        .filter(|(pressed, _)| *pressed)
+        .map(|(_, key)| key)
+        .collect()
+}
+
+// 用键盘打完一局并录制输入脚本
+fn record_match(seed: u64) -> (MatchLog, InputScript) {
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(GameSeed(seed))
+        .insert_resource(GameRng::from_seed(seed))
+        .add_plugin(InputScriptRecordPlugin);
+    let mut app = scripted_app(builder);
+    app.update();
+    key(&mut app, READY_KEY, ElementState::Pressed);
+    app.update();
+    key(&mut app, READY_KEY, ElementState::Released);
+
+    let mut held = HashSet::new();
+    let mut frame = 0;
+    while match_state(&app) != MatchState::GameOver && frame < 5000 {
+        let keys = steer_keys(&mut app);
+        held.difference(&keys)
+            .for_each(|k| key(&mut app, *k, ElementState::Released));
+        keys.difference(&held)
+            .for_each(|k| key(&mut app, *k, ElementState::Pressed));
+        held = keys;
+        // 每帧模拟的 tick 数不同
+        app.world
+            .get_resource_mut::<SimulationClock>()
+            .unwrap()
+            .fast_forward(1 + frame % 3);
+        app.update();
+        frame += 1;
+    }
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    let script = app
+        .world
+        .get_resource::<InputScriptRecorder>()
+        .unwrap()
+        .script()
+        .clone();
+    assert_eq!(script.seed, seed);
+    (
+        app.world.get_resource::<MatchLog>().unwrap().clone(),
+        script,
+    )
+}
+
+// 只用脚本驱动, 返回比赛结果和获胜队伍. 规则需要和录制时相同
+fn play_script(script: InputScript, rules: GameRules) -> (MatchLog, usize) {
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(rules)
+        .insert_resource(InputScriptPlayback::new(script))
+        .add_plugin(InputScriptPlaybackPlugin);
+    let mut app = scripted_app(builder);
+    let mut frame = 0;
+    while !app
+        .world
+        .get_resource::<InputScriptPlayback>()
+        .unwrap()
+        .is_finished()
+    {
+        app.update();
+        frame += 1;
+        assert!(frame < 5000);
+    }
+    // 脚本结束后不再模拟
+    let tick = app.world.get_resource::<SimulationClock>().unwrap().tick();
+    (0..5).for_each(|_| app.update());
+    assert_eq!(
+        app.world.get_resource::<SimulationClock>().unwrap().tick(),
+        tick
+    );
+    let win_team_id = app
+        .world
+        .get_resource::<GameState>()
+        .unwrap()
+        .get_win_team_id();
+    (
+        app.world.get_resource::<MatchLog>().unwrap().clone(),
+        win_team_id,
+    )
+}
+
+#[test]
+fn recorded_script_reproduces_the_match_without_a_keyboard() {
+    let (recorded, script) = record_match(7);
+    assert!(recorded.game_over_tick.is_some());
+    // 相同输入的连续帧会合并
+    assert!(script.frames.len() < 5000);
+
+    let path =
+        std::env::temp_dir().join(format!("test_bevy_game_script_{}.ron", std::process::id()));
+    script.save(&path).unwrap();
+    let loaded = InputScript::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, script);
+
+    let (played, _) = play_script(loaded, GameRules::default());
+    assert_eq!(played, recorded);
+}
+
+#[test]
+fn two_seat_script_collects_coins_and_team_one_wins() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/scripts/two_seats.ron");
+    let script = InputScript::load(&path).unwrap();
+    // 这个脚本是金币碰撞尺寸还固定为 50x50 时录制的
+    let rules = GameRules {
+        coin_brick_size: Vec2::new(50.0, 50.0),
+        ..Default::default()
+    };
+    let (log, win_team_id) = play_script(script, rules);
+
+    assert_eq!(win_team_id, 1);
+    assert_eq!(log.coins, vec![(1, 11), (2, 8)].into_iter().collect());
+    let seconds = log.game_over_tick.unwrap() as f64 * SIMULATION_TICK;
+    assert!((seconds - 3.05).abs() < 0.001, "game over at {}s", seconds);
+}
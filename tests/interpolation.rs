@@ -0,0 +1,51 @@
+use bevy::prelude::*;
+use test_bevy_game::plugins::net::SnapshotBuffer;
+
+fn buffer(samples: &[(u32, f32)]) -> SnapshotBuffer {
+    let mut buffer = SnapshotBuffer::default();
+    samples
+        .iter()
+        .for_each(|(tick, x)| buffer.push(*tick, Vec3::new(*x, 0.0, 0.0)));
+    buffer
+}
+
+fn x(position: Option<Vec3>) -> f32 {
+    position.unwrap().x
+}
+
+#[test]
+fn interpolates_between_snapshots() {
+    let buffer = buffer(&[(1, 0.0), (2, 10.0), (3, 30.0)]);
+    assert_eq!(x(buffer.sample(0.0, 3, 0.0)), 0.0);
+    assert!((x(buffer.sample(1.5, 3, 0.0)) - 5.0).abs() < 1e-4);
+    assert!((x(buffer.sample(2.25, 3, 0.0)) - 15.0).abs() < 1e-4);
+    assert_eq!(x(buffer.sample(3.0, 3, 0.0)), 30.0);
+}
+
+#[test]
+fn unchanged_entity_holds_position_between_changes() {
+    // tick 2~4 没有变化, 不能把 1 到 5 的移动平摊到整个区间
+    let buffer = buffer(&[(1, 0.0), (5, 10.0)]);
+    assert_eq!(x(buffer.sample(3.0, 5, 0.0)), 0.0);
+    assert!((x(buffer.sample(4.5, 5, 0.0)) - 5.0).abs() < 1e-4);
+}
+
+#[test]
+fn late_snapshots_extrapolate_with_cap() {
+    let buffer = buffer(&[(1, 0.0), (2, 10.0)]);
+    assert!((x(buffer.sample(2.5, 2, 3.0)) - 15.0).abs() < 1e-4);
+    // 超过上限后停在上限位置
+    assert!((x(buffer.sample(10.0, 2, 3.0)) - 40.0).abs() < 1e-4);
+    // 最新快照里没有该实体说明已经停下, 不外推
+    assert_eq!(x(buffer.sample(4.0, 3, 3.0)), 10.0);
+}
+
+#[test]
+fn old_snapshots_are_ignored_and_discarded() {
+    let mut buffer = buffer(&[(1, 0.0), (2, 10.0), (3, 20.0)]);
+    buffer.push(2, Vec3::new(100.0, 0.0, 0.0));
+    assert_eq!(x(buffer.sample(2.0, 3, 0.0)), 10.0);
+
+    buffer.discard_before(2.5);
+    assert_eq!(x(buffer.sample(0.0, 3, 0.0)), 10.0);
+}
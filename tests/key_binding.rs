@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+
+use bevy::app::{Events, ManualEventReader};
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::ElementState;
+use bevy::prelude::*;
+use test_bevy_game::plugins::input_ext::{KeyConflict, PlayerInputSettings, PlayerOperate};
+use test_bevy_game::plugins::key_binding::{
+    KeyBindingPlugin, KeyBindingSettings, KeyBindings, KeyReboundEvent, RebindRequestEvent,
+    RebindResult, REBIND_CANCEL_KEY,
+};
+
+mod common;
+use common::game_app_builder;
+
+fn bindings_path(name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("test_bevy_game_{}_{}", name, std::process::id()))
+        .join("bindings.ron")
+}
+
+fn binding_app(path: &Path) -> App {
+    binding_app_with(path, PlayerInputSettings::split_keyboard())
+}
+
+fn binding_app_with(path: &Path, input_settings: PlayerInputSettings) -> App {
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(input_settings)
+        .insert_resource(KeyBindingSettings {
+            path: Some(path.to_path_buf()),
+        })
+        .add_plugin(KeyBindingPlugin);
+    let mut app = builder.app;
+    app.update();
+    app
+}
+
+fn tap(app: &mut App, key_code: KeyCode) {
+    for state in [ElementState::Pressed, ElementState::Released].iter() {
+        app.world
+            .get_resource_mut::<Events<KeyboardInput>>()
+            .unwrap()
+            .send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(key_code),
+                state: *state,
+            });
+        app.update();
+    }
+}
+
+// 请求改绑并按下 key, 返回改绑结果
+fn rebind(app: &mut App, seat: usize, operate: PlayerOperate, key: KeyCode) -> RebindResult {
+    let mut reader = ManualEventReader::<KeyReboundEvent>::default();
+    app.world
+        .get_resource_mut::<Events<RebindRequestEvent>>()
+        .unwrap()
+        .send(RebindRequestEvent { seat, operate });
+    app.update();
+    tap(app, key);
+    let events = app.world.get_resource::<Events<KeyReboundEvent>>().unwrap();
+    let results: Vec<_> = reader
+        .iter(events)
+        .filter(|e| e.seat == seat && e.operate == operate)
+        .map(|e| e.result)
+        .collect();
+    assert_eq!(results.len(), 1);
+    results[0]
+}
+
+fn settings(app: &App) -> &PlayerInputSettings {
+    app.world.get_resource::<PlayerInputSettings>().unwrap()
+}
+
+#[test]
+fn rebinding_detects_conflicts() {
+    let mut settings = PlayerInputSettings::split_keyboard();
+    assert_eq!(
+        settings.rebind(0, PlayerOperate::MoveFrond, KeyCode::Up),
+        Err(KeyConflict {
+            key: KeyCode::Up,
+            seat: 1,
+            operate: PlayerOperate::MoveFrond,
+        })
+    );
+    assert_eq!(
+        settings.keys_of(0, PlayerOperate::MoveFrond),
+        vec![KeyCode::W]
+    );
+
+    // 让出冲突的按键后可以改绑, 原来的按键解除绑定
+    settings.unbind(KeyCode::Up);
+    settings
+        .rebind(0, PlayerOperate::MoveFrond, KeyCode::Up)
+        .unwrap();
+    assert_eq!(
+        settings.keys_of(0, PlayerOperate::MoveFrond),
+        vec![KeyCode::Up]
+    );
+    assert_eq!(settings.binding_of(KeyCode::W), None);
+    assert!(settings.keys_of(1, PlayerOperate::MoveFrond).is_empty());
+
+    // 绑定文件中重复的按键也是冲突
+    let mut bindings = KeyBindings::from(&settings);
+    bindings.seats[1]
+        .entry(PlayerOperate::MoveBack)
+        .or_default()
+        .push(KeyCode::Up);
+    assert!(bindings.to_settings().is_err());
+}
+
+#[test]
+fn captured_key_is_bound_and_persisted() {
+    let path = bindings_path("capture");
+    let _ = std::fs::remove_file(&path);
+    let mut app = binding_app(&path);
+
+    assert_eq!(
+        rebind(&mut app, 0, PlayerOperate::MoveFrond, KeyCode::I),
+        RebindResult::Bound(KeyCode::I)
+    );
+    assert_eq!(
+        settings(&app).keys_of(0, PlayerOperate::MoveFrond),
+        vec![KeyCode::I]
+    );
+    assert_eq!(
+        rebind(&mut app, 0, PlayerOperate::MoveBack, KeyCode::Down),
+        RebindResult::Conflict(KeyConflict {
+            key: KeyCode::Down,
+            seat: 1,
+            operate: PlayerOperate::MoveBack,
+        })
+    );
+    assert_eq!(
+        rebind(&mut app, 0, PlayerOperate::MoveBack, REBIND_CANCEL_KEY),
+        RebindResult::Cancelled
+    );
+    assert_eq!(
+        settings(&app).keys_of(0, PlayerOperate::MoveBack),
+        vec![KeyCode::S]
+    );
+
+    // 重新启动后读取保存的绑定
+    let saved = settings(&app).clone();
+    let restarted = binding_app(&path);
+    assert_eq!(settings(&restarted), &saved);
+
+    // 不分屏时只读取第一个座位
+    let single = binding_app_with(&path, PlayerInputSettings::default());
+    assert_eq!(settings(&single).seat_num(), 1);
+    assert_eq!(settings(&single).bindings(0), saved.bindings(0));
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::{Coin, CoinInfo};
+use test_bevy_game::plugins::game::{GameStage, SimulationClock};
+use test_bevy_game::plugins::input_ext::PlayerOperate;
+use test_bevy_game::plugins::net::{Lockstep, LockstepSlot};
+use test_bevy_game::plugins::player::{PlayerSystem, Score};
+
+mod common;
+use common::{lockstep_apps, tick_of};
+
+// 每个 tick 结束时的世界状态, 用于比较各节点是否一致
+#[derive(Default)]
+struct TickHistory(BTreeMap<u32, String>);
+
+fn record_tick_system(
+    clock: Res<SimulationClock>,
+    mut history: ResMut<TickHistory>,
+    players: Query<(&LockstepSlot, &Transform, &Score)>,
+    coins: Query<(&Transform, &CoinInfo), With<Coin>>,
+) {
+    let mut players: Vec<_> = players
+        .iter()
+        .map(|(slot, transform, score)| (slot.0, transform.translation, score.val))
+        .collect();
+    players.sort_by_key(|(slot, _, _)| *slot);
+    let coins: Vec<_> = coins
+        .iter()
+        .map(|(transform, info)| (transform.translation, info.score_value))
+        .collect();
+    history
+        .0
+        .insert(clock.tick(), format!("{:?} {:?}", players, coins));
+}
+
+fn input(app: &mut App) -> Mut<'_, Input<PlayerOperate>> {
+    app.world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap()
+}
+
+#[test]
+fn two_peers_simulate_identically() {
+    let mut apps = lockstep_apps(2, 42);
+    apps.iter_mut().for_each(|app| {
+        app.world.insert_resource(TickHistory::default());
+        app.schedule.add_system_to_stage(
+            GameStage::Simulation,
+            record_tick_system.system().after(PlayerSystem::PlayerScore),
+        );
+    });
+
+    input(&mut apps[0]).press(PlayerOperate::MoveRight);
+    input(&mut apps[1]).press(PlayerOperate::MoveFrond);
+
+    for _ in 0..2000 {
+        apps.iter_mut().for_each(|app| app.update());
+        if apps.iter().all(|app| tick_of(app) >= 60) {
+            input(&mut apps[0]).release(PlayerOperate::MoveRight);
+            input(&mut apps[0]).press(PlayerOperate::MoveLeft);
+        }
+        if apps.iter().all(|app| tick_of(app) >= 120) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert!(apps.iter().all(|app| tick_of(app) >= 120));
+
+    let histories: Vec<_> = apps
+        .iter()
+        .map(|app| &app.world.get_resource::<TickHistory>().unwrap().0)
+        .collect();
+    let mut compared = 0;
+    for (tick, state) in histories[0].iter() {
+        if let Some(other) = histories[1].get(tick) {
+            assert_eq!(state, other, "peers desync at tick {}", tick);
+            compared += 1;
+        }
+    }
+    assert!(compared >= 120);
+
+    // 两个节点上的玩家都按各自的输入移动了
+    let mut query = apps[1].world.query::<(&LockstepSlot, &Transform)>();
+    let moved = query
+        .iter(&apps[1].world)
+        .find(|(slot, _)| slot.0 == 1)
+        .map(|(_, transform)| transform.translation.y > -215.0);
+    assert_eq!(moved, Some(true));
+}
+
+#[test]
+fn peer_addr_can_be_replaced_before_the_first_update() {
+    let mut apps = lockstep_apps(2, 42);
+    let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    peer.set_nonblocking(true).unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let mut lockstep = apps[0].world.get_resource_mut::<Lockstep>().unwrap();
+    lockstep.set_peer_addr(1, peer_addr);
+    // 本机编号没有会话, 忽略
+    lockstep.set_peer_addr(0, peer_addr);
+    assert_eq!(lockstep.slot_num(), 2);
+
+    // 发给 1 号的 Hello 到达新地址
+    let mut buf = [0; 1500];
+    let mut received = None;
+    for _ in 0..100 {
+        apps[0].update();
+        if let Ok((size, from)) = peer.recv_from(&mut buf) {
+            received = Some((size, from));
+            break;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    let (size, from) = received.unwrap();
+    assert!(size > 0);
+    assert_eq!(
+        from,
+        apps[0]
+            .world
+            .get_resource::<Lockstep>()
+            .unwrap()
+            .local_addr()
+    );
+}
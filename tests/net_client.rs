@@ -0,0 +1,222 @@
+use std::thread;
+use std::time::Duration;
+
+use bevy::app::Events;
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::Coin;
+use test_bevy_game::plugins::game::{MatchState, ReadyRequestEvent};
+use test_bevy_game::plugins::input_ext::PlayerOperate;
+use test_bevy_game::plugins::net::{ConnectionState, NetClient, NetId, NetServer};
+use test_bevy_game::plugins::player::{LocalPlayer, Player};
+
+mod common;
+use common::{client_app, run_until, server_app, spectator_app};
+
+fn local_player_position(app: &mut App) -> Option<Vec3> {
+    app.world
+        .query_filtered::<&Transform, With<LocalPlayer>>()
+        .iter(&app.world)
+        .next()
+        .map(|transform| transform.translation)
+}
+
+#[test]
+fn client_mirrors_server_players_and_coins() {
+    let mut server = server_app();
+    let server_addr = server
+        .world
+        .get_resource::<NetServer>()
+        .unwrap()
+        .local_addr();
+    let mut client = client_app(server_addr, "alice");
+
+    run_until(&mut server, &mut client, |_, client| {
+        local_player_position(client).is_some()
+    });
+
+    // 准备后比赛开始, 客户端玩家以及所有金币都会出现在客户端
+    client
+        .world
+        .get_resource_mut::<Events<ReadyRequestEvent>>()
+        .unwrap()
+        .send(ReadyRequestEvent { ready: true });
+    run_until(&mut server, &mut client, |server, client| {
+        let coin_num = server
+            .world
+            .query_filtered::<&NetId, With<Coin>>()
+            .iter(&server.world)
+            .count();
+        let mirrored_players = client
+            .world
+            .query_filtered::<&NetId, With<Player>>()
+            .iter(&client.world)
+            .count();
+        let mirrored_coins = client
+            .world
+            .query_filtered::<&NetId, With<Coin>>()
+            .iter(&client.world)
+            .count();
+        coin_num > 0
+            && mirrored_players == 1
+            && mirrored_coins == coin_num
+            && *client
+                .world
+                .get_resource::<State<MatchState>>()
+                .unwrap()
+                .current()
+                == MatchState::Playing
+    });
+
+    let player_id = client
+        .world
+        .get_resource::<NetClient>()
+        .unwrap()
+        .player_id()
+        .unwrap();
+    let start = local_player_position(&mut client).unwrap();
+    client
+        .world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap()
+        .press(PlayerOperate::MoveFrond);
+
+    // 输入经服务器处理后, 服务器和客户端镜像都向上移动
+    run_until(&mut server, &mut client, |server, client| {
+        let server_moved = server
+            .world
+            .query::<(&NetId, &Transform)>()
+            .iter(&server.world)
+            .any(|(id, transform)| id.0 == player_id && transform.translation.y > start.y + 20.0);
+        server_moved && local_player_position(client).unwrap().y > start.y + 20.0
+    });
+}
+
+#[test]
+fn client_reconnects_to_its_player() {
+    let mut server = server_app();
+    let server_addr = server
+        .world
+        .get_resource::<NetServer>()
+        .unwrap()
+        .local_addr();
+    let mut client = client_app(server_addr, "alice");
+    run_until(&mut server, &mut client, |_, client| {
+        local_player_position(client).is_some()
+    });
+    let (player_id, token) = {
+        let net_client = client.world.get_resource::<NetClient>().unwrap();
+        (net_client.player_id(), net_client.session_token())
+    };
+    assert!(token.is_some());
+
+    // 换一个新会话重连, 服务器把原来的玩家交还给客户端
+    client
+        .world
+        .get_resource_mut::<NetClient>()
+        .unwrap()
+        .reconnect();
+    assert_eq!(
+        client
+            .world
+            .get_resource::<NetClient>()
+            .unwrap()
+            .connection_state(),
+        &ConnectionState::Connecting
+    );
+    run_until(&mut server, &mut client, |server, client| {
+        client
+            .world
+            .get_resource::<NetClient>()
+            .unwrap()
+            .connection_state()
+            == &ConnectionState::Connected
+            && server
+                .world
+                .get_resource::<NetServer>()
+                .unwrap()
+                .client_count()
+                == 1
+    });
+    assert_eq!(
+        client
+            .world
+            .get_resource::<NetClient>()
+            .unwrap()
+            .player_id(),
+        player_id
+    );
+    assert_eq!(
+        server
+            .world
+            .query_filtered::<Entity, With<Player>>()
+            .iter(&server.world)
+            .count(),
+        1
+    );
+}
+
+fn run_all_until<F: FnMut(&mut [App]) -> bool>(apps: &mut [App], mut done: F) {
+    for _ in 0..500 {
+        apps.iter_mut().for_each(|app| app.update());
+        if done(apps) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    panic!("condition not reached in time");
+}
+
+fn mirrored_player_ys(app: &mut App) -> Vec<f32> {
+    app.world
+        .query_filtered::<&Transform, (With<Player>, With<NetId>)>()
+        .iter(&app.world)
+        .map(|transform| transform.translation.y)
+        .collect()
+}
+
+#[test]
+fn spectator_mirrors_players_without_controlling_any() {
+    let server = server_app();
+    let server_addr = server
+        .world
+        .get_resource::<NetServer>()
+        .unwrap()
+        .local_addr();
+    let mut apps = vec![
+        server,
+        client_app(server_addr, "alice"),
+        spectator_app(server_addr, "watcher"),
+    ];
+
+    run_all_until(&mut apps, |apps| {
+        local_player_position(&mut apps[1]).is_some() && mirrored_player_ys(&mut apps[2]).len() == 1
+    });
+    let spectator = apps[2].world.get_resource::<NetClient>().unwrap();
+    assert!(spectator.is_spectator() && spectator.player_id().is_none());
+    assert!(local_player_position(&mut apps[2]).is_none());
+
+    // 观战者的按键不会产生输入
+    apps[2]
+        .world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap()
+        .press(PlayerOperate::MoveFrond);
+    for _ in 0..30 {
+        apps.iter_mut().for_each(|app| app.update());
+        thread::sleep(Duration::from_millis(5));
+    }
+    let start = local_player_position(&mut apps[1]).unwrap();
+    assert_eq!(mirrored_player_ys(&mut apps[2]), vec![start.y]);
+
+    // 玩家的移动同步给观战者
+    apps[1]
+        .world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap()
+        .press(PlayerOperate::MoveFrond);
+    run_all_until(&mut apps, |apps| {
+        mirrored_player_ys(&mut apps[2])
+            .iter()
+            .all(|y| *y > start.y + 20.0)
+    });
+}
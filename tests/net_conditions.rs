@@ -0,0 +1,185 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::app::Events;
+use bevy::prelude::*;
+use test_bevy_game::plugins::game::{MatchState, ReadyRequestEvent};
+use test_bevy_game::plugins::input_ext::PlayerOperate;
+use test_bevy_game::plugins::net::conditions::{NetConditionsParseError, NET_SIM_ENV};
+use test_bevy_game::plugins::net::transport::{bind_socket, bind_socket_with, NetSocket};
+use test_bevy_game::plugins::net::{NetClient, NetConditions, NetId, NetServer};
+use test_bevy_game::plugins::player::LocalPlayer;
+
+mod common;
+use common::{client_app_with, run_until, server_app_with};
+
+// 在 timeout 内收取所有到达的包
+fn receive_all(socket: &NetSocket, timeout: Duration) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut buf = [0; 1500];
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        match socket.recv_from(&mut buf) {
+            Ok((size, _)) => packets.push(buf[..size].to_vec()),
+            Err(_) => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+    packets
+}
+
+// 发送方需要被轮询才会发出到期的延迟包
+fn pump(sender: &NetSocket, receiver: &NetSocket, timeout: Duration) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        let mut buf = [0; 1500];
+        let _ = sender.recv_from(&mut buf);
+        packets.extend(receive_all(receiver, Duration::from_millis(2)));
+    }
+    packets
+}
+
+fn local_y(client: &mut App) -> Option<f32> {
+    client
+        .world
+        .query_filtered::<&Transform, With<LocalPlayer>>()
+        .iter(&client.world)
+        .next()
+        .map(|transform| transform.translation.y)
+}
+
+fn conditions(spec: &str) -> NetConditions {
+    spec.parse().unwrap()
+}
+
+#[test]
+fn conditions_parse_from_spec() {
+    assert_eq!(
+        conditions("latency=100, jitter=20,loss=0.05,dup=0.01,reorder=0.5,seed=7"),
+        NetConditions {
+            latency_ms: 100,
+            jitter_ms: 20,
+            loss: 0.05,
+            duplicate: 0.01,
+            reorder: 0.5,
+            seed: Some(7),
+        }
+    );
+    assert!(conditions("").is_ideal());
+    assert!("loss=2".parse::<NetConditions>().is_err());
+    assert!("latency".parse::<NetConditions>().is_err());
+    assert!("bandwidth=10".parse::<NetConditions>().is_err());
+}
+
+#[test]
+fn conditions_from_env_report_bad_spec() {
+    // 只有这个测试读写 NET_SIM
+    std::env::set_var(NET_SIM_ENV, "latency=30");
+    assert_eq!(NetConditions::from_env().unwrap().latency_ms, 30);
+    std::env::set_var(NET_SIM_ENV, "latency=30,loss=2");
+    assert_eq!(
+        NetConditions::from_env(),
+        Err(NetConditionsParseError("loss=2".to_string()))
+    );
+    std::env::remove_var(NET_SIM_ENV);
+    assert!(NetConditions::from_env().unwrap().is_ideal());
+}
+
+#[test]
+fn latency_loss_and_duplication_are_applied() {
+    let receiver = bind_socket("127.0.0.1:0").unwrap();
+    let addr = receiver.local_addr().unwrap();
+
+    let lossy = bind_socket_with("127.0.0.1:0", &conditions("loss=1")).unwrap();
+    (0..20u8).for_each(|i| {
+        lossy.send_to(&[i], addr).unwrap();
+    });
+    assert!(pump(&lossy, &receiver, Duration::from_millis(50)).is_empty());
+
+    let duplicated = bind_socket_with("127.0.0.1:0", &conditions("dup=1")).unwrap();
+    duplicated.send_to(&[1], addr).unwrap();
+    assert_eq!(
+        pump(&duplicated, &receiver, Duration::from_millis(50)),
+        vec![vec![1], vec![1]]
+    );
+
+    let delayed = bind_socket_with("127.0.0.1:0", &conditions("latency=80")).unwrap();
+    let start = Instant::now();
+    delayed.send_to(&[2], addr).unwrap();
+    assert!(pump(&delayed, &receiver, Duration::from_millis(40)).is_empty());
+    assert_eq!(
+        pump(&delayed, &receiver, Duration::from_millis(100)),
+        vec![vec![2]]
+    );
+    assert!(start.elapsed() >= Duration::from_millis(80));
+}
+
+#[test]
+fn reorder_delivers_out_of_order() {
+    let receiver = bind_socket("127.0.0.1:0").unwrap();
+    let addr = receiver.local_addr().unwrap();
+    let sender = bind_socket_with("127.0.0.1:0", &conditions("reorder=0.5,seed=3")).unwrap();
+    (0..50u8).for_each(|i| {
+        sender.send_to(&[i], addr).unwrap();
+    });
+    let packets: Vec<u8> = pump(&sender, &receiver, Duration::from_millis(100))
+        .into_iter()
+        .map(|packet| packet[0])
+        .collect();
+    assert_eq!(packets.len(), 50);
+    let mut sorted = packets.clone();
+    sorted.sort_unstable();
+    assert_eq!(sorted, (0..50).collect::<Vec<_>>());
+    assert_ne!(packets, sorted);
+}
+
+#[test]
+fn client_plays_over_bad_network() {
+    let bad = conditions("latency=30,jitter=20,loss=0.1,dup=0.05,reorder=0.05,seed=1");
+    let mut server = server_app_with(bad.clone());
+    let server_addr = server
+        .world
+        .get_resource::<NetServer>()
+        .unwrap()
+        .local_addr();
+    let mut client = client_app_with(server_addr, "alice", bad);
+
+    run_until(&mut server, &mut client, |_, client| {
+        local_y(client).is_some()
+    });
+    client
+        .world
+        .get_resource_mut::<Events<ReadyRequestEvent>>()
+        .unwrap()
+        .send(ReadyRequestEvent { ready: true });
+    run_until(&mut server, &mut client, |_, client| {
+        *client
+            .world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current()
+            == MatchState::Playing
+    });
+    let player_id = client
+        .world
+        .get_resource::<NetClient>()
+        .unwrap()
+        .player_id()
+        .unwrap();
+    let start = local_y(&mut client).unwrap();
+    client
+        .world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap()
+        .press(PlayerOperate::MoveFrond);
+
+    // 丢包由 KCP 重传补上, 输入最终到达服务器, 客户端也收到确认
+    run_until(&mut server, &mut client, |server, client| {
+        let server_moved = server
+            .world
+            .query::<(&NetId, &Transform)>()
+            .iter(&server.world)
+            .any(|(id, transform)| id.0 == player_id && transform.translation.y > start + 50.0);
+        server_moved && local_y(client).unwrap() > start + 50.0
+    });
+}
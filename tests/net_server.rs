@@ -0,0 +1,651 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use test_bevy_game::plugins::game::{GameRules, MatchState};
+use test_bevy_game::plugins::input_ext::PlayerOperate;
+use test_bevy_game::plugins::net::protocol::{
+    ClientMessage, ClientRole, Codec, CodecError, DisconnectReason, InputFrame, RejectReason,
+    ServerMessage, WorldDelta, WorldSnapshot, PROTOCOL_VERSION,
+};
+use test_bevy_game::plugins::net::transport::{
+    bind_socket, KcpClock, KcpSession, NetSocket, MAX_PACKET_SIZE,
+};
+use test_bevy_game::plugins::net::{NetId, NetServer, NetServerSettings};
+use test_bevy_game::plugins::player::{Movement, Player, Score, Team};
+
+mod common;
+use common::server_app;
+
+struct TestClient {
+    socket: Arc<NetSocket>,
+    session: KcpSession,
+    clock: KcpClock,
+    codec: Codec,
+    input_seq: u32,
+    player_id: Option<u32>,
+    session_token: Option<u64>,
+    rejected: Option<RejectReason>,
+    snapshot: Option<WorldSnapshot>,
+    spectating: bool,
+    team_scores: BTreeMap<usize, usize>,
+}
+
+impl TestClient {
+    fn connect(conv: u32, server_addr: SocketAddr) -> Self {
+        Self::with_codec(conv, server_addr, Codec::default())
+    }
+
+    fn with_codec(conv: u32, server_addr: SocketAddr, codec: Codec) -> Self {
+        let socket = bind_socket("127.0.0.1:0").unwrap();
+        let session = KcpSession::new(conv, socket.clone(), server_addr);
+        TestClient {
+            socket,
+            session,
+            clock: Default::default(),
+            codec,
+            input_seq: 0,
+            player_id: None,
+            session_token: None,
+            rejected: None,
+            snapshot: None,
+            spectating: false,
+            team_scores: Default::default(),
+        }
+    }
+
+    fn send(&mut self, message: &ClientMessage) {
+        self.session.send(&self.codec.encode(message)).unwrap();
+    }
+
+    fn handshake(&mut self, name: &str) {
+        self.resume(name, None);
+    }
+
+    fn resume(&mut self, name: &str, session_token: Option<u64>) {
+        self.join_as(name, session_token, ClientRole::Player);
+    }
+
+    fn spectate(&mut self, name: &str) {
+        self.join_as(name, None, ClientRole::Spectator);
+    }
+
+    fn join_as(&mut self, name: &str, session_token: Option<u64>, role: ClientRole) {
+        let version = self.codec.version();
+        self.send(&ClientMessage::Handshake {
+            version,
+            name: name.to_string(),
+            session_token,
+            role,
+        });
+    }
+
+    fn ready(&mut self) {
+        self.send(&ClientMessage::Ready(true));
+    }
+
+    fn send_input(&mut self, pressed: Vec<PlayerOperate>) {
+        self.send_frame(pressed, 0.1);
+    }
+
+    fn send_frame(&mut self, pressed: Vec<PlayerOperate>, dt: f32) {
+        self.send_analog(pressed, [0.0, 0.0], dt);
+    }
+
+    fn send_analog(&mut self, pressed: Vec<PlayerOperate>, analog: [f32; 2], dt: f32) {
+        self.input_seq += 1;
+        let frame = InputFrame {
+            seq: self.input_seq,
+            dt,
+            pressed,
+            analog,
+        };
+        self.send(&ClientMessage::InputFrame(frame));
+    }
+
+    fn pump(&mut self) {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((size, _)) => self.session.input(&buf[..size]).unwrap(),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        while let Some(bytes) = self.session.recv() {
+            let message = match self.codec.decode::<ServerMessage>(&bytes) {
+                Ok(message) => message,
+                Err(CodecError::VersionMismatch { expected, found }) => {
+                    self.rejected = Some(RejectReason::VersionMismatch {
+                        server: found,
+                        client: expected,
+                    });
+                    continue;
+                }
+                Err(e) => panic!("{}", e),
+            };
+            match message {
+                ServerMessage::HandshakeAccepted {
+                    player_id,
+                    session_token,
+                } => {
+                    self.player_id = Some(player_id);
+                    self.session_token = Some(session_token);
+                }
+                ServerMessage::HandshakeRejected(reason) => self.rejected = Some(reason),
+                ServerMessage::SpectatorAccepted => self.spectating = true,
+                ServerMessage::ScoreUpdate {
+                    team_id,
+                    team_score,
+                } => {
+                    self.team_scores.insert(team_id, team_score);
+                }
+                ServerMessage::EntitySnapshot(snapshot) => self.snapshot = Some(snapshot),
+                ServerMessage::EntityDelta(delta) => self.apply_delta(delta),
+                ServerMessage::MatchStateChanged { state, .. } => {
+                    if let Some(snapshot) = self.snapshot.as_mut() {
+                        snapshot.match_state = state;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.session.update(&self.clock).unwrap();
+    }
+
+    fn apply_delta(&mut self, delta: WorldDelta) {
+        let snapshot = match self.snapshot.as_mut() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        snapshot.tick = delta.tick;
+        for player in delta.players {
+            snapshot.players.retain(|p| p.id != player.id);
+            snapshot.players.push(player);
+        }
+        for coin in delta.coins {
+            snapshot.coins.retain(|c| c.id != coin.id);
+            snapshot.coins.push(coin);
+        }
+        let removed = delta.removed;
+        snapshot.players.retain(|p| !removed.contains(&p.id));
+        snapshot.coins.retain(|c| !removed.contains(&c.id));
+    }
+
+    fn my_position(&self) -> Option<Vec2> {
+        let id = self.player_id?;
+        let snapshot = self.snapshot.as_ref()?;
+        snapshot
+            .players
+            .iter()
+            .find(|p| p.id == id)
+            .map(|p| p.position)
+    }
+}
+
+fn run_until<F: FnMut(&mut App, &mut [TestClient]) -> bool>(
+    app: &mut App,
+    clients: &mut [TestClient],
+    mut done: F,
+) {
+    for _ in 0..500 {
+        app.update();
+        clients.iter_mut().for_each(|c| c.pump());
+        if done(app, clients) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    panic!("condition not reached in time");
+}
+
+#[test]
+fn two_clients_join_and_move_over_loopback() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    let mut clients = vec![
+        TestClient::connect(1, server_addr),
+        TestClient::connect(2, server_addr),
+    ];
+    clients[0].handshake("alice");
+    clients[1].handshake("bob");
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients.iter().all(|c| c.player_id.is_some())
+    });
+    clients.iter_mut().for_each(|c| c.ready());
+
+    // 两个网络玩家都已准备, 比赛已经开始
+    run_until(&mut app, &mut clients, |app, clients| {
+        app.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count()
+            == 2
+            && clients.iter().all(|c| {
+                c.my_position().is_some()
+                    && c.snapshot.iter().any(|s| {
+                        s.players.len() == 2
+                            && s.players.iter().all(|p| p.ready)
+                            && s.match_state == MatchState::Playing
+                            && !s.coins.is_empty()
+                    })
+            })
+    });
+    assert_ne!(clients[0].player_id, clients[1].player_id);
+
+    let start = clients[0].my_position().unwrap();
+    let bob_start = clients[1].my_position().unwrap();
+    clients[0].send_input(vec![PlayerOperate::MoveRight]);
+
+    // 服务器按 alice 的输入移动 alice 的玩家, bob 也能看到
+    let alice_id = clients[0].player_id.unwrap();
+    run_until(&mut app, &mut clients, |_, clients| {
+        let moved = clients[0].my_position().unwrap().x > start.x + 20.0;
+        let seen_by_bob = clients[1].snapshot.iter().any(|s| {
+            s.players
+                .iter()
+                .any(|p| p.id == alice_id && p.position.x > start.x + 20.0)
+        });
+        moved && seen_by_bob
+    });
+    assert_eq!(clients[1].my_position().unwrap(), bob_start);
+
+    clients[1].send(&ClientMessage::Disconnect(DisconnectReason::ClientLeave));
+    run_until(&mut app, &mut clients, |app, clients| {
+        app.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count()
+            == 1
+            && clients[0].snapshot.iter().any(|s| s.players.len() == 1)
+    });
+}
+
+#[test]
+fn client_with_other_protocol_version_is_rejected() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    let mut clients = vec![TestClient::with_codec(
+        1,
+        server_addr,
+        Codec::new(PROTOCOL_VERSION + 1),
+    )];
+    clients[0].handshake("mallory");
+
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].rejected.is_some()
+    });
+    assert_eq!(
+        clients[0].rejected,
+        Some(RejectReason::VersionMismatch {
+            server: PROTOCOL_VERSION,
+            client: PROTOCOL_VERSION + 1,
+        })
+    );
+    assert_eq!(clients[0].player_id, None);
+    assert_eq!(
+        app.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count(),
+        0
+    );
+}
+
+#[test]
+fn short_datagrams_are_dropped() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    // 不到 KCP 包头长度的数据报不能让服务器崩溃, 也不会建立连接
+    let stray = UdpSocket::bind("127.0.0.1:0").unwrap();
+    stray.send_to(&[0], server_addr).unwrap();
+    stray.send_to(&[1, 0, 0, 0, 81], server_addr).unwrap();
+    for _ in 0..10 {
+        app.update();
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(
+        app.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count(),
+        0
+    );
+
+    let mut clients = vec![TestClient::connect(1, server_addr)];
+    clients[0].handshake("alice");
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].player_id.is_some()
+    });
+}
+
+fn server_position(app: &mut App, player_id: u32) -> Vec3 {
+    app.world
+        .query::<(&NetId, &Transform)>()
+        .iter(&app.world)
+        .find(|(id, _)| id.0 == player_id)
+        .map(|(_, transform)| transform.translation)
+        .unwrap()
+}
+
+#[test]
+fn flooded_input_frames_cannot_outrun_server_time() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    let mut clients = vec![TestClient::connect(1, server_addr)];
+    clients[0].handshake("mallory");
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].player_id.is_some()
+    });
+    clients[0].ready();
+    run_until(&mut app, &mut clients, |app, _| {
+        *app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current()
+            == MatchState::Playing
+    });
+
+    let player_id = clients[0].player_id.unwrap();
+    let start = server_position(&mut app, player_id);
+    let started_at = Instant::now();
+    // 一次发送 50 秒的移动输入
+    (0..200).for_each(|_| clients[0].send_frame(vec![PlayerOperate::MoveRight], 0.25));
+    for _ in 0..50 {
+        app.update();
+        clients[0].pump();
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    let moved = server_position(&mut app, player_id).x - start.x;
+    let speed = app
+        .world
+        .query::<&Movement>()
+        .iter(&app.world)
+        .next()
+        .unwrap()
+        .speed;
+    // 接受的输入时长不超过经过的时间加上 0.5 秒的余量
+    let limit = speed * (started_at.elapsed().as_secs_f32() + 0.5);
+    assert!(moved > 0.0);
+    assert!(moved <= limit, "moved {} > {}", moved, limit);
+}
+
+#[test]
+fn analog_input_frames_move_at_stick_speed() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    let mut clients = vec![TestClient::connect(1, server_addr)];
+    clients[0].handshake("alice");
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].player_id.is_some()
+    });
+    clients[0].ready();
+    run_until(&mut app, &mut clients, |app, _| {
+        *app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current()
+            == MatchState::Playing
+    });
+
+    let player_id = clients[0].player_id.unwrap();
+    let start = server_position(&mut app, player_id);
+    // 摇杆向右推一半, 同时按着的方向键被摇杆代替
+    clients[0].send_analog(vec![PlayerOperate::MoveFrond], [0.5, 0.0], 0.1);
+    run_until(&mut app, &mut clients, |app, _| {
+        server_position(app, player_id) != start
+    });
+
+    let moved = server_position(&mut app, player_id) - start;
+    let speed = app
+        .world
+        .query::<&Movement>()
+        .iter(&app.world)
+        .next()
+        .unwrap()
+        .speed;
+    assert!(
+        (moved - Vec3::new(speed * 0.05, 0.0, 0.0)).length() < 1e-3,
+        "moved {:?}",
+        moved
+    );
+}
+
+#[test]
+fn lobby_waits_for_ready_and_rejects_extra_players() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+    let max_player_num = app
+        .world
+        .get_resource::<GameRules>()
+        .unwrap()
+        .max_player_num;
+
+    let mut clients: Vec<_> = (0..max_player_num as u32 + 1)
+        .map(|conv| TestClient::connect(conv + 1, server_addr))
+        .collect();
+    clients
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, c)| c.handshake(&format!("player{}", i)));
+
+    // 超过 max_player_num 的加入请求被拒绝
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients
+            .iter()
+            .all(|c| c.player_id.is_some() || c.rejected.is_some())
+    });
+    let rejected: Vec<_> = clients.iter().filter_map(|c| c.rejected.clone()).collect();
+    assert_eq!(rejected, vec![RejectReason::ServerFull { max_player_num }]);
+
+    // 没有人准备时一直停在大厅
+    for _ in 0..30 {
+        app.update();
+        clients.iter_mut().for_each(|c| c.pump());
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(
+        *app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current(),
+        MatchState::WaitingForBegin
+    );
+
+    clients
+        .iter_mut()
+        .find(|c| c.player_id.is_some())
+        .unwrap()
+        .ready();
+    run_until(&mut app, &mut clients, |app, _| {
+        *app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current()
+            == MatchState::Playing
+    });
+}
+
+fn set_timeouts(app: &mut App, client_timeout_seconds: f32, reconnect_grace_seconds: f32) {
+    let mut settings = app.world.get_resource_mut::<NetServerSettings>().unwrap();
+    settings.client_timeout_seconds = client_timeout_seconds;
+    settings.reconnect_grace_seconds = reconnect_grace_seconds;
+}
+
+fn server_player_num(app: &mut App) -> usize {
+    app.world
+        .query_filtered::<Entity, With<Player>>()
+        .iter(&app.world)
+        .count()
+}
+
+#[test]
+fn dropped_client_resumes_player_with_session_token() {
+    let mut app = server_app();
+    set_timeouts(&mut app, 0.3, 30.0);
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    let mut clients = vec![TestClient::connect(1, server_addr)];
+    clients[0].handshake("alice");
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].my_position().is_some()
+    });
+    let player_id = clients[0].player_id.unwrap();
+    let token = clients[0].session_token.unwrap();
+
+    // 移动一段距离并在服务器上记下分数
+    let start = clients[0].my_position().unwrap();
+    clients[0].send_input(vec![PlayerOperate::MoveRight]);
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].my_position().unwrap().x > start.x + 20.0
+    });
+    let (team_id, position) = {
+        let mut query = app.world.query::<(&NetId, &Team, &mut Score, &Transform)>();
+        let (_, team, mut score, transform) = query
+            .iter_mut(&mut app.world)
+            .find(|(id, ..)| id.0 == player_id)
+            .unwrap();
+        score.val = 5;
+        (team.id, transform.translation.truncate())
+    };
+
+    // 客户端掉线, 服务器断开连接后仍然保留玩家
+    clients.clear();
+    run_until(&mut app, &mut clients, |app, _| {
+        app.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count()
+            == 0
+    });
+    assert_eq!(server_player_num(&mut app), 1);
+
+    // 换一个连接带着会话令牌重连, 找回原来的玩家
+    clients.push(TestClient::connect(2, server_addr));
+    clients[0].resume("alice", Some(token));
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].my_position().is_some()
+    });
+    assert_eq!(clients[0].player_id, Some(player_id));
+    assert_eq!(clients[0].session_token, Some(token));
+    let snapshot = clients[0].snapshot.as_ref().unwrap();
+    assert_eq!(snapshot.players.len(), 1);
+    let player = &snapshot.players[0];
+    assert_eq!(
+        (player.score, player.team_id, player.position),
+        (5, team_id, position)
+    );
+}
+
+#[test]
+fn dropped_player_leaves_after_grace_period() {
+    let mut app = server_app();
+    set_timeouts(&mut app, 0.2, 0.3);
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+
+    let mut clients = vec![TestClient::connect(1, server_addr)];
+    clients[0].handshake("alice");
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].player_id.is_some()
+    });
+    let token = clients[0].session_token.unwrap();
+
+    clients.clear();
+    run_until(&mut app, &mut clients, |app, _| {
+        app.world
+            .get_resource::<NetServer>()
+            .unwrap()
+            .client_count()
+            == 0
+    });
+    assert_eq!(server_player_num(&mut app), 1);
+    run_until(&mut app, &mut clients, |app, _| server_player_num(app) == 0);
+
+    // 宽限期过后令牌失效, 重连会作为新玩家加入
+    clients.push(TestClient::connect(2, server_addr));
+    clients[0].resume("alice", Some(token));
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients[0].player_id.is_some()
+    });
+    assert_ne!(clients[0].session_token, Some(token));
+}
+
+#[test]
+fn spectators_watch_without_taking_player_slots() {
+    let mut app = server_app();
+    let server_addr = app.world.get_resource::<NetServer>().unwrap().local_addr();
+    let max_player_num = app
+        .world
+        .get_resource::<GameRules>()
+        .unwrap()
+        .max_player_num;
+
+    // 先占满玩家名额, 其中一个玩家已经有分数
+    let mut clients: Vec<_> = (0..max_player_num as u32)
+        .map(|conv| TestClient::connect(conv + 1, server_addr))
+        .collect();
+    clients
+        .iter_mut()
+        .enumerate()
+        .for_each(|(i, c)| c.handshake(&format!("player{}", i)));
+    run_until(&mut app, &mut clients, |_, clients| {
+        clients.iter().all(|c| c.player_id.is_some())
+    });
+    let player_id = clients[0].player_id.unwrap();
+    let team_id = {
+        let mut query = app.world.query::<(&NetId, &Team, &mut Score)>();
+        let (_, team, mut score) = query
+            .iter_mut(&mut app.world)
+            .find(|(id, ..)| id.0 == player_id)
+            .unwrap();
+        score.val = 4;
+        team.id
+    };
+
+    // 观战者不受玩家上限限制, 能收到所有玩家和当前队伍总分
+    clients.push(TestClient::connect(100, server_addr));
+    clients[max_player_num].spectate("watcher");
+    run_until(&mut app, &mut clients, |_, clients| {
+        let spectator = &clients[max_player_num];
+        spectator.spectating
+            && spectator
+                .snapshot
+                .iter()
+                .any(|s| s.players.len() == max_player_num)
+            && spectator.team_scores.get(&team_id) == Some(&4)
+    });
+    let server = app.world.get_resource::<NetServer>().unwrap();
+    assert_eq!(
+        (server.client_count(), server.spectator_count()),
+        (max_player_num, 1)
+    );
+    assert!(clients[max_player_num].player_id.is_none());
+
+    // 观战者的准备和输入都被忽略
+    let spectator = &mut clients[max_player_num];
+    spectator.ready();
+    spectator.send_input(vec![PlayerOperate::MoveFrond]);
+    for _ in 0..30 {
+        app.update();
+        clients.iter_mut().for_each(|c| c.pump());
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(
+        *app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current(),
+        MatchState::WaitingForBegin
+    );
+    assert_eq!(server_player_num(&mut app), max_player_num);
+}
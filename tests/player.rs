@@ -0,0 +1,143 @@
+use std::thread;
+use std::time::Duration;
+
+use bevy::app::{Events, ManualEventReader};
+use bevy::prelude::*;
+use test_bevy_game::plugins::game::GameRules;
+use test_bevy_game::plugins::player::{
+    IncreasePlayerScoreEvent, Leaving, PlayerColor, PlayerJoinedEvent, PlayerLeftEvent,
+    PlayerRoster, Score, Team, TeamScoreChangedEvent,
+};
+
+mod common;
+use common::game_app;
+
+fn join(app: &mut App, name: &str) -> Entity {
+    let mut roster = app.world.remove_resource::<PlayerRoster>().unwrap();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let rules = app.world.get_resource::<GameRules>().unwrap();
+    let player = roster.join(&mut commands, rules, Some(name.to_string()));
+    queue.apply(&mut app.world);
+    app.world.insert_resource(roster);
+    player
+}
+
+fn read_events<T: Send + Sync + 'static, R>(
+    app: &App,
+    reader: &mut ManualEventReader<T>,
+    map: impl Fn(&T) -> R,
+) -> Vec<R> {
+    let events = app.world.get_resource::<Events<T>>().unwrap();
+    reader.iter(events).map(map).collect()
+}
+
+#[test]
+fn joined_players_get_team_color_and_spawn_point_by_slot() {
+    let mut app = game_app();
+    let mut joined = app
+        .world
+        .get_resource::<Events<PlayerJoinedEvent>>()
+        .unwrap()
+        .get_reader();
+    let players: Vec<_> = ["alice", "bob", "carol"]
+        .iter()
+        .map(|name| join(&mut app, name))
+        .collect();
+    app.update();
+
+    let events = read_events(&app, &mut joined, |e| (e.player, e.name.clone(), e.team_id));
+    assert_eq!(
+        events,
+        vec![
+            (players[0], "alice".to_string(), 1),
+            (players[1], "bob".to_string(), 2),
+            (players[2], "carol".to_string(), 1),
+        ]
+    );
+
+    // 每个玩家的出生点和颜色都不同
+    let spawn_points = app
+        .world
+        .get_resource::<GameRules>()
+        .unwrap()
+        .spawn_points
+        .clone();
+    let colors: Vec<_> = players
+        .iter()
+        .enumerate()
+        .map(|(slot, player)| {
+            let position = app.world.get::<Transform>(*player).unwrap().translation;
+            assert_eq!(position.truncate(), spawn_points[slot]);
+            app.world.get::<PlayerColor>(*player).unwrap().0
+        })
+        .collect();
+    assert!(colors[0] != colors[1] && colors[1] != colors[2] && colors[0] != colors[2]);
+}
+
+#[test]
+fn leaving_player_keeps_score_for_team_and_frees_slot() {
+    let mut app = game_app();
+    let mut left = app
+        .world
+        .get_resource::<Events<PlayerLeftEvent>>()
+        .unwrap()
+        .get_reader();
+    let mut team_scores = app
+        .world
+        .get_resource::<Events<TeamScoreChangedEvent>>()
+        .unwrap()
+        .get_reader();
+    let alice = join(&mut app, "alice");
+    join(&mut app, "bob");
+    let carol = join(&mut app, "carol");
+    app.update();
+
+    // alice 比赛中途带着 7 分离开
+    app.world.get_mut::<Score>(alice).unwrap().val = 7;
+    app.world.entity_mut(alice).insert(Leaving);
+    app.update();
+
+    assert!(app.world.get_entity(alice).is_none());
+    assert_eq!(
+        read_events(&app, &mut left, |e| (
+            e.player,
+            e.name.clone(),
+            e.team_id,
+            e.score
+        )),
+        vec![(alice, "alice".to_string(), 1, 7)]
+    );
+    assert_eq!(
+        app.world
+            .get_resource::<PlayerRoster>()
+            .unwrap()
+            .player_num(),
+        2
+    );
+
+    // 新玩家补上空出的位置
+    let dave = join(&mut app, "dave");
+    let roster = app.world.get_resource::<PlayerRoster>().unwrap();
+    assert_eq!(roster.slot_of(dave), Some(0));
+    assert_eq!(app.world.get::<Team>(dave).unwrap().id, 1);
+
+    // 队伍总分仍然包含 alice 留下的分数
+    app.world
+        .get_resource_mut::<Events<IncreasePlayerScoreEvent>>()
+        .unwrap()
+        .send(IncreasePlayerScoreEvent {
+            player: carol,
+            score_to_increase: 3,
+        });
+    for _ in 0..100 {
+        app.update();
+        let scores = read_events(&app, &mut team_scores, |e| (e.team_id, e.team_score));
+        if !scores.is_empty() {
+            assert_eq!(scores, vec![(1, 10)]);
+            return;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    panic!("team score not updated in time");
+}
@@ -0,0 +1,64 @@
+use bevy::prelude::*;
+use test_bevy_game::plugins::input_ext::{PlayerOperate, PlayerOperateState};
+use test_bevy_game::plugins::net::InputBuffer;
+
+fn pressed(ops: &[PlayerOperate]) -> PlayerOperateState {
+    PlayerOperateState::from_pressed(ops.iter().cloned())
+}
+
+#[test]
+fn acknowledged_inputs_are_dropped() {
+    let mut buffer = InputBuffer::default();
+    let seqs: Vec<u32> = (0..5)
+        .map(|_| buffer.push(0.1, &pressed(&[PlayerOperate::MoveRight])).seq)
+        .collect();
+    assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+
+    buffer.acknowledge(3);
+    let pending: Vec<u32> = buffer.pending().map(|frame| frame.seq).collect();
+    assert_eq!(pending, vec![4, 5]);
+
+    // 乱序到达的旧确认不影响
+    buffer.acknowledge(1);
+    assert_eq!(buffer.len(), 2);
+    buffer.acknowledge(5);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn replay_applies_unacknowledged_inputs_from_server_position() {
+    let mut buffer = InputBuffer::default();
+    buffer.push(0.1, &pressed(&[PlayerOperate::MoveRight]));
+    buffer.push(0.1, &pressed(&[PlayerOperate::MoveFrond]));
+    buffer.push(
+        0.2,
+        &pressed(&[PlayerOperate::MoveRight, PlayerOperate::MoveFrond]),
+    );
+
+    // 服务器只处理了第一帧, 且因为某些原因位置与预测不同
+    buffer.acknowledge(1);
+    let server_position = Vec3::new(40.0, 0.0, 0.0);
+    let predicted = buffer.replay(server_position, 500.0);
+    assert!((predicted - Vec3::new(140.0, 150.0, 0.0)).length() < 1e-3);
+}
+
+#[test]
+fn full_buffer_drops_oldest_input() {
+    let mut buffer = InputBuffer::with_capacity(3);
+    (0..5).for_each(|_| {
+        buffer.push(0.016, &pressed(&[]));
+    });
+    let pending: Vec<u32> = buffer.pending().map(|frame| frame.seq).collect();
+    assert_eq!(pending, vec![3, 4, 5]);
+}
+
+#[test]
+fn replay_keeps_analog_stick_speed() {
+    let mut buffer = InputBuffer::default();
+    let frame = buffer.push(0.1, &pressed(&[]).with_analog(Vec2::new(0.5, 0.0)));
+    assert_eq!(frame.analog, [0.5, 0.0]);
+
+    // 摇杆推到一半, 速度也是一半
+    let predicted = buffer.replay(Vec3::ZERO, 500.0);
+    assert!((predicted - Vec3::new(25.0, 0.0, 0.0)).length() < 1e-3);
+}
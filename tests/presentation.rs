@@ -0,0 +1,74 @@
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::spawn_coin;
+use test_bevy_game::plugins::game::{Collider, GameRules};
+use test_bevy_game::plugins::player::{spawn_player, PlayerSpawn};
+use test_bevy_game::plugins::presentation::{PresentationMaterials, PresentationPlugin};
+
+#[test]
+fn gameplay_entities_get_sprites_only_with_presentation() {
+    let mut builder = App::build();
+    builder
+        .add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .add_asset::<ColorMaterial>()
+        .add_plugin(PresentationPlugin);
+    let mut app = builder.app;
+
+    let rules = GameRules::default();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let player = spawn_player(
+        &mut commands,
+        &rules,
+        PlayerSpawn {
+            name: "Player 0".to_string(),
+            team_id: 1,
+            color: Color::PINK,
+            position: Vec3::new(10.0, -215.0, 0.0),
+        },
+    );
+    let coin = spawn_coin(&mut commands, &rules, 1, 3, Vec3::new(-20.0, 40.0, 0.0));
+    queue.apply(&mut app.world);
+
+    // 玩法实体本身不带任何显示组件
+    assert!(app.world.get::<Sprite>(player).is_none());
+    assert!(app.world.get::<Handle<ColorMaterial>>(coin).is_none());
+
+    app.update();
+
+    // 玩家按自己的颜色显示
+    let materials = app.world.get_resource::<Assets<ColorMaterial>>().unwrap();
+    let player_material = app.world.get::<Handle<ColorMaterial>>(player).unwrap();
+    assert_eq!(
+        materials
+            .get(player_material)
+            .map(|material| material.color),
+        Some(Color::PINK)
+    );
+
+    let coin_material = app
+        .world
+        .get_resource::<PresentationMaterials>()
+        .unwrap()
+        .coin
+        .clone();
+    for (entity, position) in [
+        (player, Vec3::new(10.0, -215.0, 0.0)),
+        (coin, Vec3::new(-20.0, 40.0, 0.0)),
+    ]
+    .iter()
+    {
+        let collider = app.world.get::<Collider>(*entity).unwrap();
+        let sprite = app.world.get::<Sprite>(*entity).unwrap();
+        assert_eq!(sprite.size, collider.size);
+        assert_eq!(
+            app.world.get::<Transform>(*entity).unwrap().translation,
+            *position
+        );
+    }
+    assert_eq!(
+        app.world.get::<Handle<ColorMaterial>>(coin),
+        Some(&coin_material)
+    );
+}
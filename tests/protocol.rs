@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use test_bevy_game::plugins::game::{MatchResult, MatchState, PlayerResult};
+use test_bevy_game::plugins::input_ext::PlayerOperate;
+use test_bevy_game::plugins::net::protocol::{
+    read_header, ClientMessage, ClientRole, Codec, CodecError, CoinSnapshot, DisconnectReason,
+    InputFrame, LockstepMessage, PlayerSnapshot, RejectReason, ServerMessage, WorldDelta,
+    WorldSnapshot, PROTOCOL_VERSION,
+};
+
+fn player(id: u32, x: f32) -> PlayerSnapshot {
+    PlayerSnapshot {
+        id,
+        name: format!("Player {}", id),
+        team_id: id as usize % 2 + 1,
+        score: id as usize * 10,
+        ready: id > 1,
+        color: [1.0, 0.4, 0.3, 1.0],
+        position: Vec2::new(x, -215.0),
+    }
+}
+
+fn coin(id: u32, score_value: usize) -> CoinSnapshot {
+    CoinSnapshot {
+        id,
+        score_value,
+        position: Vec2::new(id as f32 * 10.0, 100.0),
+    }
+}
+
+fn snapshot(tick: u32) -> WorldSnapshot {
+    WorldSnapshot {
+        tick,
+        match_state: MatchState::Playing,
+        result: None,
+        players: vec![player(1, 0.0), player(2, 50.0)],
+        coins: vec![coin(3, 1), coin(4, 2)],
+    }
+}
+
+fn client_messages() -> Vec<ClientMessage> {
+    vec![
+        ClientMessage::Handshake {
+            version: PROTOCOL_VERSION,
+            name: "alice".to_string(),
+            session_token: None,
+            role: ClientRole::Player,
+        },
+        ClientMessage::Handshake {
+            version: PROTOCOL_VERSION,
+            name: "alice".to_string(),
+            session_token: Some(0x1234_5678_9abc_def0),
+            role: ClientRole::Spectator,
+        },
+        ClientMessage::InputFrame(InputFrame {
+            seq: 42,
+            dt: 1.0 / 60.0,
+            pressed: vec![PlayerOperate::MoveFrond, PlayerOperate::MoveRight],
+            analog: [0.5, -0.25],
+        }),
+        ClientMessage::InputFrame(InputFrame {
+            seq: u32::MAX,
+            dt: 0.0,
+            pressed: vec![],
+            analog: [0.0, 0.0],
+        }),
+        ClientMessage::Ready(true),
+        ClientMessage::Disconnect(DisconnectReason::ClientLeave),
+    ]
+}
+
+fn server_messages() -> Vec<ServerMessage> {
+    let reason = RejectReason::VersionMismatch {
+        server: PROTOCOL_VERSION,
+        client: PROTOCOL_VERSION + 1,
+    };
+    let mut next = snapshot(2);
+    next.players[0].position.x += 5.0;
+    next.coins.pop();
+    vec![
+        ServerMessage::HandshakeAccepted {
+            player_id: 7,
+            session_token: u64::MAX,
+        },
+        ServerMessage::HandshakeRejected(reason.clone()),
+        ServerMessage::SpectatorAccepted,
+        ServerMessage::HandshakeRejected(RejectReason::ServerFull { max_player_num: 2 }),
+        ServerMessage::EntitySnapshot(snapshot(1)),
+        ServerMessage::EntityDelta(next.delta_from(&snapshot(1))),
+        ServerMessage::ScoreUpdate {
+            team_id: 1,
+            team_score: 3,
+        },
+        ServerMessage::CoinPickedup { coin_id: 4 },
+        ServerMessage::ElapsedSeconds { seconds: 59 },
+        ServerMessage::InputAck {
+            seq: 42,
+            position: Vec2::new(-12.5, 215.0),
+        },
+        ServerMessage::MatchStateChanged {
+            state: MatchState::GameOver,
+            result: Some(MatchResult::new(
+                vec![(1, 4), (2, 5)].into_iter().collect::<BTreeMap<_, _>>(),
+                vec![PlayerResult {
+                    name: "Bob".to_string(),
+                    team_id: 2,
+                    score: 5,
+                    coins: 3,
+                }],
+            )),
+        },
+        ServerMessage::Disconnect(DisconnectReason::Timeout),
+        ServerMessage::Disconnect(DisconnectReason::Rejected(reason)),
+    ]
+}
+
+#[test]
+fn every_message_round_trips() {
+    let codec = Codec::default();
+    for message in client_messages() {
+        let bytes = codec.encode(&message);
+        assert_eq!(read_header(&bytes).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(codec.decode::<ClientMessage>(&bytes).unwrap(), message);
+    }
+    for message in server_messages() {
+        let bytes = codec.encode(&message);
+        assert_eq!(read_header(&bytes).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(codec.decode::<ServerMessage>(&bytes).unwrap(), message);
+    }
+    let lockstep_messages = vec![
+        LockstepMessage::Hello { slot: 1, seed: 42 },
+        LockstepMessage::Input {
+            slot: 1,
+            tick: 7,
+            pressed: vec![PlayerOperate::MoveBack],
+        },
+        LockstepMessage::Checksum {
+            slot: 0,
+            tick: 7,
+            hash: u64::MAX,
+        },
+        LockstepMessage::Dump {
+            slot: 0,
+            tick: 7,
+            dump: "tick 7\nmatch_state Playing".to_string(),
+        },
+    ];
+    for message in lockstep_messages {
+        let bytes = codec.encode(&message);
+        assert_eq!(codec.decode::<LockstepMessage>(&bytes).unwrap(), message);
+    }
+}
+
+#[test]
+fn delta_contains_only_changes() {
+    let prev = snapshot(1);
+    assert!(prev.delta_from(&prev).is_empty());
+
+    let mut next = snapshot(2);
+    next.players[1].score += 1;
+    next.coins.remove(0);
+    next.coins.push(coin(5, 3));
+    let delta = next.delta_from(&prev);
+    assert_eq!(
+        delta,
+        WorldDelta {
+            tick: 2,
+            players: vec![next.players[1].clone()],
+            coins: vec![coin(5, 3)],
+            removed: vec![3],
+        }
+    );
+}
+
+#[test]
+fn header_errors_are_reported() {
+    let codec = Codec::default();
+    let bytes = codec.encode(&ClientMessage::Disconnect(DisconnectReason::ClientLeave));
+
+    assert!(matches!(
+        codec.decode::<ClientMessage>(&bytes[..3]),
+        Err(CodecError::Truncated(3))
+    ));
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert!(matches!(
+        codec.decode::<ClientMessage>(&bad_magic),
+        Err(CodecError::BadMagic([b'X', _]))
+    ));
+
+    let newer = Codec::new(PROTOCOL_VERSION + 1).encode(&ClientMessage::Handshake {
+        version: PROTOCOL_VERSION + 1,
+        name: "bob".to_string(),
+        session_token: None,
+        role: ClientRole::Player,
+    });
+    assert!(matches!(
+        codec.decode::<ClientMessage>(&newer),
+        Err(CodecError::VersionMismatch { expected, found })
+            if expected == PROTOCOL_VERSION && found == PROTOCOL_VERSION + 1
+    ));
+
+    let mut trailing = bytes;
+    trailing.push(0);
+    assert!(matches!(
+        codec.decode::<ClientMessage>(&trailing),
+        Err(CodecError::Malformed(_))
+    ));
+}
+
+// 任意输入都只能返回错误, 不能 panic 或者分配超大内存
+#[test]
+fn decode_never_panics_on_garbage() {
+    let codec = Codec::default();
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+
+    for _ in 0..10_000 {
+        let len = rng.gen_range(0..256);
+        let mut bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        if rng.gen_bool(0.5) && bytes.len() >= 4 {
+            // 一半的数据带合法的消息头, 这样才能走到 bincode 解码
+            bytes[..2].copy_from_slice(b"TG");
+            bytes[2..4].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        }
+        let _ = codec.decode::<ClientMessage>(&bytes);
+        let _ = codec.decode::<ServerMessage>(&bytes);
+    }
+
+    let valid: Vec<Vec<u8>> = client_messages()
+        .iter()
+        .map(|m| codec.encode(m))
+        .chain(server_messages().iter().map(|m| codec.encode(m)))
+        .collect();
+    for _ in 0..10_000 {
+        let mut bytes = valid[rng.gen_range(0..valid.len())].clone();
+        match rng.gen_range(0..3) {
+            0 => bytes.truncate(rng.gen_range(0..bytes.len())),
+            1 => {
+                let flips = rng.gen_range(1..4);
+                for _ in 0..flips {
+                    let i = rng.gen_range(0..bytes.len());
+                    bytes[i] = rng.gen();
+                }
+            }
+            _ => {
+                let i = rng.gen_range(0..=bytes.len());
+                bytes.insert(i, rng.gen());
+            }
+        }
+        let _ = codec.decode::<ClientMessage>(&bytes);
+        let _ = codec.decode::<ServerMessage>(&bytes);
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use bevy::app::Events;
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::{Coin, CoinInfo};
+use test_bevy_game::plugins::game::{
+    GameRules, GameRulesChangedEvent, GameStage, GameState, MatchState, ReadyRequestEvent,
+    SimulationClock,
+};
+use test_bevy_game::plugins::input_ext::{PlayerOperate, PlayerOperateState};
+use test_bevy_game::plugins::net::{NetConditions, NetServer};
+use test_bevy_game::plugins::player::{
+    LocalPlayer, Player, PlayerInfo, PlayerRoster, Ready, Score,
+};
+use test_bevy_game::plugins::replay::{
+    Replay, ReplayDesyncUI, ReplayError, ReplayPlayback, ReplayPlaybackPlugin, ReplayRecordPlugin,
+    ReplayRecorder, MAX_REPLAY_SIZE, MAX_REPLAY_SPEED, REPLAY_VERSION,
+};
+
+mod common;
+use common::{client_app, game_app_builder, match_state, run_until, server_app_builder, tick_of};
+
+// 每个 tick 新出现的金币: (分值, x, y)
+#[derive(Default)]
+struct CoinLog(BTreeMap<u32, Vec<(usize, f32, f32)>>);
+
+fn coin_log_system(
+    clock: Res<SimulationClock>,
+    mut log: ResMut<CoinLog>,
+    query: Query<(&CoinInfo, &Transform), Added<Coin>>,
+) {
+    let coins: Vec<_> = query
+        .iter()
+        .map(|(info, transform)| {
+            let position = transform.translation;
+            (info.score_value, position.x, position.y)
+        })
+        .collect();
+    if !coins.is_empty() {
+        log.0.insert(clock.tick(), coins);
+    }
+}
+
+fn with_coin_log(app: &mut App) {
+    app.world.insert_resource(CoinLog::default());
+    app.schedule
+        .add_system_to_stage(GameStage::Simulation, coin_log_system.system());
+}
+
+fn replay_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "test_bevy_game_{}_{}.replay",
+        name,
+        std::process::id()
+    ))
+}
+
+fn join(app: &mut App, name: &str) -> Entity {
+    let mut roster = app.world.remove_resource::<PlayerRoster>().unwrap();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let rules = app.world.get_resource::<GameRules>().unwrap();
+    let player = roster.join(&mut commands, rules, Some(name.to_string()));
+    queue.apply(&mut app.world);
+    app.world.insert_resource(roster);
+    app.world.get_mut::<Ready>(player).unwrap().0 = true;
+    player
+}
+
+fn win_team_id(app: &App) -> usize {
+    app.world
+        .get_resource::<GameState>()
+        .unwrap()
+        .get_win_team_id()
+}
+
+fn scores(app: &mut App) -> BTreeMap<String, usize> {
+    app.world
+        .query_filtered::<(&PlayerInfo, &Score), With<Player>>()
+        .iter(&app.world)
+        .map(|(info, score)| (info.name.clone(), score.val))
+        .collect()
+}
+
+// 每个玩家朝最近的金币移动
+fn coin_positions(app: &mut App) -> Vec<Vec3> {
+    app.world
+        .query_filtered::<&Transform, With<Coin>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation)
+        .collect()
+}
+
+// 朝最近的金币移动需要按下的方向
+fn steer(position: Vec3, coins: &[Vec3]) -> Vec<PlayerOperate> {
+    let target = coins.iter().cloned().min_by(|a, b| {
+        let da = a.distance(position);
+        let db = b.distance(position);
+        da.partial_cmp(&db).unwrap()
+    });
+    let mut ops = Vec::new();
+    if let Some(target) = target {
+        let offset = target - position;
+        if offset.x > 5.0 {
+            ops.push(PlayerOperate::MoveRight);
+        } else if offset.x < -5.0 {
+            ops.push(PlayerOperate::MoveLeft);
+        }
+        if offset.y > 5.0 {
+            ops.push(PlayerOperate::MoveFrond);
+        } else if offset.y < -5.0 {
+            ops.push(PlayerOperate::MoveBack);
+        }
+    }
+    ops
+}
+
+fn steer_to_coins(app: &mut App) {
+    let coins = coin_positions(app);
+    app.world
+        .query_filtered::<(&Transform, &mut PlayerOperateState), With<Player>>()
+        .iter_mut(&mut app.world)
+        .for_each(|(transform, mut state)| {
+            *state = PlayerOperateState::from_pressed(steer(transform.translation, &coins));
+        });
+}
+
+fn player_positions(app: &mut App) -> Vec<Vec3> {
+    app.world
+        .query_filtered::<&Transform, With<Player>>()
+        .iter(&app.world)
+        .map(|transform| transform.translation)
+        .collect()
+}
+
+// 客户端按键控制本地玩家朝金币移动
+fn steer_client_to_coins(client: &mut App) {
+    let coins = coin_positions(client);
+    let position = client
+        .world
+        .query_filtered::<&Transform, With<LocalPlayer>>()
+        .iter(&client.world)
+        .next()
+        .map(|transform| transform.translation);
+    let ops = position.map_or_else(Vec::new, |position| steer(position, &coins));
+    let mut input = client
+        .world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap();
+    [
+        PlayerOperate::MoveFrond,
+        PlayerOperate::MoveBack,
+        PlayerOperate::MoveLeft,
+        PlayerOperate::MoveRight,
+    ]
+    .iter()
+    .for_each(|op| {
+        if ops.contains(op) {
+            input.press(*op);
+        } else {
+            input.release(*op);
+        }
+    });
+}
+
+// 两个玩家抢金币直到比赛结束, 返回录像
+fn record_match(name: &str) -> (App, Replay) {
+    record_match_with(name, |_, _| {})
+}
+
+// 同 record_match, 每帧更新前调用 before_frame
+fn record_match_with(name: &str, mut before_frame: impl FnMut(&mut App, usize)) -> (App, Replay) {
+    let path = replay_path(name);
+    let mut builder = game_app_builder();
+    builder
+        .add_plugin(ReplayRecordPlugin)
+        .insert_resource(ReplayRecorder::new(&path));
+    let mut app = builder.app;
+    with_coin_log(&mut app);
+    app.world
+        .get_resource_mut::<GameRules>()
+        .unwrap()
+        .target_score = 10;
+    join(&mut app, "alice");
+    join(&mut app, "bob");
+
+    // 只按 fast_forward 推进, 每帧模拟的 tick 数固定
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    let mut frame = 0;
+    while match_state(&app) != MatchState::GameOver && frame < 5000 {
+        steer_to_coins(&mut app);
+        before_frame(&mut app, frame);
+        app.world
+            .get_resource_mut::<SimulationClock>()
+            .unwrap()
+            .fast_forward(3);
+        app.update();
+        frame += 1;
+    }
+    assert_eq!(match_state(&app), MatchState::GameOver);
+
+    let recorder = app.world.get_resource::<ReplayRecorder>().unwrap();
+    assert!(recorder.is_finished());
+    let replay = Replay::load(&path).unwrap();
+    assert_eq!(Some(&replay), recorder.replay());
+    std::fs::remove_file(&path).unwrap();
+    (app, replay)
+}
+
+fn playback_app(replay: Replay) -> App {
+    let mut playback = ReplayPlayback::new(replay);
+    // 暂停后只靠 seek 推进
+    playback.set_paused(true);
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(playback)
+        .add_plugin(ReplayPlaybackPlugin);
+    let mut app = builder.app;
+    with_coin_log(&mut app);
+    app
+}
+
+fn seek(app: &mut App, tick: u32) {
+    app.world
+        .get_resource_mut::<ReplayPlayback>()
+        .unwrap()
+        .seek(tick);
+    app.update();
+}
+
+#[test]
+fn replay_reproduces_coin_spawns_and_winner() {
+    let (mut recorded, replay) = record_match("reproduce");
+    let tick_num = replay.tick_num();
+    assert_eq!(replay.players.len(), 2);
+    assert_eq!(
+        replay.result.as_ref(),
+        recorded.world.get_resource::<GameState>().unwrap().result()
+    );
+
+    let mut app = playback_app(replay);
+    seek(&mut app, tick_num);
+    assert_eq!(tick_of(&app), tick_num);
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    assert_eq!(win_team_id(&app), win_team_id(&recorded));
+    assert_eq!(
+        app.world.get_resource::<CoinLog>().unwrap().0,
+        recorded.world.get_resource::<CoinLog>().unwrap().0
+    );
+    assert_eq!(scores(&mut app), scores(&mut recorded));
+
+    // 到达录像结尾后不再继续模拟
+    app.world
+        .get_resource_mut::<ReplayPlayback>()
+        .unwrap()
+        .set_paused(false);
+    (0..5).for_each(|_| app.update());
+    assert_eq!(tick_of(&app), tick_num);
+    assert!(desync_text(&mut app).is_none());
+}
+
+#[test]
+fn seeking_backwards_resimulates_from_the_start() {
+    let (recorded, replay) = record_match("seek");
+    let tick_num = replay.tick_num();
+    let expected = recorded.world.get_resource::<CoinLog>().unwrap().0.clone();
+
+    let mut app = playback_app(replay);
+    seek(&mut app, tick_num);
+    assert_eq!(match_state(&app), MatchState::GameOver);
+
+    let half = tick_num / 2;
+    seek(&mut app, half);
+    assert_eq!(tick_of(&app), half);
+    assert_eq!(match_state(&app), MatchState::Playing);
+    let log = &app.world.get_resource::<CoinLog>().unwrap().0;
+    assert_eq!(
+        log.range(..=half).collect::<Vec<_>>(),
+        expected.range(..=half).collect::<Vec<_>>()
+    );
+
+    // 暂停时不前进
+    (0..5).for_each(|_| app.update());
+    assert_eq!(tick_of(&app), half);
+
+    seek(&mut app, tick_num);
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    assert_eq!(win_team_id(&app), win_team_id(&recorded));
+    assert_eq!(app.world.get_resource::<CoinLog>().unwrap().0, expected);
+}
+
+// 屏幕上显示的不同步提示
+fn desync_text(app: &mut App) -> Option<String> {
+    app.world
+        .query_filtered::<&Text, With<ReplayDesyncUI>>()
+        .iter(&app.world)
+        .next()
+        .map(|text| text.sections[0].value.clone())
+}
+
+#[test]
+fn desynced_playback_is_shown_on_screen() {
+    let (_, replay) = record_match("desync");
+    let tick_num = replay.tick_num();
+
+    // 录像中的结果和重演的结果不同
+    let mut tampered = replay.clone();
+    tampered.result.as_mut().unwrap().teams[0].score += 1;
+    let mut app = playback_app(tampered);
+    seek(&mut app, tick_num);
+    app.update();
+    let desync = app
+        .world
+        .get_resource::<ReplayPlayback>()
+        .unwrap()
+        .desync()
+        .cloned()
+        .unwrap();
+    assert_eq!(desync.tick, tick_num);
+    assert!(desync.result.is_some());
+    assert_eq!(desync_text(&mut app), Some(desync.to_string()));
+
+    // 从头重新模拟时清除, 再次到达结尾时重新报告
+    seek(&mut app, tick_num / 2);
+    app.update();
+    assert!(desync_text(&mut app).is_none());
+    seek(&mut app, tick_num);
+    app.update();
+    assert!(desync_text(&mut app).is_some());
+
+    // 目标分数变大, 回放到最后比赛也没有结束
+    let mut tampered = replay;
+    tampered.rules.target_score *= 10;
+    let mut app = playback_app(tampered);
+    seek(&mut app, tick_num);
+    app.update();
+    assert_eq!(match_state(&app), MatchState::Playing);
+    let desync = app
+        .world
+        .get_resource::<ReplayPlayback>()
+        .unwrap()
+        .desync()
+        .cloned()
+        .unwrap();
+    assert_eq!(desync.result, None);
+    assert!(desync_text(&mut app).unwrap().contains("no result"));
+}
+
+#[test]
+fn playback_speed_is_clamped_and_bad_files_are_rejected() {
+    let mut playback = ReplayPlayback::new(Replay::new(&GameRules::default(), 1));
+    playback.set_speed(100.0);
+    assert_eq!(playback.speed(), MAX_REPLAY_SPEED);
+
+    let replay = Replay::new(&GameRules::default(), 42);
+    let mut bytes = replay.encode();
+    assert_eq!(Replay::decode(&bytes).unwrap(), replay);
+
+    bytes[4..6].copy_from_slice(&(REPLAY_VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        Replay::decode(&bytes),
+        Err(ReplayError::VersionMismatch { .. })
+    ));
+    assert!(matches!(
+        Replay::decode(b"not a replay"),
+        Err(ReplayError::BadMagic(_))
+    ));
+
+    let mut bytes = replay.encode();
+    bytes.push(0);
+    assert!(matches!(
+        Replay::decode(&bytes),
+        Err(ReplayError::Malformed(_))
+    ));
+    bytes.resize(MAX_REPLAY_SIZE as usize + 1, 0);
+    assert!(matches!(
+        Replay::decode(&bytes),
+        Err(ReplayError::TooLarge(_))
+    ));
+}
+
+#[test]
+fn server_match_with_remote_client_replays_to_the_same_result() {
+    let path = replay_path("remote");
+    let mut builder = server_app_builder(NetConditions::default());
+    builder
+        .add_plugin(ReplayRecordPlugin)
+        .insert_resource(ReplayRecorder::new(&path));
+    let mut server = builder.app;
+    server
+        .world
+        .get_resource_mut::<GameRules>()
+        .unwrap()
+        .target_score = 10;
+    let server_addr = server
+        .world
+        .get_resource::<NetServer>()
+        .unwrap()
+        .local_addr();
+    let mut client = client_app(server_addr, "alice");
+    run_until(&mut server, &mut client, |server, _| {
+        server
+            .world
+            .get_resource::<PlayerRoster>()
+            .unwrap()
+            .player_num()
+            == 1
+    });
+    client
+        .world
+        .get_resource_mut::<Events<ReadyRequestEvent>>()
+        .unwrap()
+        .send(ReadyRequestEvent { ready: true });
+
+    // 远程玩家按客户端输入帧的时长移动, 和服务器的 tick 不对齐
+    for _ in 0..3000 {
+        if match_state(&server) == MatchState::GameOver {
+            break;
+        }
+        steer_client_to_coins(&mut client);
+        server.update();
+        client.update();
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert_eq!(match_state(&server), MatchState::GameOver);
+    let recorded = server
+        .world
+        .get_resource::<GameState>()
+        .unwrap()
+        .result()
+        .cloned()
+        .unwrap();
+    assert!(recorded.players[0].coins > 0);
+    let position = player_positions(&mut server);
+
+    let replay = Replay::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(replay
+        .ticks
+        .iter()
+        .flatten()
+        .all(|input| input.offset.is_some()));
+    let tick_num = replay.tick_num();
+    let mut app = playback_app(replay);
+    seek(&mut app, tick_num);
+    app.update();
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    assert_eq!(
+        app.world.get_resource::<GameState>().unwrap().result(),
+        Some(&recorded)
+    );
+    assert_eq!(player_positions(&mut app), position);
+    assert!(app
+        .world
+        .get_resource::<ReplayPlayback>()
+        .unwrap()
+        .desync()
+        .is_none());
+}
+
+// 和规则文件热更新一样替换 GameRules 并发送事件
+fn reload_rules(app: &mut App, change: impl FnOnce(&mut GameRules)) {
+    let mut rules = app.world.get_resource_mut::<GameRules>().unwrap();
+    let previous = rules.clone();
+    change(&mut rules);
+    app.world
+        .get_resource_mut::<Events<GameRulesChangedEvent>>()
+        .unwrap()
+        .send(GameRulesChangedEvent { previous });
+}
+
+#[test]
+fn rules_reloaded_during_a_match_are_replayed() {
+    let (mut recorded, replay) = record_match_with("reload", |app, frame| {
+        if frame == 20 {
+            reload_rules(app, |rules| {
+                rules.target_score = 6;
+                rules.min_coin_score_value = 2;
+                rules.max_coin_score_value = 4;
+                rules.coin_brick_size = Vec2::new(60.0, 60.0);
+            });
+        }
+    });
+    assert_eq!(replay.rule_changes.len(), 1);
+    assert_eq!(replay.rule_changes[0].rules.target_score, 6);
+    assert_eq!(replay.rules.target_score, 10);
+    let tick_num = replay.tick_num();
+
+    let mut without_changes = replay.clone();
+    without_changes.rule_changes.clear();
+
+    let mut app = playback_app(replay);
+    seek(&mut app, tick_num);
+    assert_eq!(match_state(&app), MatchState::GameOver);
+    assert_eq!(win_team_id(&app), win_team_id(&recorded));
+    assert_eq!(
+        app.world.get_resource::<CoinLog>().unwrap().0,
+        recorded.world.get_resource::<CoinLog>().unwrap().0
+    );
+    assert_eq!(scores(&mut app), scores(&mut recorded));
+    assert!(desync_text(&mut app).is_none());
+
+    // 丢掉规则变化后回放结果不同
+    let mut app = playback_app(without_changes);
+    seek(&mut app, tick_num);
+    assert_ne!(
+        app.world.get_resource::<CoinLog>().unwrap().0,
+        recorded.world.get_resource::<CoinLog>().unwrap().0
+    );
+}
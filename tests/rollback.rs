@@ -0,0 +1,106 @@
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use test_bevy_game::plugins::game::GameRules;
+use test_bevy_game::plugins::input_ext::PlayerOperate;
+use test_bevy_game::plugins::net::{
+    DesyncDetectedEvent, Lockstep, LockstepSlot, RollbackBuffer, WorldChecksums,
+};
+
+mod common;
+use common::{rollback_apps, tick_of};
+
+#[derive(Default)]
+struct DesyncLog(Vec<DesyncDetectedEvent>);
+
+fn collect_desync_system(mut log: ResMut<DesyncLog>, mut events: EventReader<DesyncDetectedEvent>) {
+    log.0.extend(events.iter().cloned());
+}
+
+fn input(app: &mut App) -> Mut<'_, Input<PlayerOperate>> {
+    app.world
+        .get_resource_mut::<Input<PlayerOperate>>()
+        .unwrap()
+}
+
+#[test]
+fn late_inputs_are_rolled_back_and_peers_converge() {
+    let mut apps = rollback_apps(2, 11);
+    apps.iter_mut().for_each(|app| {
+        app.world.insert_resource(DesyncLog::default());
+        app.schedule
+            .add_system_to_stage(CoreStage::Last, collect_desync_system.system());
+    });
+
+    // 两边在不同时刻切换输入, 对方只能先预测再回滚
+    let mut frame = 0;
+    while apps.iter().any(|app| tick_of(app) < 150) && frame < 3000 {
+        match frame {
+            10 => input(&mut apps[0]).press(PlayerOperate::MoveRight),
+            25 => input(&mut apps[1]).press(PlayerOperate::MoveFrond),
+            40 => input(&mut apps[0]).release(PlayerOperate::MoveRight),
+            55 => input(&mut apps[1]).release(PlayerOperate::MoveFrond),
+            70 => input(&mut apps[1]).press(PlayerOperate::MoveLeft),
+            _ => {}
+        }
+        apps.iter_mut().for_each(|app| app.update());
+        thread::sleep(Duration::from_millis(2));
+        frame += 1;
+    }
+    input(&mut apps[1]).release(PlayerOperate::MoveLeft);
+    // 停止输入后多跑一会, 让输入和校验值都交换完
+    for _ in 0..50 {
+        apps.iter_mut().for_each(|app| app.update());
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    let rollbacks: usize = apps
+        .iter()
+        .map(|app| {
+            app.world
+                .get_resource::<RollbackBuffer>()
+                .unwrap()
+                .rollback_count()
+        })
+        .sum();
+    assert!(rollbacks > 0, "remote inputs should have been mispredicted");
+
+    let confirmed = apps
+        .iter()
+        .map(|app| {
+            app.world
+                .get_resource::<Lockstep>()
+                .unwrap()
+                .confirmed_tick()
+        })
+        .min()
+        .unwrap();
+    assert!(confirmed >= 150);
+    let checksums: Vec<_> = apps
+        .iter()
+        .map(|app| app.world.get_resource::<WorldChecksums>().unwrap())
+        .collect();
+    (1..=confirmed).for_each(|tick| {
+        assert_eq!(
+            checksums[0].dump(tick),
+            checksums[1].dump(tick),
+            "peers differ at tick {}",
+            tick
+        );
+    });
+    assert!(apps
+        .iter()
+        .all(|app| app.world.get_resource::<DesyncLog>().unwrap().0.is_empty()));
+
+    // 两个玩家都按输入移动过
+    let mut query = apps[0].world.query::<(&LockstepSlot, &Transform)>();
+    let positions: Vec<_> = query
+        .iter(&apps[0].world)
+        .map(|(slot, transform)| (slot.0, transform.translation))
+        .collect();
+    let rules = apps[0].world.get_resource::<GameRules>().unwrap();
+    assert!(positions
+        .iter()
+        .all(|(slot, position)| position.truncate() != rules.spawn_points[*slot]));
+}
@@ -0,0 +1,267 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use test_bevy_game::plugins::coin::Coin;
+use test_bevy_game::plugins::game::{
+    Collider, GameRules, GameRulesChangedEvent, MatchState, SimulationClock,
+};
+use test_bevy_game::plugins::game_mode::GameMode;
+use test_bevy_game::plugins::player::{PlayerRoster, Ready};
+use test_bevy_game::plugins::rules::{RulesError, RulesHotReloadPlugin, RulesReloadSettings};
+
+mod common;
+use common::{game_app_builder, run};
+
+fn invalid_field(result: Result<GameRules, RulesError>) -> &'static str {
+    match result {
+        Err(RulesError::Invalid { field, .. }) => field,
+        other => panic!("expected invalid rules, got {:?}", other),
+    }
+}
+
+fn parse_error(result: Result<GameRules, RulesError>) -> String {
+    match result {
+        Err(e @ RulesError::Parse(_)) => e.to_string(),
+        other => panic!("expected parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn example_rules_file_matches_defaults() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("rules.ron");
+    assert_eq!(GameRules::load(&path).unwrap(), GameRules::default());
+}
+
+#[test]
+fn missing_fields_use_defaults() {
+    let rules = GameRules::from_toml(
+        r#"
+        target_score = 10
+        spawn_points = [[0.0, 1.0]]
+        font_path = "fonts/Other.ttf"
+        mode = { TimeLimit = { seconds = 90 } }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(rules.target_score, 10);
+    assert_eq!(rules.spawn_points, vec![Vec2::new(0.0, 1.0)]);
+    assert_eq!(rules.font_path, "fonts/Other.ttf");
+    assert_eq!(rules.mode, GameMode::TimeLimit { seconds: 90 });
+    assert_eq!(rules.max_coin_num, GameRules::default().max_coin_num);
+
+    let rules = GameRules::from_ron("(max_coin_num: 5, coin_brick_size: (10.0, 20.0))").unwrap();
+    assert_eq!(rules.max_coin_num, 5);
+    assert_eq!(rules.coin_brick_size, Vec2::new(10.0, 20.0));
+    assert_eq!(rules.target_score, GameRules::default().target_score);
+}
+
+#[test]
+fn invalid_values_name_the_bad_field() {
+    let result = GameRules::from_ron("(min_coin_score_value: 5, max_coin_score_value: 5)");
+    let message = result.as_ref().unwrap_err().to_string();
+    assert_eq!(invalid_field(result), "min_coin_score_value");
+    assert!(message.contains("max_coin_score_value"), "{}", message);
+
+    assert_eq!(
+        invalid_field(GameRules::from_toml(
+            "min_player_num = 3\nmax_player_num = 2"
+        )),
+        "min_player_num"
+    );
+    assert_eq!(
+        invalid_field(GameRules::from_ron("(spawn_points: [])")),
+        "spawn_points"
+    );
+    assert_eq!(
+        invalid_field(GameRules::from_ron("(player_brick_size: (0.0, 50.0))")),
+        "player_brick_size"
+    );
+    assert_eq!(
+        invalid_field(GameRules::from_ron("(mode: TimeLimit(seconds: 0))")),
+        "mode"
+    );
+}
+
+#[test]
+fn malformed_files_are_rejected_with_location() {
+    // 拼错的字段不会被忽略
+    let message = parse_error(GameRules::from_ron("(target_scroe: 10)"));
+    assert!(message.contains("target_scroe"), "{}", message);
+
+    let message = parse_error(GameRules::from_toml("target_score = \"ten\""));
+    assert!(message.contains("target_score"), "{}", message);
+    assert!(message.contains("line 1"), "{}", message);
+
+    assert!(matches!(
+        GameRules::load(Path::new("rules.json")),
+        Err(RulesError::Io(..))
+    ));
+    let path =
+        std::env::temp_dir().join(format!("test_bevy_game_rules_{}.json", std::process::id()));
+    std::fs::write(&path, "{}").unwrap();
+    let result = GameRules::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(result, Err(RulesError::UnknownFormat(_))));
+}
+
+#[derive(Default)]
+struct RulesChangedCount(usize);
+
+fn rules_changed_count_system(
+    mut count: ResMut<RulesChangedCount>,
+    mut events: EventReader<GameRulesChangedEvent>,
+) {
+    count.0 += events.iter().count();
+}
+
+// 修改文件后等待检查并应用, 修改时间相同时长度也会不同
+fn rewrite(app: &mut App, path: &Path, text: &str) {
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(path, text).unwrap();
+    run(app, 1);
+    run(app, 1);
+}
+
+fn coin_sizes(app: &mut App) -> Vec<Vec2> {
+    app.world
+        .query_filtered::<&Collider, With<Coin>>()
+        .iter(&app.world)
+        .map(|collider| collider.size)
+        .collect()
+}
+
+fn coin_serials(app: &mut App) -> Vec<u64> {
+    let mut serials: Vec<u64> = app
+        .world
+        .query::<&Coin>()
+        .iter(&app.world)
+        .map(|coin| coin.serial)
+        .collect();
+    serials.sort_unstable();
+    serials
+}
+
+#[test]
+fn rules_file_changes_apply_to_the_running_match() {
+    let path =
+        std::env::temp_dir().join(format!("test_bevy_game_reload_{}.ron", std::process::id()));
+    std::fs::write(&path, "(max_coin_num: 3)").unwrap();
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(GameRules::load(&path).unwrap())
+        .insert_resource(RulesReloadSettings {
+            path: Some(path.clone()),
+            poll_seconds: 0.0,
+        })
+        .init_resource::<RulesChangedCount>()
+        .add_plugin(RulesHotReloadPlugin)
+        .add_system(rules_changed_count_system.system());
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+
+    let mut roster = app.world.remove_resource::<PlayerRoster>().unwrap();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let rules = app.world.get_resource::<GameRules>().unwrap();
+    let player = roster.join(&mut commands, rules, None);
+    queue.apply(&mut app.world);
+    app.world.insert_resource(roster);
+    app.world.get_mut::<Ready>(player).unwrap().0 = true;
+    run(&mut app, 1);
+    run(&mut app, 1);
+    assert_eq!(
+        app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current(),
+        &MatchState::Playing
+    );
+    assert_eq!(coin_sizes(&mut app).len(), 3);
+
+    // 补充金币, 已有的金币和玩家换成新尺寸
+    rewrite(
+        &mut app,
+        &path,
+        "(max_coin_num: 5, coin_brick_size: (20.0, 30.0), player_brick_size: (40.0, 40.0))",
+    );
+    let sizes = coin_sizes(&mut app);
+    assert_eq!(sizes, vec![Vec2::new(20.0, 30.0); 5]);
+    assert_eq!(
+        app.world.get::<Collider>(player).unwrap().size,
+        Vec2::new(40.0, 40.0)
+    );
+    assert_eq!(app.world.get_resource::<RulesChangedCount>().unwrap().0, 1);
+
+    // 无效的文件保留当前规则
+    rewrite(
+        &mut app,
+        &path,
+        "(max_coin_num: 2, min_coin_score_value: 9)",
+    );
+    assert_eq!(
+        app.world.get_resource::<GameRules>().unwrap().max_coin_num,
+        5
+    );
+    assert_eq!(coin_sizes(&mut app).len(), 5);
+    assert_eq!(app.world.get_resource::<RulesChangedCount>().unwrap().0, 1);
+
+    // 移除最后生成的金币, 没写的字段恢复默认值. 在下一个 tick 内才生效
+    let mut serials = coin_serials(&mut app);
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(&path, "(max_coin_num: 2)").unwrap();
+    app.update();
+    assert_eq!(coin_serials(&mut app).len(), 5);
+    run(&mut app, 1);
+    std::fs::remove_file(&path).unwrap();
+    let sizes = coin_sizes(&mut app);
+    assert_eq!(sizes, vec![GameRules::default().coin_brick_size; 2]);
+    serials.truncate(2);
+    assert_eq!(coin_serials(&mut app), serials);
+    assert_eq!(app.world.get_resource::<RulesChangedCount>().unwrap().0, 2);
+}
+
+fn match_state(app: &App) -> MatchState {
+    app.world
+        .get_resource::<State<MatchState>>()
+        .unwrap()
+        .current()
+        .clone()
+}
+
+#[test]
+fn reloaded_delay_changes_the_running_countdown() {
+    let mut builder = game_app_builder();
+    builder.insert_resource(GameRules {
+        delay_seconds: 10.0,
+        ..Default::default()
+    });
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+
+    let mut roster = app.world.remove_resource::<PlayerRoster>().unwrap();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let rules = app.world.get_resource::<GameRules>().unwrap().clone();
+    let player = roster.join(&mut commands, &rules, None);
+    queue.apply(&mut app.world);
+    app.world.insert_resource(roster);
+    app.world.get_mut::<Ready>(player).unwrap().0 = true;
+    run(&mut app, 60);
+    assert_eq!(match_state(&app), MatchState::WaitingForBegin);
+
+    // 已经倒计时 1 秒, 改成 1.5 秒后再过半秒开始
+    app.world.insert_resource(GameRules {
+        delay_seconds: 1.5,
+        ..rules
+    });
+    run(&mut app, 25);
+    assert_eq!(match_state(&app), MatchState::WaitingForBegin);
+    run(&mut app, 10);
+    assert_eq!(match_state(&app), MatchState::Playing);
+}
@@ -0,0 +1,107 @@
+use bevy::app::Events;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::{ElementState, InputPlugin};
+use bevy::prelude::*;
+use test_bevy_game::plugins::game::GameRules;
+use test_bevy_game::plugins::player::{spawn_player, PlayerSpawn};
+use test_bevy_game::plugins::spectator::{
+    SpectatorCamera, SpectatorCameraPlugin, SpectatorView, SPECTATOR_NEXT_VIEW_KEY,
+};
+use test_bevy_game::plugins::ui::GameCamera;
+
+fn spectator_app() -> App {
+    let mut builder = App::build();
+    builder
+        .add_plugins(MinimalPlugins)
+        .add_plugin(InputPlugin)
+        .add_plugin(SpectatorCameraPlugin);
+    let mut app = builder.app;
+    app.world
+        .spawn()
+        .insert_bundle(OrthographicCameraBundle::new_2d())
+        .insert(GameCamera);
+    app
+}
+
+fn spawn_at(app: &mut App, x: f32, y: f32) -> Entity {
+    let rules = GameRules::default();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let player = spawn_player(
+        &mut commands,
+        &rules,
+        PlayerSpawn {
+            name: format!("player at {}", x),
+            team_id: 1,
+            color: Color::WHITE,
+            position: Vec3::new(x, y, 0.0),
+        },
+    );
+    queue.apply(&mut app.world);
+    player
+}
+
+// 按下并松开切换视角的按键
+fn next_view(app: &mut App) {
+    for state in [ElementState::Pressed, ElementState::Released].iter() {
+        app.world
+            .get_resource_mut::<Events<KeyboardInput>>()
+            .unwrap()
+            .send(KeyboardInput {
+                scan_code: 0,
+                key_code: Some(SPECTATOR_NEXT_VIEW_KEY),
+                state: *state,
+            });
+        app.update();
+    }
+}
+
+fn camera(app: &mut App) -> (Vec2, f32) {
+    let mut query = app
+        .world
+        .query_filtered::<(&Transform, &OrthographicProjection), With<GameCamera>>();
+    let (transform, projection) = query.iter(&app.world).next().unwrap();
+    (transform.translation.truncate(), projection.scale)
+}
+
+fn view(app: &App) -> SpectatorView {
+    app.world.get_resource::<SpectatorCamera>().unwrap().view
+}
+
+#[test]
+fn spectator_cycles_between_players_and_arena() {
+    let mut app = spectator_app();
+    let first = spawn_at(&mut app, -100.0, -215.0);
+    let second = spawn_at(&mut app, 150.0, 40.0);
+    app.update();
+    assert_eq!(view(&app), SpectatorView::Arena);
+    assert_eq!(camera(&mut app), (Vec2::ZERO, 1.0));
+
+    next_view(&mut app);
+    assert_eq!(view(&app), SpectatorView::Follow(first));
+    let (position, scale) = camera(&mut app);
+    assert_eq!(position, Vec2::new(-100.0, -215.0));
+    assert!(scale < 1.0);
+
+    next_view(&mut app);
+    assert_eq!(view(&app), SpectatorView::Follow(second));
+    assert_eq!(camera(&mut app).0, Vec2::new(150.0, 40.0));
+
+    // 最后一个玩家之后回到全场
+    next_view(&mut app);
+    assert_eq!(view(&app), SpectatorView::Arena);
+    assert_eq!(camera(&mut app), (Vec2::ZERO, 1.0));
+}
+
+#[test]
+fn spectator_returns_to_arena_when_followed_player_leaves() {
+    let mut app = spectator_app();
+    let player = spawn_at(&mut app, 60.0, 0.0);
+    next_view(&mut app);
+    assert_eq!(view(&app), SpectatorView::Follow(player));
+
+    app.world.despawn(player);
+    app.update();
+    assert_eq!(view(&app), SpectatorView::Arena);
+    assert_eq!(camera(&mut app), (Vec2::ZERO, 1.0));
+}
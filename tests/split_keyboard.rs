@@ -0,0 +1,92 @@
+use bevy::input::ElementState;
+use bevy::prelude::*;
+use test_bevy_game::plugins::game::SimulationClock;
+use test_bevy_game::plugins::input_ext::{
+    PlayerInputSettings, PlayerOperate, SeatInputs, ARROW_INPUT_MAP, WASD_INPUT_MAP,
+};
+use test_bevy_game::plugins::player::{LocalPlayer, LocalSeat, SpawnLocalPlayer, Team};
+
+mod common;
+use common::{game_app_builder, key};
+
+fn split_keyboard_app() -> App {
+    let mut builder = game_app_builder();
+    builder
+        .insert_resource(SpawnLocalPlayer(true))
+        .insert_resource(PlayerInputSettings::from_seats(&[
+            &WASD_INPUT_MAP,
+            &ARROW_INPUT_MAP,
+        ]));
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    app.update();
+    app
+}
+
+// 模拟 ticks 个 tick
+fn simulate(app: &mut App, ticks: u32) {
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .fast_forward(ticks);
+    app.update();
+}
+
+// 按座位排序的本地玩家: (座位, 队伍, 位置)
+fn local_players(app: &mut App) -> Vec<(usize, usize, Vec3)> {
+    let mut players: Vec<_> = app
+        .world
+        .query_filtered::<(&LocalSeat, &Team, &Transform), With<LocalPlayer>>()
+        .iter(&app.world)
+        .map(|(seat, team, transform)| (seat.0, team.id, transform.translation))
+        .collect();
+    players.sort_by_key(|(seat, _, _)| *seat);
+    players
+}
+
+#[test]
+fn each_seat_spawns_a_local_player_on_its_own_team() {
+    let mut app = split_keyboard_app();
+    let players = local_players(&mut app);
+    assert_eq!(players.len(), 2);
+    assert_eq!(players[0].0, 0);
+    assert_eq!(players[1].0, 1);
+    assert_ne!(players[0].1, players[1].1);
+}
+
+#[test]
+fn wasd_and_arrow_keys_move_different_players() {
+    let mut app = split_keyboard_app();
+    let start = local_players(&mut app);
+
+    key(&mut app, KeyCode::D, ElementState::Pressed);
+    key(&mut app, KeyCode::Up, ElementState::Pressed);
+    simulate(&mut app, 10);
+    {
+        let seat_inputs = app.world.get_resource::<SeatInputs>().unwrap();
+        assert!(seat_inputs
+            .get(1)
+            .unwrap()
+            .pressed(PlayerOperate::MoveFrond));
+        assert!(seat_inputs.get(0).is_none());
+        let input = app.world.get_resource::<Input<PlayerOperate>>().unwrap();
+        assert!(input.pressed(PlayerOperate::MoveRight));
+        assert!(!input.pressed(PlayerOperate::MoveFrond));
+    }
+
+    let moved = local_players(&mut app);
+    // 座位 0 只向右, 座位 1 只向上
+    assert!(moved[0].2.x > start[0].2.x);
+    assert_eq!(moved[0].2.y, start[0].2.y);
+    assert_eq!(moved[1].2.x, start[1].2.x);
+    assert!(moved[1].2.y > start[1].2.y);
+
+    key(&mut app, KeyCode::D, ElementState::Released);
+    simulate(&mut app, 10);
+    let stopped = local_players(&mut app);
+    assert_eq!(stopped[0].2, moved[0].2);
+    assert!(stopped[1].2.y > moved[1].2.y);
+}
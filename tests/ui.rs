@@ -0,0 +1,139 @@
+use bevy::app::Events;
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use test_bevy_game::plugins::elapsed_time::ElapsedTimePlugin;
+use test_bevy_game::plugins::game::{
+    GameRules, GameRulesChangedEvent, GameState, MatchState, SimulationClock,
+};
+use test_bevy_game::plugins::player::{PlayerRoster, Ready, Score, TeamScoreChangedEvent};
+use test_bevy_game::plugins::ui::{ScoreUI, UiPlugin};
+
+mod common;
+use common::{game_app_builder, run};
+
+// 结果界面的文字, 第一段是胜负, 第二段是排名和玩家数据
+fn game_over_font_sizes(app: &mut App) -> Vec<f32> {
+    app.world
+        .query::<&Text>()
+        .iter(&app.world)
+        .find(|text| text.sections.len() == 2)
+        .map(|text| {
+            text.sections
+                .iter()
+                .map(|section| section.style.font_size)
+                .collect()
+        })
+        .unwrap()
+}
+
+fn ui_app() -> App {
+    let mut builder = game_app_builder();
+    builder
+        .add_plugin(AssetPlugin)
+        .add_plugin(ElapsedTimePlugin)
+        .add_plugin(UiPlugin);
+    let mut app = builder.app;
+    app.world
+        .get_resource_mut::<SimulationClock>()
+        .unwrap()
+        .set_speed(0.0);
+    app
+}
+
+fn join(app: &mut App) -> Entity {
+    let mut roster = app.world.remove_resource::<PlayerRoster>().unwrap();
+    let mut queue = Default::default();
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let rules = app.world.get_resource::<GameRules>().unwrap().clone();
+    let player = roster.join(&mut commands, &rules, None);
+    queue.apply(&mut app.world);
+    app.world.insert_resource(roster);
+    player
+}
+
+fn score_lines(app: &mut App) -> Vec<String> {
+    app.world
+        .query_filtered::<&Text, With<ScoreUI>>()
+        .iter(&app.world)
+        .next()
+        .unwrap()
+        .sections
+        .iter()
+        .map(|section| section.value.trim().to_string())
+        .collect()
+}
+
+#[test]
+fn score_hud_lists_every_team_scoring_in_the_same_frame() {
+    let mut app = ui_app();
+    let first = join(&mut app);
+    let second = join(&mut app);
+    run(&mut app, 1);
+
+    // 两个队伍同一帧得分, 事件顺序和队伍顺序相反
+    app.world.get_mut::<Score>(first).unwrap().val = 5;
+    app.world.get_mut::<Score>(second).unwrap().val = 3;
+    let mut events = app
+        .world
+        .get_resource_mut::<Events<TeamScoreChangedEvent>>()
+        .unwrap();
+    events.send(TeamScoreChangedEvent {
+        team_id: 2,
+        team_score: 3,
+    });
+    events.send(TeamScoreChangedEvent {
+        team_id: 1,
+        team_score: 5,
+    });
+    app.update();
+    assert_eq!(score_lines(&mut app), vec!["|team 1: 5", "|team 2: 3"]);
+}
+
+#[test]
+fn rules_reload_keeps_result_details_smaller() {
+    let mut app = ui_app();
+    let rules = app.world.get_resource::<GameRules>().unwrap().clone();
+    let player = join(&mut app);
+    app.world.get_mut::<Ready>(player).unwrap().0 = true;
+    run(&mut app, 1);
+    run(&mut app, 1);
+
+    // 直接达到目标分数结束比赛
+    app.world.get_mut::<Score>(player).unwrap().val = rules.target_score;
+    app.world
+        .get_resource_mut::<Events<TeamScoreChangedEvent>>()
+        .unwrap()
+        .send(TeamScoreChangedEvent {
+            team_id: 1,
+            team_score: rules.target_score,
+        });
+    run(&mut app, 1);
+    assert!(app
+        .world
+        .get_resource::<GameState>()
+        .unwrap()
+        .result()
+        .is_some());
+    assert_eq!(
+        app.world
+            .get_resource::<State<MatchState>>()
+            .unwrap()
+            .current(),
+        &MatchState::GameOver
+    );
+    assert_eq!(
+        game_over_font_sizes(&mut app),
+        vec![rules.font_size, rules.font_size * 0.5]
+    );
+
+    app.world.insert_resource(GameRules {
+        font_size: 30.0,
+        ..rules.clone()
+    });
+    app.world
+        .get_resource_mut::<Events<GameRulesChangedEvent>>()
+        .unwrap()
+        .send(GameRulesChangedEvent { previous: rules });
+    app.update();
+    assert_eq!(game_over_font_sizes(&mut app), vec![30.0, 15.0]);
+}